@@ -30,7 +30,7 @@ stack_top:
 align 1 << 12 ; page aligned
 global starting_page_tables
 starting_page_tables:
-resb 3 << 12 ; 3 pages
+resb 4 << 12 ; 4 pages
 
 [section .bootstrap_text alloc exec nowrite progbits align=16]
 global _start:function (_start.end - _start)
@@ -160,17 +160,19 @@ set_up_paging:
     ; 0x0000: level 4 PML4T (page map level-4 table)
     ; 0x1000: level 3 PDPT (page directory pointer table)
     ; 0x2000: level 2 PDT (page directory table)
+    ; 0x3000: level 5 table, only used with 5-level paging
 
+    ; 0x3000 will point to 0x0000 at 0 and 511
     ; 0x0000 will point to 0x1000 at 0 and 511
     ; 0x1000 will point to 0x2000 at 0 and 511
     ; 0x2000 will identity map the bottom 1gb
 
-    ; first, zero all 3 pages
+    ; first, zero all 4 pages
     mov edi, starting_page_tables
     xor eax, eax
-    mov ecx, 3 * 1024
-    ; 3 * 1024 times, set 4 bytes at [edi] to 0 and add 4 to edi
-    ; so zero 3 * 1024 * 4 bytes or 3 pages
+    mov ecx, 4 * 1024
+    ; 4 * 1024 times, set 4 bytes at [edi] to 0 and add 4 to edi
+    ; so zero 4 * 1024 * 4 bytes or 4 pages
     rep stosd
 
     .ENTRY_PRESENT  equ 1 << 0
@@ -204,13 +206,54 @@ set_up_paging:
     add eax, 0x200000 ; point next entry to next 2MB
     add edi, 8        ; move to location of next entry
     loop .id_map_loop
-    
+
+    call is_la57_supported
+    jz .four_levels
+
+    ; 0x3000 (L5) points to:
+    ; 0x0000 at 0b000000000 and 0b111111111
+    ; so the bottom and top of the address space look the same as with 4 levels
+    mov eax, starting_page_tables
+    or eax, .ENTRY_PRESENT | .ENTRY_RW
+    mov dword [starting_page_tables + 0x3000], eax
+    mov dword [starting_page_tables + 0x3000 + 511 * 8], eax
+
+    ; LA57 can only be changed with paging off
+    mov eax, cr4
+    or eax, 1 << 12 ; set LA57 bit
+    mov cr4, eax
+
+    ; use new page table
+    mov eax, starting_page_tables + 0x3000
+    mov cr3, eax
+    ret
+
+.four_levels:
     ; use new page table
     mov eax, starting_page_tables
     mov cr3, eax
 
     ret
 
+; zero flag set if not
+is_la57_supported:
+    ; check whether cpuid leaf 7 is supported
+    xor eax, eax
+    cpuid
+    cmp eax, 7
+    jb .no
+
+    ; check whether 5-level paging is supported
+    mov eax, 7
+    xor ecx, ecx
+    cpuid
+    test ecx, 1 << 16
+    ret
+
+.no:
+    xor eax, eax
+    ret
+
 prepare_for_long_mode:
     mov eax, cr4
     or eax, 1 << 5 ; set PAE bit
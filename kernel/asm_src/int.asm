@@ -61,4 +61,18 @@ asm_handle_test:
     extern handle_test_interrupt
     call handle_test_interrupt
     POP_REGS
-    iretq
\ No newline at end of file
+    iretq
+global asm_handle_page_fault
+asm_handle_page_fault:
+    PUSH_REGS
+    cld
+    mov rdi, [rsp + 9 * 8] ; error code, under the saved registers
+    lea rsi, [rsp + 10 * 8] ; the interrupt frame, under the error code
+    ; the error code leaves the stack 8 bytes off from 16 byte aligned
+    sub rsp, 8
+    extern handle_page_fault
+    call handle_page_fault
+    add rsp, 8
+    POP_REGS
+    add rsp, 8 ; pop the error code
+    iretq
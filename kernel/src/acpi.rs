@@ -1,35 +1,48 @@
 use core::{
     mem::{size_of, transmute},
+    ops::Range,
     ptr::NonNull,
 };
 
-use acpi::{rsdp::Rsdp, PhysicalMapping};
+use acpi::{
+    rsdp::Rsdp,
+    sdt::{SdtHeader, Signature},
+    AcpiTable, PhysicalMapping,
+};
 
-use crate::types::{HasPhysAddr, HasVirtAddr};
+use crate::mm::ioremap::{ioremap, iounmap};
+use crate::types::page::PAGE_SIZE;
+use crate::types::page_table::CacheMode;
+use crate::types::{HasPhysAddr, HasVirtAddr, PhysAddr};
 
-// use the identity map for the first 2MB
+// the tables can be anywhere in memory, so they get mapped with ioremap
+// they're in normal RAM, so they can be cached
 #[derive(Clone)]
-pub struct IdMapper;
+pub struct IoremapMapper;
 
-impl acpi::AcpiHandler for IdMapper {
+impl acpi::AcpiHandler for IoremapMapper {
     unsafe fn map_physical_region<T>(
         &self,
         physical_address: usize,
         size: usize,
     ) -> PhysicalMapping<Self, T> {
-        // make sure the end of the mapping fits in the id-map
-        (physical_address + size - 1).to_virt();
+        let region = ioremap(physical_address.phys_addr(), size, CacheMode::WriteBack)
+            .expect("no room to map an ACPI table");
+        // the acpi crate holds onto it and gives it back in unmap_physical_region
+        let virt = region.leak();
+        let offset = physical_address % PAGE_SIZE;
         PhysicalMapping::new(
             physical_address,
-            NonNull::new(physical_address.to_virt().ptr()).unwrap(),
+            NonNull::new(virt.ptr()).unwrap(),
             size,
-            0,
+            (offset + size).next_multiple_of(PAGE_SIZE),
             self.clone(),
         )
     }
 
-    fn unmap_physical_region<T>(_region: &PhysicalMapping<Self, T>) {
-        // don't unmap anything
+    fn unmap_physical_region<T>(region: &PhysicalMapping<Self, T>) {
+        let virt = region.virtual_start().as_ptr().virt_addr();
+        unsafe { iounmap(virt, region.region_length()) };
     }
 }
 
@@ -39,7 +52,125 @@ pub fn rsdp_from_buf(buf: &[u8]) -> Rsdp {
     unsafe { transmute(rsdp) }
 }
 
-pub unsafe fn acpi_tables_from_rsdp(rsdp: Rsdp) -> acpi::AcpiResult<acpi::AcpiTables<IdMapper>> {
+pub unsafe fn acpi_tables_from_rsdp(
+    rsdp: Rsdp,
+) -> acpi::AcpiResult<acpi::AcpiTables<IoremapMapper>> {
     rsdp.validate()?;
-    acpi::AcpiTables::from_validated_rsdp(IdMapper, rsdp)
+    acpi::AcpiTables::from_validated_rsdp(IoremapMapper, rsdp)
+}
+
+// the acpi crate doesn't parse the NUMA tables, so that's done here
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+// the bytes of a table after its fixed-size part
+unsafe fn table_body<T: AcpiTable>(table: &T) -> &[u8] {
+    let len = { table.header().length } as usize - size_of::<T>();
+    let start = (table as *const T).add(1) as *const u8;
+    core::slice::from_raw_parts(start, len)
+}
+
+#[repr(C, packed)]
+pub struct Srat {
+    header: SdtHeader,
+    _reserved: [u8; 12],
+}
+
+unsafe impl AcpiTable for Srat {
+    const SIGNATURE: Signature = Signature::SRAT;
+
+    fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+}
+
+#[derive(Debug)]
+pub enum SratEntry {
+    Cpu { apic_id: u32, domain: u32 },
+    Memory { range: Range<PhysAddr>, domain: u32 },
+    // disabled entries and kinds we don't care about
+    Other,
+}
+
+impl SratEntry {
+    fn parse(bytes: &[u8]) -> Self {
+        const ENABLED_FLAG: u32 = 1 << 0;
+        match (bytes[0], bytes.len()) {
+            // processor local APIC affinity
+            (0, 16..) if read_u32(bytes, 4) & ENABLED_FLAG != 0 => {
+                let domain_high = read_u32(bytes, 8) >> 8;
+                SratEntry::Cpu {
+                    apic_id: bytes[3] as u32,
+                    domain: bytes[2] as u32 | domain_high << 8,
+                }
+            }
+            // memory affinity
+            (1, 40..) if read_u32(bytes, 28) & ENABLED_FLAG != 0 => {
+                let start = read_u64(bytes, 8) as usize;
+                let len = read_u64(bytes, 16) as usize;
+                SratEntry::Memory {
+                    range: start.phys_addr()..(start + len).phys_addr(),
+                    domain: read_u32(bytes, 2),
+                }
+            }
+            // processor local x2APIC affinity
+            (2, 24..) if read_u32(bytes, 12) & ENABLED_FLAG != 0 => SratEntry::Cpu {
+                apic_id: read_u32(bytes, 8),
+                domain: read_u32(bytes, 4),
+            },
+            _ => SratEntry::Other,
+        }
+    }
+}
+
+impl Srat {
+    pub fn entries(&self) -> impl Iterator<Item = SratEntry> + '_ {
+        let mut rest = unsafe { table_body(self) };
+        core::iter::from_fn(move || {
+            // every entry starts with its kind and length
+            let len = *rest.get(1)? as usize;
+            if len < 2 || len > rest.len() {
+                return None;
+            }
+            let (entry, after) = rest.split_at(len);
+            rest = after;
+            Some(SratEntry::parse(entry))
+        })
+    }
+}
+
+#[repr(C, packed)]
+pub struct Slit {
+    header: SdtHeader,
+    locality_count: u64,
+}
+
+unsafe impl AcpiTable for Slit {
+    const SIGNATURE: Signature = Signature::SLIT;
+
+    fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+}
+
+impl Slit {
+    pub fn locality_count(&self) -> usize {
+        self.locality_count as usize
+    }
+
+    // relative distance, 10 means local
+    pub fn distance(&self, from: u32, to: u32) -> Option<u8> {
+        let count = self.locality_count();
+        let (from, to) = (from as usize, to as usize);
+        if from >= count || to >= count {
+            return None;
+        }
+        unsafe { table_body(self) }.get(from * count + to).copied()
+    }
 }
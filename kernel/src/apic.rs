@@ -4,10 +4,10 @@ use raw_cpuid::CpuId;
 
 use crate::{
     asm,
-    types::{HasPhysAddr, HasVirtAddr, PageAddr},
+    mm::ioremap::{ioremap, MmioRegion},
+    types::{page::PAGE_SIZE, page_table::CacheMode, HasPhysAddr, PhysAddr},
 };
 
-const APIC_PAGE_ADDR: usize = 0xFEE00000;
 const APIC_BASE_MSR: u32 = 0x1B;
 
 fn ensure_x2apic() {
@@ -29,12 +29,25 @@ fn check_apic_msr_enabled() {
     assert!(val & ENABLED_FLAG != 0, "APIC is disabled in MSR");
 }
 
-struct APICPage(PageAddr);
+// the APIC ID this CPU started with, which is what the ACPI tables refer to
+pub fn local_apic_id() -> u32 {
+    CpuId::new()
+        .get_feature_info()
+        .map_or(0, |info| info.initial_local_apic_id() as u32)
+}
+
+// usually 0xFEE00000, which is way past the id-map
+fn apic_page_addr() -> PhysAddr {
+    const ADDR_BITS: u64 = 0x000F_FFFF_FFFF_F000;
+    ((unsafe { asm::read_msr(APIC_BASE_MSR) } & ADDR_BITS) as usize).phys_addr()
+}
+
+struct APICPage(MmioRegion);
 
 impl APICPage {
     fn get_reg_ptr(&self, offset: usize) -> *const u8 {
         assert!(offset < 0x40);
-        unsafe { self.0.ptr::<u8>().add(offset * 16) }
+        self.0.ptr::<u8>(offset * 16)
     }
 
     fn get_reg32(&self, offset: usize) -> &AtomicU32 {
@@ -50,6 +63,8 @@ pub fn init() {
     ensure_x2apic();
     check_apic_msr_enabled();
 
-    let apic = APICPage(APIC_PAGE_ADDR.to_virt().as_aligned());
+    let apic = APICPage(
+        ioremap(apic_page_addr(), PAGE_SIZE, CacheMode::Uncached).expect("can't map the APIC"),
+    );
     println!("svr val: {:16x}", apic.svr().load(Ordering::Relaxed));
 }
@@ -43,3 +43,59 @@ pub unsafe fn write_msr(msr: u32, val: u64) {
         in("eax") lo,
     );
 }
+
+#[inline(always)]
+pub unsafe fn invlpg(addr: usize) {
+    asm!("invlpg [{}]", in(reg) addr);
+}
+
+// kind 0 is one address in one pcid, 1 is all of one pcid,
+// 2 is everything including global pages, 3 is everything except global pages
+#[inline(always)]
+pub unsafe fn invpcid(kind: usize, pcid: u16, addr: usize) {
+    let descriptor: [u64; 2] = [pcid as u64, addr as u64];
+    asm!("invpcid {}, [{}]", in(reg) kind, in(reg) &descriptor);
+}
+
+#[inline(always)]
+pub unsafe fn read_cr0() -> usize {
+    let cr0: usize;
+    asm!("mov {}, cr0", out(reg) cr0);
+    cr0
+}
+
+#[inline(always)]
+pub unsafe fn write_cr0(val: usize) {
+    asm!("mov cr0, {}", in(reg) val);
+}
+
+#[inline(always)]
+pub unsafe fn read_cr2() -> usize {
+    let cr2: usize;
+    asm!("mov {}, cr2", out(reg) cr2);
+    cr2
+}
+
+#[inline(always)]
+pub unsafe fn read_cr3() -> usize {
+    let cr3: usize;
+    asm!("mov {}, cr3", out(reg) cr3);
+    cr3
+}
+
+#[inline(always)]
+pub unsafe fn write_cr3(val: usize) {
+    asm!("mov cr3, {}", in(reg) val);
+}
+
+#[inline(always)]
+pub unsafe fn read_cr4() -> usize {
+    let cr4: usize;
+    asm!("mov {}, cr4", out(reg) cr4);
+    cr4
+}
+
+#[inline(always)]
+pub unsafe fn write_cr4(val: usize) {
+    asm!("mov cr4, {}", in(reg) val);
+}
@@ -0,0 +1,73 @@
+use core::arch::asm;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::mm::numa::{self, NodeId};
+
+// per-CPU data, found through the GS base so finding it doesn't take a CPUID every time
+
+// CPUs are indexed in the order they come up, and by APIC ID in the ACPI tables,
+// which is 8 bits without x2APIC
+pub const MAX_CPUS: usize = 256;
+
+const GS_BASE_MSR: u32 = 0xC000_0101;
+
+#[repr(C)]
+pub struct PerCpu {
+    // has to be first, it's how the struct is found from GS
+    this: *mut PerCpu,
+    index: usize,
+    apic_id: u32,
+    node: NodeId,
+}
+
+static mut CPUS: [PerCpu; MAX_CPUS] = [const {
+    PerCpu {
+        this: core::ptr::null_mut(),
+        index: 0,
+        apic_id: 0,
+        node: NodeId(0),
+    }
+}; MAX_CPUS];
+
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// has to run once on each CPU before anything asks which CPU it's on
+pub fn init() {
+    let index = CPU_COUNT.fetch_add(1, Ordering::Relaxed);
+    assert!(index < MAX_CPUS, "more than {} CPUs", MAX_CPUS);
+    let apic_id = crate::apic::local_apic_id();
+    unsafe {
+        let cpu = addr_of_mut!(CPUS[index]);
+        *cpu = PerCpu {
+            this: cpu,
+            index,
+            apic_id,
+            node: numa::topology().cpu_node(apic_id),
+        };
+        crate::asm::write_msr(GS_BASE_MSR, cpu as u64);
+    }
+}
+
+// only this CPU touches its own PerCpu
+fn current() -> *mut PerCpu {
+    let cpu: *mut PerCpu;
+    unsafe { asm!("mov {}, gs:[0]", out(reg) cpu, options(nostack, preserves_flags, readonly)) };
+    cpu
+}
+
+pub fn index() -> usize {
+    unsafe { (*current()).index }
+}
+
+pub fn node() -> NodeId {
+    unsafe { (*current()).node }
+}
+
+// for CPUs that were already up when the NUMA topology was found
+pub fn refresh_node() {
+    unsafe {
+        let cpu = current();
+        (*cpu).node = numa::topology().cpu_node((*cpu).apic_id);
+    }
+}
@@ -1,7 +1,6 @@
-pub mod intrusive_list;
 pub mod list;
-mod slot;
-mod storage;
 
-pub use slot::{IsSlotOf, ObjectSlot};
-pub use storage::{AtomicStore, Store, Stores};
+pub use sparkle_lib::data_structures::{
+    atomic_stack, intrusive_dlist, intrusive_list, intrusive_tree, mpsc_queue, radix_tree,
+};
+pub use sparkle_lib::data_structures::{AtomicStore, IsSlotOf, ObjectSlot, Store, Stores};
@@ -1,20 +1,19 @@
 use core::alloc::{GlobalAlloc, Layout};
 use core::cell::Cell;
 use core::mem::MaybeUninit;
-use core::ptr::{addr_of, slice_from_raw_parts_mut};
+use core::ptr::{null_mut, slice_from_raw_parts_mut};
 
 use alloc::boxed::Box;
 use alloc::vec;
 
 use crate::mm::bump_alloc::BumpAllocator;
-use crate::mm::page_table::PTL2;
 use crate::mm::{self, frame_alloc};
 use crate::multiboot::{self, BootloaderName, Rsdp};
 use crate::sync::SpinLock;
 use crate::task::{switch_to_task, TCB};
 use crate::types::page::PAGE_SIZE;
-use crate::types::page_table::{Entry, Flags, LargePageFlags, PTE_INDEX_SIZE};
-use crate::types::{zeroable, FrameAddr, HasPhysAddr, HasVirtAddr};
+use crate::types::page_table::PTE_INDEX_SIZE;
+use crate::types::{HasPhysAddr, HasVirtAddr, ID_MAP_SIZE};
 
 fn print_mmap_entry(entry: multiboot::MMapEntry) {
     // you can't take a reference to a field of a packed struct, so
@@ -40,6 +39,8 @@ fn print_all_mmap_entries(entries: multiboot::MMapEntryIterator) {
 #[no_mangle]
 extern "sysv64" fn kernel_main(multiboot_info: i32, magic: u32) {
     crate::io::init_com1();
+    crate::types::detect_paging_mode();
+    mm::page_table::detect_huge_pages();
 
     if magic != 0x36D76289 {
         // check multiboot2 magic number
@@ -48,10 +49,16 @@ extern "sysv64" fn kernel_main(multiboot_info: i32, magic: u32) {
     }
 
     println!("in kernel now");
+    println!("{}-level paging", crate::types::page_table::paging_levels());
 
     let multiboot_info = (multiboot_info as usize).phys_addr().to_virt();
 
     crate::int::init();
+    crate::cpu::init();
+    mm::pat::init();
+    unsafe { mm::kernel_map::init() };
+    mm::kernel_map::check_text_is_read_only();
+    mm::tlb::init();
     crate::apic::init();
 
     println!("multiboot info at {}", multiboot_info);
@@ -86,25 +93,26 @@ extern "sysv64" fn kernel_main(multiboot_info: i32, magic: u32) {
         }
     }
 
-    unsafe {
-        frame_alloc::init(multiboot_info);
-    };
+    mm::numa::init(&acpi_tables);
 
-    let frame = frame_alloc::alloc_frame_with_order(frame_alloc::FrameOrder(9)).unwrap();
-    println!("order-9 frame: {}", frame);
-
-    let mut l3_entries = unsafe { mm::page_alloc::get_l3_entries() };
-    let mut l2_entries;
     unsafe {
-        static mut L2_TABLE: PTL2 = zeroable::zeroed();
-        let l2_table_frame: FrameAddr = addr_of!(L2_TABLE).to_phys().as_aligned();
-        l2_entries = l3_entries
-            .take_first()
-            .map_subtable(Entry::at_frame(l2_table_frame), &mut L2_TABLE);
+        frame_alloc::init(multiboot_info);
+        mm::page_alloc::init();
     };
-    let large_page_entry = Entry::at_frame(frame).set_flags(LargePageFlags::none());
-    let large_page_addr = unsafe { l2_entries.take_first().map_large_page(large_page_entry) };
-    println!("order-9 page: {}", large_page_addr);
+    mm::vmalloc::init();
+    mm::page_alloc::check_reuse_and_reclaim();
+    mm::kernel_map::check_split_and_merge();
+
+    // done with ACPI, so the memory it's in can be used
+    drop(madt);
+    drop(acpi_tables);
+    unsafe { frame_alloc::reclaim_acpi_memory(multiboot_info) };
+
+    // the heap starts out in 2MiB of the id-map, which is already mapped with large pages
+    let id_map = 0.phys_addr()..ID_MAP_SIZE.phys_addr();
+    let frame = frame_alloc::alloc_frame_in(id_map, frame_alloc::FrameOrder(9)).unwrap();
+    let large_page_addr = frame.to_virt();
+    println!("heap: {}", large_page_addr);
     let large_page: &'static mut [MaybeUninit<u8>] = unsafe {
         &mut *slice_from_raw_parts_mut(large_page_addr.ptr(), PAGE_SIZE << PTE_INDEX_SIZE)
     };
@@ -124,7 +132,10 @@ extern "sysv64" fn kernel_main(multiboot_info: i32, magic: u32) {
         unsafe { &mut *(slab.alloc_fast().unwrap() as *mut [u8; 1024] as *mut [u8]) };*/
 
     let alloc = BumpAllocator::new(large_page);
-    *GLOBAL_ALLOC.lock() = Some(alloc);
+    *GLOBAL_ALLOC.0.lock() = Some(alloc);
+    mm::vmalloc::check_demand_zero();
+    mm::kernel_map::check_split_and_merge_huge();
+    mm::page_table::check_snapshot_diff();
 
     let stack: &'static mut [u64] = Box::leak(vec![0; 1024].into_boxed_slice());
     let stack: &'static mut [u8] =
@@ -142,18 +153,32 @@ extern "sysv64" fn kernel_main(multiboot_info: i32, magic: u32) {
     println!("switched back")
 }
 
-unsafe impl GlobalAlloc for SpinLock<Option<BumpAllocator<'static>>> {
+// anything this big gets its own pages from vmalloc, so it can actually be freed
+fn is_large(layout: Layout) -> bool {
+    layout.size() >= PAGE_SIZE && layout.align() <= PAGE_SIZE
+}
+
+struct Heap(SpinLock<Option<BumpAllocator<'static>>>);
+
+unsafe impl GlobalAlloc for Heap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.lock().as_mut().unwrap().alloc_layout(layout) as *mut u8
+        // vmalloc uses the heap too, so this can't be holding the lock
+        if is_large(layout) {
+            return mm::vmalloc(layout.size()).map_or(null_mut(), |page| page.ptr());
+        }
+        self.0.lock().as_mut().unwrap().alloc_layout(layout) as *mut u8
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if is_large(layout) {
+            mm::vfree(ptr.virt_addr().as_aligned());
+        }
         // bump allocators can't deallocate
     }
 }
 
 #[global_allocator]
-static GLOBAL_ALLOC: SpinLock<Option<BumpAllocator<'static>>> = SpinLock::new(None);
+static GLOBAL_ALLOC: Heap = Heap(SpinLock::new(None));
 
 fn hello_world_task() {
     println!("hello from a thread");
@@ -1,10 +1,12 @@
 use core::{
     arch::asm,
     ptr::addr_of,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
 };
 
+use crate::asm::read_cr2;
 use crate::hang::hang;
+use crate::types::HasVirtAddr;
 
 pub struct IdtEntryBuilder {
     pub offset: usize,
@@ -98,6 +100,60 @@ fn handle_double_fault_interrupt(error: u64) {
     hang();
 }
 
+// what the cpu pushes for an interrupt, after the error code if there is one
+#[repr(C)]
+pub struct InterruptFrame {
+    pub rip: usize,
+    pub cs: usize,
+    pub rflags: usize,
+    pub rsp: usize,
+    pub ss: usize,
+}
+
+// where to continue if the next page fault is expected, 0 if it isn't
+static FAULT_FIXUP: AtomicUsize = AtomicUsize::new(0);
+static FAULTED: AtomicBool = AtomicBool::new(false);
+
+#[no_mangle]
+fn handle_page_fault(error: u64, frame: &mut InterruptFrame) {
+    let addr = unsafe { read_cr2() };
+    // demand-zero memory being touched for the first time
+    if unsafe { crate::mm::address_space::handle_page_fault(addr, error) } {
+        return;
+    }
+    let fixup = FAULT_FIXUP.swap(0, Ordering::Relaxed);
+    if fixup != 0 {
+        FAULTED.store(true, Ordering::Relaxed);
+        frame.rip = fixup;
+        return;
+    }
+    panic!(
+        "page fault accessing {} at {}, error code {:#x}",
+        addr.virt_addr(),
+        frame.rip.virt_addr(),
+        error
+    );
+}
+
+// writes a byte back to where it was read from, and returns whether the write page faulted
+// for checking page permissions
+pub unsafe fn write_faults(addr: *mut u8) -> bool {
+    FAULTED.store(false, Ordering::Relaxed);
+    asm!(
+        "lea {tmp}, [rip + 2f]",
+        "mov [{fixup}], {tmp}",
+        "mov {val}, byte ptr [{addr}]",
+        "mov byte ptr [{addr}], {val}",
+        "2:",
+        tmp = out(reg) _,
+        val = out(reg_byte) _,
+        fixup = in(reg) FAULT_FIXUP.as_ptr(),
+        addr = in(reg) addr,
+    );
+    FAULT_FIXUP.store(0, Ordering::Relaxed);
+    FAULTED.load(Ordering::Relaxed)
+}
+
 #[no_mangle]
 fn handle_test_interrupt() {
     println!("printing this from an interrupt");
@@ -128,6 +184,7 @@ pub fn init() {
         // these are linker variables; their addresses matter, but they have no values
         static generic_irq_table: [usize; 22];
         fn asm_handle_double_fault();
+        fn asm_handle_page_fault();
         fn asm_handle_test();
     }
 
@@ -141,6 +198,8 @@ pub fn init() {
         )
     };
 
+    unsafe { IDT[14].set(IdtEntryBuilder::new(asm_handle_page_fault as usize)) };
+
     unsafe {
         IDT[50].set(IdtEntryBuilder::new(asm_handle_test as usize).with_gate_type(GateType::Trap))
     };
@@ -8,6 +8,7 @@ extern crate alloc;
 
 use core::fmt::Write;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 #[macro_use]
 mod io;
@@ -15,6 +16,7 @@ mod io;
 mod acpi;
 mod apic;
 mod asm;
+mod cpu;
 mod data_structures;
 mod entry;
 mod hang;
@@ -45,6 +47,12 @@ unsafe fn panic(info: &PanicInfo) -> ! {
         );
     }
 
+    // don't try again if dumping the page tables is what panicked
+    static DUMPED_PAGE_TABLES: AtomicBool = AtomicBool::new(false);
+    if !DUMPED_PAGE_TABLES.swap(true, Ordering::Relaxed) {
+        _ = mm::page_table::dump_current(&mut io::SerialOut);
+    }
+
     _ = writeln!(io::SerialOut, "Now halting");
 
     hang::hang();
@@ -1,15 +1,27 @@
 pub mod address_space;
-pub mod bitmap_frame_alloc;
-pub mod bump_alloc;
+pub mod frame_alloc;
+pub mod frame_cache;
+#[cfg(feature = "frame-debug")]
+mod frame_debug;
+pub mod frame_meta;
+pub mod ioremap;
+pub mod kernel_map;
+pub mod numa;
 pub mod page_alloc;
 pub mod page_table;
+pub mod pat;
 pub mod slab_alloc;
+pub mod tlb;
+pub mod vmalloc;
 
-// i could switch this out for something easier to debug in the future
-pub use bitmap_frame_alloc as frame_alloc;
+pub use sparkle_lib::bump_alloc;
 
 pub use frame_alloc::{
-    alloc_frame, alloc_frame_with_order, free_frame, free_frame_with_order, FrameOrder,
+    alloc_frame, alloc_frame_in, alloc_frame_in_zone, alloc_frame_with_order, alloc_frames,
+    free_frame, free_frame_with_order, free_frames, get_frame, put_frame, put_frame_with_order,
+    FrameOrder, Zone,
 };
-pub use page_alloc::{alloc_l1_entry, alloc_page};
-pub use slab_alloc::{Segment, Slab};
+pub use frame_meta::{frame_meta, try_frame_meta, Frame, FrameFlags};
+pub use page_alloc::{alloc_l1_entry, alloc_page, free_l1_entry, free_page};
+pub use slab_alloc::{Segment, Slab, TreeSlab};
+pub use vmalloc::{vfree, vmalloc, vreserve};
@@ -1,51 +1,402 @@
+use core::ops::Range;
+use core::ptr::{addr_of, null_mut};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
 use crate::{
     data_structures::{
-        intrusive_list::{IntrusiveList, ListLink},
-        IsSlotOf, Store, Stores,
+        intrusive_tree::{IntrusiveTree, TreeLink, TreeLinks},
+        IsSlotOf, Stores,
+    },
+    mm::{
+        frame_alloc::{alloc_frame, alloc_frame_in, free_frame, FrameOrder},
+        kernel_map::{global_flag, no_exec_flag},
+        page_table::table_virt,
+        tlb::{self, Pcid},
+    },
+    sync::SpinLock,
+    types::{
+        page::{PAGE_SHIFT, PAGE_SIZE},
+        page_table::{
+            paging_levels, CacheMode, Entry, Flags, PageFlags, SubtableFlags, ENTRY_COUNT,
+            PRESENT_FLAG, PTE_INDEX_SIZE, PT_FRAME_BITS, USER_FLAG, WRITABLE_FLAG,
+        },
+        FrameAddr, HasPhysAddr, HasVirtAddr, PageAddr, ID_MAP_SIZE,
     },
-    types::PageAddr,
 };
 
-// right now, linked list of memory regions
-// later, rb-tree or something
+// memory regions, kept in a tree ordered by address
+// the tree tracks the free gaps between regions, so finding space for a new one is O(log n)
 
-struct Region {
+// page fault error code bits
+const FAULT_PRESENT: u64 = 1 << 0;
+const FAULT_WRITE: u64 = 1 << 1;
+const FAULT_USER: u64 = 1 << 2;
+const FAULT_INSTR: u64 = 1 << 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Backing {
+    // whoever made the region maps it
+    Fixed,
+    // nothing is mapped until it's touched
+    // reads get the shared zero page, and the first write gets a frame of its own
+    DemandZero { writable: bool, user: bool },
+}
+
+pub struct Region {
     start: PageAddr,
     size: usize,
-    next: Store<Option<RawRegionLink>, Option<RegionLink>>,
+    // space kept free after the region, so running off the end faults
+    guard: usize,
+    backing: Backing,
+    links: TreeLinks<RawRegionLink>,
+}
+
+impl Region {
+    // doesn't have a start until it's put in an address space
+    pub fn new(size: usize) -> Self {
+        Self {
+            start: 0.virt_addr().as_aligned(),
+            size,
+            guard: 0,
+            backing: Backing::Fixed,
+            links: TreeLinks::new(),
+        }
+    }
+
+    pub fn demand_zero(size: usize, writable: bool, user: bool) -> Self {
+        Self {
+            backing: Backing::DemandZero { writable, user },
+            ..Self::new(size)
+        }
+    }
+
+    pub fn with_guard(self, guard: usize) -> Self {
+        Self { guard, ..self }
+    }
+
+    pub fn backing(&self) -> Backing {
+        self.backing
+    }
+
+    pub fn start(&self) -> PageAddr {
+        self.start
+    }
+
+    // not counting the guard
+    pub fn size(&self) -> usize {
+        self.size
+    }
 }
 
 type RawRegionLink = *mut Region;
 type RegionLink = &'static mut Region;
 
-unsafe impl Stores<Option<RegionLink>> for Option<RawRegionLink> {
-    unsafe fn store(val: &Option<RegionLink>) -> Self {
-        val.as_ref().map(|p| *p as *const Region as *mut Region)
+unsafe impl Stores<RegionLink> for RawRegionLink {
+    unsafe fn store(val: &RegionLink) -> Self {
+        *val as *const Region as *mut Region
     }
 
-    unsafe fn extract(store: Self) -> Option<RegionLink> {
-        store.map(|p| &mut *p)
+    unsafe fn extract(store: Self) -> RegionLink {
+        &mut *store
     }
 }
 
-struct NextLinkSlot;
-unsafe impl IsSlotOf<RegionLink> for NextLinkSlot {
-    type Value = Store<Option<RawRegionLink>, Option<RegionLink>>;
+pub struct TreeLinksSlot;
+unsafe impl IsSlotOf<RegionLink> for TreeLinksSlot {
+    type Value = TreeLinks<RawRegionLink>;
 
     fn get(st: &RegionLink) -> &Self::Value {
-        &st.next
+        &st.links
     }
 
     fn get_mut(st: &mut RegionLink) -> &mut Self::Value {
-        &mut st.next
+        &mut st.links
     }
 }
 
-impl ListLink for RegionLink {
-    type Slot = NextLinkSlot;
-    type Store = Option<RawRegionLink>;
+impl TreeLink for RegionLink {
+    type Slot = TreeLinksSlot;
+    type Raw = RawRegionLink;
+
+    fn range(&self) -> Range<usize> {
+        self.start.usize()..self.start.usize() + self.size + self.guard
+    }
 }
 
-struct AddressSpace {
-    regions: IntrusiveList<RegionLink>,
+#[repr(C, align(4096))]
+struct ZeroPage([u8; PAGE_SIZE]);
+
+// mapped read-only wherever a demand-zero page has been read but not written
+static ZERO_PAGE: ZeroPage = ZeroPage([0; PAGE_SIZE]);
+
+pub fn zero_page() -> FrameAddr {
+    addr_of!(ZERO_PAGE).to_phys().as_aligned()
+}
+
+// tables come from the id-mapped 1GiB so they can be used without mapping them somewhere first
+fn alloc_table() -> Option<FrameAddr> {
+    let id_map = 0.phys_addr()..ID_MAP_SIZE.phys_addr();
+    let frame = alloc_frame_in(id_map, FrameOrder(0))?;
+    unsafe { frame.to_virt().ptr::<u8>().write_bytes(0, PAGE_SIZE) };
+    Some(frame)
+}
+
+fn is_kernel_addr(addr: usize) -> bool {
+    (addr as isize) < 0
+}
+
+pub struct AddressSpace {
+    // the part of the virtual address space that regions can be put in
+    bounds: Range<usize>,
+    regions: IntrusiveTree<RegionLink>,
+    // the top-level table
+    root: FrameAddr,
+    // tags this address space's tlb entries, None means it shares pcid 0
+    pcid: Option<Pcid>,
+    // false for new_kernel, which uses the current tables instead of its own
+    owns_root: bool,
+}
+
+impl AddressSpace {
+    // gets its own top-level table, with the kernel's half the same as the current one's
+    // kernel top-level entries made after this won't show up in it
+    pub fn new(bounds: Range<usize>) -> Option<Self> {
+        let root = alloc_table()?;
+        unsafe {
+            let src = table_virt(tlb::current_root()).unwrap();
+            let dst = root.to_virt().ptr::<usize>();
+            let half = ENTRY_COUNT / 2;
+            dst.add(half).copy_from_nonoverlapping(src.add(half), half);
+        }
+        Some(Self {
+            bounds,
+            regions: IntrusiveTree::empty(),
+            root,
+            pcid: Pcid::alloc(),
+            owns_root: true,
+        })
+    }
+
+    // for part of the kernel's half, which is mapped the same everywhere and doesn't need a pcid
+    // it uses whatever tables are current, which should be the boot tables
+    pub fn new_kernel(bounds: Range<usize>) -> Self {
+        Self {
+            bounds,
+            regions: IntrusiveTree::empty(),
+            root: tlb::current_root(),
+            pcid: None,
+            owns_root: false,
+        }
+    }
+
+    pub fn root(&self) -> FrameAddr {
+        self.root
+    }
+
+    // the l1 entry that maps addr, None if a table on the way there is missing
+    // with create, missing tables get made instead (None is then out of memory)
+    pub unsafe fn l1_entry(&self, addr: usize, create: bool) -> Option<&'static mut usize> {
+        // entries above user pages have to allow user access too, the leaf decides
+        let table_flags = if is_kernel_addr(addr) {
+            WRITABLE_FLAG
+        } else {
+            WRITABLE_FLAG | USER_FLAG
+        };
+        let mut table = table_virt(self.root)?;
+        for level in (2..=paging_levels()).rev() {
+            let shift = PAGE_SHIFT + PTE_INDEX_SIZE * (level - 1);
+            let entry = &mut *table.add((addr >> shift) % ENTRY_COUNT);
+            if *entry & PRESENT_FLAG == 0 {
+                if !create {
+                    return None;
+                }
+                *entry = Entry::at_frame(alloc_table()?)
+                    .set_flags(SubtableFlags::from_usize(table_flags))
+                    .to_usize();
+            }
+            table = table_virt((*entry & PT_FRAME_BITS).phys_addr().as_aligned())?;
+        }
+        Some(&mut *table.add((addr >> PAGE_SHIFT) % ENTRY_COUNT))
+    }
+
+    // unmaps whatever pages are mapped in the range and frees their frames,
+    // except the zero page, which is shared
+    // the tables stay, the next region there will probably need them anyways
+    pub unsafe fn unmap_range(&self, pages: Range<usize>) {
+        for page in pages.step_by(PAGE_SIZE) {
+            let Some(entry) = self.l1_entry(page, false) else {
+                continue;
+            };
+            if *entry & PRESENT_FLAG == 0 {
+                continue;
+            }
+            let frame = (*entry & PT_FRAME_BITS).phys_addr().as_aligned();
+            *entry = 0;
+            self.flush_page(page);
+            if frame != zero_page() {
+                free_frame(frame);
+            }
+        }
+    }
+
+    unsafe fn flush_page(&self, addr: usize) {
+        match &self.pcid {
+            Some(pcid) => tlb::flush_page_in(pcid, addr),
+            None => tlb::flush_page(addr),
+        }
+    }
+
+    // backs the page under addr if it's in a demand-zero region and the access is allowed
+    // false means it's a real fault
+    pub unsafe fn handle_fault(&mut self, addr: usize, error: u64) -> bool {
+        let Some(region) = self.regions.find(addr) else {
+            return false;
+        };
+        let Backing::DemandZero { writable, user } = region.backing else {
+            return false;
+        };
+        if addr >= region.start.usize() + region.size {
+            // in the guard
+            return false;
+        }
+        let write = error & FAULT_WRITE != 0;
+        if (write && !writable) || (error & FAULT_USER != 0 && !user) {
+            return false;
+        }
+        if error & FAULT_INSTR != 0 {
+            // demand-zero pages are never executable
+            return false;
+        }
+
+        let page = addr & !(PAGE_SIZE - 1);
+        let Some(entry) = self.l1_entry(page, true) else {
+            return false;
+        };
+        let mut flags = no_exec_flag();
+        if user {
+            flags |= USER_FLAG;
+        }
+        if is_kernel_addr(page) {
+            flags |= global_flag();
+        }
+        let mapped = *entry & PRESENT_FLAG != 0;
+        let on_zero_page = mapped && (*entry & PT_FRAME_BITS) == zero_page().usize();
+        if mapped && error & FAULT_PRESENT == 0 {
+            // something else backed it between the fault and now, just retry
+            // non-present entries aren't cached, so there's nothing to flush
+            return true;
+        }
+        if mapped && !(write && on_zero_page) {
+            // a protection fault on a present entry, which is real unless it's a write to the zero page
+            return false;
+        }
+
+        if !write {
+            // non-present entries aren't cached, so there's nothing to flush
+            *entry = Entry::at_frame(zero_page())
+                .set_flags(PageFlags::from_usize(flags))
+                .set_cache_mode(CacheMode::WriteBack, false)
+                .to_usize();
+            return true;
+        }
+        let Some(frame) = alloc_frame() else {
+            return false;
+        };
+        *entry = Entry::at_frame(frame)
+            .set_flags(PageFlags::from_usize(flags | WRITABLE_FLAG))
+            .set_cache_mode(CacheMode::WriteBack, false)
+            .to_usize();
+        if on_zero_page {
+            self.flush_page(page);
+        }
+        // it's writable and mapped now, so it can be zeroed right where it is
+        (page as *mut u8).write_bytes(0, PAGE_SIZE);
+        true
+    }
+
+    // finds a free spot for region and sets its start
+    pub fn insert_anywhere(&mut self, region: RegionLink) -> Result<PageAddr, RegionLink> {
+        assert!(region.size % PAGE_SIZE == 0 && region.guard % PAGE_SIZE == 0);
+        let Some(start) = self
+            .regions
+            .find_gap(region.size + region.guard, self.bounds.clone())
+        else {
+            return Err(region);
+        };
+        region.start = start.virt_addr().as_aligned();
+        self.regions.insert(region);
+        Ok(start.virt_addr().as_aligned())
+    }
+
+    pub fn insert_fixed(&mut self, region: RegionLink) -> Result<(), RegionLink> {
+        let range = region.range();
+        let in_bounds = self.bounds.start <= range.start && range.end <= self.bounds.end;
+        if !in_bounds || self.regions.range(range).next().is_some() {
+            return Err(region);
+        }
+        self.regions.insert(region);
+        Ok(())
+    }
+
+    pub fn remove_containing(&mut self, addr: usize) -> Option<RegionLink> {
+        self.regions.take_containing(addr)
+    }
+}
+
+// frees the table entry points to and everything under it down to the l1 tables
+// level is the level of the table entry points to
+unsafe fn free_table(entry: usize, level: usize) {
+    if entry & PRESENT_FLAG == 0 {
+        return;
+    }
+    let frame = (entry & PT_FRAME_BITS).phys_addr().as_aligned();
+    if level > 1 {
+        let table = table_virt(frame).unwrap();
+        for i in 0..ENTRY_COUNT {
+            free_table(*table.add(i), level - 1);
+        }
+    }
+    free_frame(frame);
+}
+
+// frees the user half's tables and the top-level table, the kernel half is shared
+// whatever is still mapped in the regions is up to the owner to free first
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if !self.owns_root {
+            return;
+        }
+        unsafe {
+            let root = table_virt(self.root).unwrap();
+            for i in 0..ENTRY_COUNT / 2 {
+                free_table(*root.add(i), paging_levels() - 1);
+            }
+        }
+        free_frame(self.root);
+    }
+}
+
+// the address space below the kernel's half that the cpu is in, if it's not the boot one
+static CURRENT: AtomicPtr<SpinLock<AddressSpace>> = AtomicPtr::new(null_mut());
+
+pub unsafe fn activate(space: &'static SpinLock<AddressSpace>) {
+    {
+        let space = space.lock();
+        tlb::switch_address_space(space.root, space.pcid.as_ref());
+    }
+    CURRENT.store(space as *const _ as *mut _, Ordering::Relaxed);
+}
+
+// called by the page fault handler, true if the fault was handled and the access can be retried
+// nothing touches a region's memory while holding its address space's lock,
+// so the lock can be waited on, whoever has it isn't the one that faulted
+pub unsafe fn handle_page_fault(addr: usize, error: u64) -> bool {
+    if is_kernel_addr(addr) {
+        return error & FAULT_USER == 0 && super::vmalloc::handle_fault(addr, error);
+    }
+    let current = CURRENT.load(Ordering::Relaxed);
+    if current.is_null() {
+        return false;
+    }
+    (*current).lock().handle_fault(addr, error)
 }
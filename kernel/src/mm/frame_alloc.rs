@@ -0,0 +1,529 @@
+use core::mem::MaybeUninit;
+use core::ops::Range;
+use core::ptr::{addr_of, addr_of_mut};
+
+#[cfg(not(feature = "buddy-frame-alloc"))]
+use sparkle_lib::bitmap_frame_alloc::BitmapFrameAllocator;
+#[cfg(feature = "buddy-frame-alloc")]
+use sparkle_lib::buddy_frame_alloc::BuddyFrameAllocator;
+pub use sparkle_lib::frame_alloc::{FitPolicy, FrameOrder, FrameStats};
+use sparkle_lib::frame_alloc::{FrameAllocator, MAX_PAGE_ORDER};
+use sparkle_lib::ranges::RangesDifference;
+
+use crate::mm::bump_alloc::BumpAllocator;
+use crate::mm::frame_cache;
+#[cfg(feature = "frame-debug")]
+use crate::mm::frame_debug;
+use crate::mm::frame_meta::{self, frame_meta, Frame};
+use crate::mm::kernel_map::starting_page_tables;
+use crate::mm::numa::{self, NodeId, MAX_NODES};
+use crate::mm::tlb;
+use crate::multiboot::{self, MMapEntryKind};
+use crate::sync::SpinLock;
+use crate::types::page::PAGE_SIZE;
+use crate::types::{five_level_paging, PhysAddr};
+use crate::types::{page_table::PageTable, FrameAddr, HasPhysAddr, HasVirtAddr};
+use crate::util::align::Alignment;
+
+// physical memory that some devices are limited to
+#[derive(Clone, Copy, Debug)]
+pub enum Zone {
+    // legacy ISA DMA, below 16MiB
+    Dma,
+    // 32-bit DMA engines, below 4GiB
+    Dma32,
+    Normal,
+}
+
+impl Zone {
+    pub fn range(self) -> Range<PhysAddr> {
+        let end = match self {
+            Zone::Dma => 16 << 20,
+            Zone::Dma32 => 4 << 30,
+            Zone::Normal => usize::MAX,
+        };
+        0.phys_addr()..end.phys_addr()
+    }
+}
+
+// what the memory map had in it, in bytes
+#[derive(Clone, Copy)]
+pub struct MemorySummary {
+    pub total: usize,
+    // what the frame allocator got, including anything reclaimed
+    pub usable: usize,
+    pub reserved: usize,
+    // where the ACPI tables are, it can be used once they've been parsed
+    pub acpi_reclaimable: usize,
+    pub reclaimed: usize,
+    // ACPI non-volatile storage, can never be used
+    pub nvs: usize,
+    pub bad: usize,
+}
+
+impl MemorySummary {
+    const fn empty() -> Self {
+        Self {
+            total: 0,
+            usable: 0,
+            reserved: 0,
+            acpi_reclaimable: 0,
+            reclaimed: 0,
+            nvs: 0,
+            bad: 0,
+        }
+    }
+}
+
+impl core::fmt::Display for MemorySummary {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "memory total: {} KiB", self.total / 1024)?;
+        writeln!(f, "  usable: {} KiB", self.usable / 1024)?;
+        writeln!(f, "  reserved: {} KiB", self.reserved / 1024)?;
+        writeln!(
+            f,
+            "  ACPI reclaimable: {} KiB, {} KiB reclaimed",
+            self.acpi_reclaimable / 1024,
+            self.reclaimed / 1024
+        )?;
+        writeln!(f, "  ACPI NVS: {} KiB", self.nvs / 1024)?;
+        write!(f, "  bad: {} KiB", self.bad / 1024)
+    }
+}
+
+static MEMORY_SUMMARY: SpinLock<MemorySummary> = SpinLock::new(MemorySummary::empty());
+
+pub fn memory_summary() -> MemorySummary {
+    *MEMORY_SUMMARY.lock()
+}
+
+#[cfg(not(feature = "buddy-frame-alloc"))]
+type NodeAllocator = BitmapFrameAllocator;
+#[cfg(feature = "buddy-frame-alloc")]
+type NodeAllocator = BuddyFrameAllocator;
+
+// indexed by NodeId, nodes without memory stay None
+static FRAME_ALLOCS: [SpinLock<Option<NodeAllocator>>; MAX_NODES] =
+    [const { SpinLock::new(None) }; MAX_NODES];
+
+// the allocators work in frame indices
+fn frame_at(idx: usize) -> FrameAddr {
+    0.phys_addr().as_aligned::<FrameAddr>().next(idx)
+}
+
+// the call sites are only recorded with frame-debug, but they're passed along either way
+#[track_caller]
+fn on_alloc(frame: FrameAddr, count: usize) {
+    #[cfg(feature = "frame-debug")]
+    frame_debug::on_alloc(frame, count, core::panic::Location::caller());
+    #[cfg(not(feature = "frame-debug"))]
+    let _ = count;
+    frame_meta(frame).on_alloc();
+}
+
+#[track_caller]
+fn on_free(frame: FrameAddr, count: usize) {
+    // before the metadata, since a double free looks fine to it
+    #[cfg(feature = "frame-debug")]
+    frame_debug::on_free(frame, count, core::panic::Location::caller());
+    #[cfg(not(feature = "frame-debug"))]
+    let _ = count;
+    frame_meta(frame).on_free();
+}
+
+// tries the node first, then the rest from nearest to farthest
+// if that fails, the per-CPU caches get emptied and it tries again
+#[track_caller]
+fn alloc_near(
+    node: NodeId,
+    count: usize,
+    mut alloc: impl FnMut(&mut NodeAllocator) -> Option<usize>,
+) -> Option<FrameAddr> {
+    let mut try_all_nodes = || {
+        numa::fallback_order(node).find_map(|n| {
+            FRAME_ALLOCS[n.0 as usize]
+                .lock()
+                .as_mut()
+                .and_then(&mut alloc)
+        })
+    };
+    let frame = try_all_nodes()
+        .or_else(|| {
+            if frame_cache::drain_all() == 0 {
+                return None;
+            }
+            try_all_nodes()
+        })
+        .map(frame_at)?;
+    on_alloc(frame, count);
+    Some(frame)
+}
+
+#[track_caller]
+fn free_to_node(frame: FrameAddr, count: usize, free: impl FnOnce(&mut NodeAllocator)) {
+    on_free(frame, count);
+    let node = numa::node_of(frame.phys_addr());
+    let mut frame_alloc = FRAME_ALLOCS[node.0 as usize].lock();
+    let frame_alloc = frame_alloc.as_mut().unwrap();
+    assert!(frame_alloc.contains(frame.index()));
+    free(frame_alloc);
+}
+
+// for frame_cache: fills out with free frames from near node, one lock per node
+// the metadata isn't touched, returns how many it got
+pub(super) fn take_frames(node: NodeId, order: FrameOrder, out: &mut [Option<FrameAddr>]) -> usize {
+    let mut count = 0;
+    for n in numa::fallback_order(node) {
+        let mut frame_alloc = FRAME_ALLOCS[n.0 as usize].lock();
+        let Some(frame_alloc) = frame_alloc.as_mut() else {
+            continue;
+        };
+        while count < out.len() {
+            let Some(frame) = frame_alloc.alloc(order) else {
+                break;
+            };
+            out[count] = Some(frame_at(frame));
+            count += 1;
+        }
+        if count == out.len() {
+            break;
+        }
+    }
+    count
+}
+
+// for frame_cache: the other direction, frames that are already free as far as the metadata knows
+pub(super) fn return_frames(frames: impl Iterator<Item = FrameAddr>, order: FrameOrder) {
+    // the frames mostly come from the same node, so keep its lock until that changes
+    let mut locked = None;
+    for frame in frames {
+        let node = numa::node_of(frame.phys_addr());
+        if locked.as_ref().map(|(n, _)| *n) != Some(node) {
+            // unlock before locking the next one, holding two could deadlock
+            drop(locked.take());
+            locked = Some((node, FRAME_ALLOCS[node.0 as usize].lock()));
+        }
+        let (_, frame_alloc) = locked.as_mut().unwrap();
+        frame_alloc.as_mut().unwrap().free(frame.index(), order);
+    }
+}
+
+// always goes to the node's allocator, skipping the per-CPU cache
+#[track_caller]
+pub fn alloc_frame_on(node: NodeId, order: FrameOrder) -> Option<FrameAddr> {
+    alloc_near(node, 1 << order.0, |frame_alloc| frame_alloc.alloc(order))
+}
+
+#[track_caller]
+pub fn alloc_frame_with_order(order: FrameOrder) -> Option<FrameAddr> {
+    if let Some(frame) = frame_cache::alloc(order) {
+        on_alloc(frame, 1 << order.0);
+        return Some(frame);
+    }
+    alloc_frame_on(numa::current_node(), order)
+}
+
+#[track_caller]
+pub fn alloc_frame() -> Option<FrameAddr> {
+    alloc_frame_with_order(FrameOrder(0))
+}
+
+// only gives back a block that's entirely inside range
+#[track_caller]
+pub fn alloc_frame_in(range: Range<PhysAddr>, order: FrameOrder) -> Option<FrameAddr> {
+    let frames =
+        range.start.align_up::<FrameAddr>().index()..range.end.align::<FrameAddr>().index();
+    alloc_near(numa::current_node(), 1 << order.0, |frame_alloc| {
+        frame_alloc.alloc_in(frames.clone(), order)
+    })
+}
+
+#[track_caller]
+pub fn alloc_frame_in_zone(zone: Zone, order: FrameOrder) -> Option<FrameAddr> {
+    alloc_frame_in(zone.range(), order)
+}
+
+#[track_caller]
+pub fn free_frame_with_order(frame: FrameAddr, order: FrameOrder) {
+    if frame_cache::is_cached_order(order) {
+        on_free(frame, 1 << order.0);
+        if !frame_cache::free(frame, order) {
+            return_frames(core::iter::once(frame), order);
+        }
+        return;
+    }
+    free_to_node(frame, 1 << order.0, |frame_alloc| {
+        frame_alloc.free(frame.index(), order)
+    });
+}
+
+#[track_caller]
+pub fn free_frame(frame: FrameAddr) {
+    free_frame_with_order(frame, FrameOrder(0));
+}
+
+// a contiguous run of count frames, starting at a multiple of align frames
+// it has to be freed all at once with free_frames
+#[track_caller]
+pub fn alloc_frames(count: usize, align: usize) -> Option<FrameAddr> {
+    assert!(count != 0);
+    // node allocators are only aligned this much
+    assert!(align <= 1 << MAX_PAGE_ORDER);
+    let align = Alignment::new(align);
+    alloc_near(numa::current_node(), count, |frame_alloc| {
+        frame_alloc.alloc_run(count, align)
+    })
+}
+
+#[track_caller]
+pub fn free_frames(frame: FrameAddr, count: usize) {
+    free_to_node(frame, count, |frame_alloc| {
+        frame_alloc.free_run(frame.index(), count)
+    });
+}
+
+pub fn node_stats(node: NodeId) -> Option<FrameStats> {
+    FRAME_ALLOCS[node.0 as usize]
+        .lock()
+        .as_ref()
+        .map(|frame_alloc| frame_alloc.stats())
+}
+
+pub fn stats() -> FrameStats {
+    let mut stats = FrameStats::empty();
+    for node in 0..MAX_NODES {
+        if let Some(node_stats) = node_stats(NodeId(node as u8)) {
+            stats.add(&node_stats);
+        }
+    }
+    stats
+}
+
+// returns the old policy
+pub fn set_fit_policy(fit: FitPolicy) -> FitPolicy {
+    let mut old = fit;
+    for frame_alloc in FRAME_ALLOCS.iter().rev() {
+        if let Some(frame_alloc) = frame_alloc.lock().as_mut() {
+            old = frame_alloc.set_fit_policy(fit);
+        }
+    }
+    old
+}
+
+// takes another reference to an allocated frame
+pub fn get_frame(frame: FrameAddr) {
+    frame_meta(frame).inc_ref();
+}
+
+// drops a reference, and frees the frame if it was the last one
+#[track_caller]
+pub fn put_frame_with_order(frame: FrameAddr, order: FrameOrder) {
+    if frame_meta(frame).dec_ref() {
+        free_frame_with_order(frame, order);
+    }
+}
+
+#[track_caller]
+pub fn put_frame(frame: FrameAddr) {
+    put_frame_with_order(frame, FrameOrder(0));
+}
+
+extern "sysv64" {
+    // these are linker variables; their addresses matter, but they have no values
+    static HIGH_ID_MAP_VMA: u8;
+    static KERNEL_END_VMA: u8;
+}
+
+fn get_usable_memory(
+    multiboot_info: multiboot::Info,
+) -> impl Iterator<Item = Range<PhysAddr>> + Clone {
+    let free_mem = get_memory_of_kind(multiboot_info, MMapEntryKind::Available);
+
+    let kernel_end_phys = addr_of!(KERNEL_END_VMA).to_phys();
+    let mut used_mem = [
+        0usize.phys_addr()..kernel_end_phys, // first 1MB, then kernel binary immediately after
+        multiboot_info.mem_range(),          // the multiboot info
+    ];
+    used_mem.sort_unstable_by_key(|r| r.start);
+
+    RangesDifference::new(free_mem, used_mem.into_iter())
+}
+
+fn get_memory_of_kind(
+    multiboot_info: multiboot::Info,
+    kind: MMapEntryKind,
+) -> impl Iterator<Item = Range<PhysAddr>> + Clone {
+    let mmap: multiboot::MMapEntryIterator = multiboot_info.get_tag().unwrap();
+    mmap.filter(move |e| e.kind() == kind)
+        .map(|e| e.addr..(e.addr.usize() + e.len).phys_addr())
+}
+
+fn get_frame_count(mem: impl Iterator<Item = Range<PhysAddr>>) -> usize {
+    mem.map(|r| r.end.align_up::<FrameAddr>())
+        .max()
+        .unwrap()
+        .index()
+}
+
+pub unsafe fn init(multiboot_info: multiboot::Info) {
+    // clear low-address identity mapping set up during boot
+    // it's probably fine to just leave it but i dont want to
+    let ptl4: &mut PageTable = unsafe { &mut *addr_of_mut!(starting_page_tables[0]) };
+    ptl4[0] = MaybeUninit::zeroed();
+    let ptl3: &mut PageTable = unsafe { &mut *addr_of_mut!(starting_page_tables[1]) };
+    ptl3[0] = MaybeUninit::zeroed();
+    // with 5 levels, the l5 table points at the l4 table from entry 0 too
+    if five_level_paging() {
+        let ptl5: &mut PageTable = unsafe { &mut *addr_of_mut!(starting_page_tables[3]) };
+        ptl5[0] = MaybeUninit::zeroed();
+    }
+
+    // the alias goes through the same l2 table as the id-map, which has global pages in it
+    unsafe { tlb::flush_global() };
+
+    let usable_memory = get_usable_memory(multiboot_info);
+    for range in usable_memory.clone() {
+        println!("open: {:?}", range);
+    }
+
+    // the allocators need to cover the ACPI tables too, so they can be reclaimed later
+    let acpi_memory = get_memory_of_kind(multiboot_info, MMapEntryKind::ACPIReclaimable);
+    let frame_count = get_frame_count(usable_memory.clone().chain(acpi_memory));
+    let topology = numa::topology();
+
+    // each node's allocator covers the frames from the start of its first memory range
+    // to the end of its last
+    let mut node_spans: [Option<Range<usize>>; MAX_NODES] = [const { None }; MAX_NODES];
+    for node in topology.nodes() {
+        let span = topology
+            .node_memory(node)
+            .map(|r| frame_index_range(r, frame_count))
+            .filter(|r| !r.is_empty())
+            .reduce(|a, b| core::cmp::min(a.start, b.start)..core::cmp::max(a.end, b.end));
+        if let Some(Range { start, end }) = span {
+            let base = Alignment::new(1 << MAX_PAGE_ORDER).align_down(start);
+            node_spans[node.0 as usize] = Some(base..end);
+        }
+    }
+
+    let bookkeeping_size_bytes = node_spans
+        .iter()
+        .flatten()
+        .map(|span| NodeAllocator::bookkeeping_size(span.len()))
+        .sum::<usize>();
+    // the frame metadata array goes first, then the allocators' bookkeeping
+    let reserved_size_bytes = frame_meta::meta_size_bytes(frame_count) + bookkeeping_size_bytes;
+    let mut reserved_addr = None;
+    for range in usable_memory.clone() {
+        let start = Alignment::of::<Frame>().align_up(range.start.usize());
+        if range.end.usize() > start && range.end.usize() - start > reserved_size_bytes {
+            reserved_addr = Some(start);
+            break;
+        }
+    }
+    let reserved_addr = reserved_addr.unwrap();
+    // every frame that the bookkeeping or metadata touch is used, even partially
+    let reserved_frames = reserved_addr.phys_addr().align::<FrameAddr>().index()
+        ..(reserved_addr + reserved_size_bytes)
+            .phys_addr()
+            .align_up::<FrameAddr>()
+            .index();
+
+    let mut bump_alloc = BumpAllocator::new_raw(
+        reserved_addr.phys_addr().to_virt().ptr(),
+        reserved_size_bytes,
+    );
+    frame_meta::init(&mut bump_alloc, frame_count);
+
+    for node in topology.nodes() {
+        let Some(span) = node_spans[node.0 as usize].clone() else {
+            continue;
+        };
+        println!("node {} frames: {:?}", node.0, span);
+        let mut frame_alloc = NodeAllocator::new(&mut bump_alloc, span);
+
+        // only the usable memory that's in this node
+        for range in usable_memory.clone() {
+            let usable = frame_index_range(range, frame_count);
+            for node_range in topology.node_memory(node) {
+                let node_frames = frame_index_range(node_range, frame_count);
+                let both = core::cmp::max(usable.start, node_frames.start)
+                    ..core::cmp::min(usable.end, node_frames.end);
+                // minus the bookkeeping and metadata
+                let [before, after] = split_around(both, &reserved_frames);
+                frame_alloc.add_free_range(before);
+                frame_alloc.add_free_range(after);
+            }
+        }
+
+        *FRAME_ALLOCS[node.0 as usize].lock() = Some(frame_alloc);
+    }
+
+    let mut summary = MemorySummary::empty();
+    let mmap: multiboot::MMapEntryIterator = multiboot_info.get_tag().unwrap();
+    for entry in mmap {
+        summary.total += entry.len;
+        match entry.kind() {
+            MMapEntryKind::Available => {}
+            MMapEntryKind::Reserved => summary.reserved += entry.len,
+            MMapEntryKind::ACPIReclaimable => summary.acpi_reclaimable += entry.len,
+            MMapEntryKind::NVS => summary.nvs += entry.len,
+            MMapEntryKind::BadRam => {
+                println!(
+                    "bad memory: {:?}",
+                    entry.addr..(entry.addr.usize() + entry.len).phys_addr()
+                );
+                summary.bad += entry.len;
+            }
+        }
+    }
+    // the kernel, multiboot info and bookkeeping are taken out of this
+    summary.usable = stats().free_frames * PAGE_SIZE;
+    println!("{}", summary);
+    *MEMORY_SUMMARY.lock() = summary;
+}
+
+// hands the memory the ACPI tables were in to the allocators
+// Safety: nothing can be using the ACPI tables anymore
+pub unsafe fn reclaim_acpi_memory(multiboot_info: multiboot::Info) {
+    let topology = numa::topology();
+    let mut reclaimed_frames = 0;
+    for range in get_memory_of_kind(multiboot_info, MMapEntryKind::ACPIReclaimable) {
+        let frames = frame_index_range(range, usize::MAX);
+        for node in topology.nodes() {
+            let mut frame_alloc = FRAME_ALLOCS[node.0 as usize].lock();
+            let Some(frame_alloc) = frame_alloc.as_mut() else {
+                continue;
+            };
+            for node_range in topology.node_memory(node) {
+                let node_frames = frame_index_range(node_range, usize::MAX);
+                let both = core::cmp::max(frames.start, node_frames.start)
+                    ..core::cmp::min(frames.end, node_frames.end);
+                reclaimed_frames += frame_alloc.add_free_range(both);
+            }
+        }
+    }
+
+    let mut summary = MEMORY_SUMMARY.lock();
+    summary.reclaimed += reclaimed_frames * PAGE_SIZE;
+    summary.usable += reclaimed_frames * PAGE_SIZE;
+    println!(
+        "reclaimed {} KiB of ACPI memory",
+        reclaimed_frames * PAGE_SIZE / 1024
+    );
+}
+
+// the frames entirely inside range, cut off at frame_count
+fn frame_index_range(range: Range<PhysAddr>, frame_count: usize) -> Range<usize> {
+    let start = range.start.align_up::<FrameAddr>().index();
+    let end = core::cmp::min(range.end.align::<FrameAddr>().index(), frame_count);
+    start..core::cmp::max(start, end)
+}
+
+// the parts of range before and after hole, either can be empty
+fn split_around(range: Range<usize>, hole: &Range<usize>) -> [Range<usize>; 2] {
+    [
+        range.start..core::cmp::min(range.end, hole.start),
+        core::cmp::max(range.start, hole.end)..range.end,
+    ]
+}
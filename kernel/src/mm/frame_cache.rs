@@ -0,0 +1,125 @@
+use crate::cpu::{self, MAX_CPUS};
+use crate::sync::SpinLock;
+use crate::types::FrameAddr;
+
+use super::frame_alloc::{self, FrameOrder};
+use super::numa;
+
+// per-CPU stacks of free frames in front of the node allocators,
+// so most allocs and frees don't take a shared lock or walk the bitmaps
+// frames in here are free as far as the metadata is concerned
+
+struct Magazine<const N: usize> {
+    frames: [Option<FrameAddr>; N],
+    len: usize,
+}
+
+impl<const N: usize> Magazine<N> {
+    const fn empty() -> Self {
+        Self {
+            frames: [None; N],
+            len: 0,
+        }
+    }
+
+    // refills and drains move half a magazine,
+    // so a CPU that's going back and forth around empty or full doesn't hit the allocator every time
+    fn alloc(&mut self, order: FrameOrder) -> Option<FrameAddr> {
+        if self.len == 0 {
+            let node = numa::current_node();
+            self.len = frame_alloc::take_frames(node, order, &mut self.frames[..N / 2]);
+        }
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        self.frames[self.len].take()
+    }
+
+    fn free(&mut self, frame: FrameAddr, order: FrameOrder) {
+        if self.len == N {
+            self.drain(N / 2, order);
+        }
+        self.frames[self.len] = Some(frame);
+        self.len += 1;
+    }
+
+    // gives back the oldest frames, the newest are more likely to still be in the CPU cache
+    fn drain(&mut self, count: usize, order: FrameOrder) {
+        let count = core::cmp::min(count, self.len);
+        frame_alloc::return_frames(self.frames[..count].iter().map(|f| f.unwrap()), order);
+        self.frames.copy_within(count..self.len, 0);
+        self.frames[self.len - count..self.len].fill(None);
+        self.len -= count;
+    }
+}
+
+struct CpuCache {
+    small: Magazine<64>,
+    // 2MiB frames, for large pages
+    large: Magazine<8>,
+}
+
+const LARGE_ORDER: FrameOrder = FrameOrder(9);
+
+impl CpuCache {
+    const fn empty() -> Self {
+        Self {
+            small: Magazine::empty(),
+            large: Magazine::empty(),
+        }
+    }
+
+    // returns how many frames were given back
+    fn drain_all(&mut self) -> usize {
+        let count = self.small.len + (self.large.len << LARGE_ORDER.0);
+        self.small.drain(self.small.len, FrameOrder(0));
+        self.large.drain(self.large.len, LARGE_ORDER);
+        count
+    }
+}
+
+static CACHES: [SpinLock<CpuCache>; MAX_CPUS] =
+    [const { SpinLock::new(CpuCache::empty()) }; MAX_CPUS];
+
+fn local_cache() -> Option<&'static SpinLock<CpuCache>> {
+    CACHES.get(cpu::index())
+}
+
+pub fn is_cached_order(order: FrameOrder) -> bool {
+    order.0 == 0 || order.0 == LARGE_ORDER.0
+}
+
+// None if the order isn't cached, or there's nothing left to refill with
+pub fn alloc(order: FrameOrder) -> Option<FrameAddr> {
+    if !is_cached_order(order) {
+        return None;
+    }
+    let mut cache = local_cache()?.lock();
+    match order.0 {
+        0 => cache.small.alloc(order),
+        _ => cache.large.alloc(order),
+    }
+}
+
+// returns false if the frame wasn't taken, and should be freed normally
+pub fn free(frame: FrameAddr, order: FrameOrder) -> bool {
+    if !is_cached_order(order) {
+        return false;
+    }
+    let Some(cache) = local_cache() else {
+        return false;
+    };
+    let mut cache = cache.lock();
+    match order.0 {
+        0 => cache.small.free(frame, order),
+        _ => cache.large.free(frame, order),
+    }
+    true
+}
+
+// empties every CPU's cache, for when memory is low
+// returns how many order-0 frames worth were given back
+pub fn drain_all() -> usize {
+    CACHES.iter().map(|cache| cache.lock().drain_all()).sum()
+}
@@ -0,0 +1,122 @@
+use core::fmt::Display;
+use core::panic::Location;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+use super::frame_meta::frame_meta;
+use crate::types::{page::PAGE_SIZE, FrameAddr, HasPhysAddr, HasVirtAddr, ID_MAP_SIZE};
+
+// shadow records of every allocation, only built with the frame-debug feature
+// the first frame of an allocation knows how big it is and where it came from,
+// every freed frame knows where it was freed and gets filled with poison
+
+const POISON: u64 = 0x6b6b_6b6b_6b6b_6b6b;
+
+type Site = &'static Location<'static>;
+
+#[derive(Default)]
+pub struct FrameRecord {
+    // how many frames were allocated starting here, 0 if it's not the start of an allocation
+    frames: AtomicUsize,
+    alloc_site: AtomicPtr<Location<'static>>,
+    free_site: AtomicPtr<Location<'static>>,
+    poisoned: AtomicBool,
+}
+
+fn load_site(site: &AtomicPtr<Location<'static>>) -> Option<Site> {
+    unsafe { site.load(Ordering::Relaxed).as_ref() }
+}
+
+fn store_site(slot: &AtomicPtr<Location<'static>>, site: Site) {
+    slot.store(site as *const Location as *mut Location, Ordering::Relaxed);
+}
+
+struct ShowSite(Option<Site>);
+
+impl Display for ShowSite {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(site) => write!(f, "{}", site),
+            None => write!(f, "<nowhere>"),
+        }
+    }
+}
+
+// only frames in the id-map can be poisoned, the rest just get the records
+fn frame_words(frame: FrameAddr) -> Option<&'static mut [u64]> {
+    if frame.usize() + PAGE_SIZE > ID_MAP_SIZE {
+        return None;
+    }
+    let ptr = frame.phys_addr().to_virt().ptr::<u64>();
+    Some(unsafe { core::slice::from_raw_parts_mut(ptr, PAGE_SIZE / 8) })
+}
+
+pub fn on_alloc(frame: FrameAddr, count: usize, site: Site) {
+    let record = &frame_meta(frame).debug;
+    if record.frames.load(Ordering::Relaxed) != 0 {
+        panic!(
+            "frame {} was allocated at {}, then handed out again at {} without being freed",
+            frame,
+            ShowSite(load_site(&record.alloc_site)),
+            site
+        );
+    }
+
+    for i in 0..count {
+        let part = frame.next(i);
+        let part_record = &frame_meta(part).debug;
+        if !part_record.poisoned.swap(false, Ordering::Relaxed) {
+            continue;
+        }
+        let Some(words) = frame_words(part) else {
+            continue;
+        };
+        if let Some(offset) = words.iter().position(|&w| w != POISON) {
+            panic!(
+                "frame {} was written to at offset {:#x} after being freed at {}, found when allocating it at {}",
+                part,
+                offset * 8,
+                ShowSite(load_site(&part_record.free_site)),
+                site
+            );
+        }
+    }
+
+    store_site(&record.alloc_site, site);
+    record.frames.store(count, Ordering::Relaxed);
+}
+
+pub fn on_free(frame: FrameAddr, count: usize, site: Site) {
+    let record = &frame_meta(frame).debug;
+    let allocated = record.frames.swap(0, Ordering::Relaxed);
+    if allocated == 0 {
+        panic!(
+            "frame {} freed at {} isn't allocated, it was last freed at {}",
+            frame,
+            site,
+            ShowSite(load_site(&record.free_site))
+        );
+    }
+    if allocated != count {
+        panic!(
+            "frame {} was allocated at {} as {} frames, but freed at {} as {} frames",
+            frame,
+            ShowSite(load_site(&record.alloc_site)),
+            allocated,
+            site,
+            count
+        );
+    }
+
+    for i in 0..count {
+        let part = frame.next(i);
+        let part_record = &frame_meta(part).debug;
+        store_site(&part_record.free_site, site);
+        if let Some(words) = frame_words(part) {
+            words.fill(POISON);
+            part_record.poisoned.store(true, Ordering::Relaxed);
+        }
+    }
+    // so a stale free_site doesn't point at an old allocation
+    record.alloc_site.store(null_mut(), Ordering::Relaxed);
+}
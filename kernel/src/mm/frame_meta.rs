@@ -0,0 +1,174 @@
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicU32, AtomicUsize, Ordering};
+
+use super::bump_alloc::BumpAllocator;
+use crate::types::FrameAddr;
+
+// per-frame info that the bitmap can't hold
+// for a multi-frame allocation, the first frame's descriptor describes the whole thing
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FrameFlags(u32);
+
+impl FrameFlags {
+    pub const KERNEL: Self = Self(1 << 0);
+    pub const USER: Self = Self(1 << 1);
+    pub const PAGE_TABLE: Self = Self(1 << 2);
+    pub const SLAB: Self = Self(1 << 3);
+    // must not be moved or reclaimed, e.g. DMA buffers
+    pub const PINNED: Self = Self(1 << 4);
+
+    pub const fn none() -> Self {
+        Self(0)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl core::ops::BitOr for FrameFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+#[derive(Default)]
+pub struct Frame {
+    refcount: AtomicU32,
+    // number of page table entries that point at this frame
+    map_count: AtomicU32,
+    flags: AtomicU32,
+    // for page tables that hand out their entries, how many are in use,
+    // which can include some that haven't been mapped yet
+    used_entries: AtomicU32,
+    // whatever owns the frame (a slab, an address space, ...), if anyone wants to record it
+    owner: AtomicPtr<()>,
+    #[cfg(feature = "frame-debug")]
+    pub(super) debug: super::frame_debug::FrameRecord,
+}
+
+impl Frame {
+    pub fn refcount(&self) -> u32 {
+        self.refcount.load(Ordering::Acquire)
+    }
+
+    pub fn inc_ref(&self) {
+        let old = self.refcount.fetch_add(1, Ordering::Relaxed);
+        assert!(old != 0, "took a reference to a free frame");
+    }
+
+    // returns true if that was the last reference
+    pub fn dec_ref(&self) -> bool {
+        let old = self.refcount.fetch_sub(1, Ordering::AcqRel);
+        assert!(old != 0, "frame refcount underflow");
+        old == 1
+    }
+
+    pub fn map_count(&self) -> u32 {
+        self.map_count.load(Ordering::Relaxed)
+    }
+
+    pub fn inc_map_count(&self) {
+        self.map_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec_map_count(&self) {
+        let old = self.map_count.fetch_sub(1, Ordering::Relaxed);
+        assert!(old != 0, "frame map count underflow");
+    }
+
+    pub fn used_entries(&self) -> u32 {
+        self.used_entries.load(Ordering::Relaxed)
+    }
+
+    pub fn inc_used_entries(&self) {
+        self.used_entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec_used_entries(&self) {
+        let old = self.used_entries.fetch_sub(1, Ordering::Relaxed);
+        assert!(old != 0, "page table entry count underflow");
+    }
+
+    pub fn flags(&self) -> FrameFlags {
+        FrameFlags(self.flags.load(Ordering::Relaxed))
+    }
+
+    pub fn set_flags(&self, flags: FrameFlags) {
+        self.flags.fetch_or(flags.0, Ordering::Relaxed);
+    }
+
+    pub fn clear_flags(&self, flags: FrameFlags) {
+        self.flags.fetch_and(!flags.0, Ordering::Relaxed);
+    }
+
+    pub fn owner(&self) -> *mut () {
+        self.owner.load(Ordering::Acquire)
+    }
+
+    pub fn set_owner(&self, owner: *mut ()) {
+        self.owner.store(owner, Ordering::Release);
+    }
+
+    // called by the frame allocator
+    pub(super) fn on_alloc(&self) {
+        self.flags.store(0, Ordering::Relaxed);
+        self.map_count.store(0, Ordering::Relaxed);
+        self.used_entries.store(0, Ordering::Relaxed);
+        self.owner.store(null_mut(), Ordering::Relaxed);
+        self.refcount.store(1, Ordering::Release);
+    }
+
+    pub(super) fn on_free(&self) {
+        assert!(
+            self.refcount() <= 1,
+            "freed a frame that still has {} references",
+            self.refcount()
+        );
+        assert!(
+            self.map_count() == 0,
+            "freed a frame that's still mapped {} times",
+            self.map_count()
+        );
+        self.refcount.store(0, Ordering::Release);
+    }
+}
+
+// set once during init, never changes after
+static FRAMES: AtomicPtr<Frame> = AtomicPtr::new(null_mut());
+static FRAME_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn meta_size_bytes(frame_count: usize) -> usize {
+    frame_count * core::mem::size_of::<Frame>()
+}
+
+pub fn init(map_alloc: &mut BumpAllocator<'static>, frame_count: usize) {
+    let frames = map_alloc.alloc_slice_default::<Frame>(frame_count);
+    FRAME_COUNT.store(frame_count, Ordering::Relaxed);
+    FRAMES.store(frames.as_mut_ptr(), Ordering::Release);
+}
+
+// for callers that might run before init or get handed frames outside ram
+pub fn try_frame_meta(frame: FrameAddr) -> Option<&'static Frame> {
+    let frames = FRAMES.load(Ordering::Acquire);
+    let idx = frame.index();
+    if frames.is_null() || idx >= FRAME_COUNT.load(Ordering::Relaxed) {
+        return None;
+    }
+    Some(unsafe { &*frames.add(idx) })
+}
+
+pub fn frame_meta(frame: FrameAddr) -> &'static Frame {
+    let frames = FRAMES.load(Ordering::Acquire);
+    assert!(!frames.is_null(), "frame metadata isn't set up yet");
+    let idx = frame.index();
+    assert!(
+        idx < FRAME_COUNT.load(Ordering::Relaxed),
+        "no metadata for frame {}",
+        frame
+    );
+    unsafe { &*frames.add(idx) }
+}
@@ -0,0 +1,275 @@
+use core::ptr::{addr_of, addr_of_mut};
+
+use super::kernel_map::{global_flag, no_exec_flag, starting_page_tables};
+use super::pat;
+use crate::asm::invlpg;
+use crate::sync::SpinLock;
+use crate::types::page::{PAGE_SHIFT, PAGE_SIZE};
+use crate::types::page_table::{
+    pte_indices_to_addr, CacheMode, Entry, Flags, LargePageFlags, PageFlags, SubtableFlags,
+    ENTRY_COUNT, LARGE_PAGE_FLAG, PRESENT_FLAG, PTE_INDEX_SIZE, PT_FRAME_BITS, WRITABLE_FLAG,
+};
+use crate::types::{HasPhysAddr, HasVirtAddr, PhysAddr, VirtAddr};
+
+// mapping device memory (and firmware tables) that can be anywhere in physical memory,
+// not just in the 1GiB id-map
+// mappings go in a 1GiB window in l3 entry 510 of the boot tables, right below the id-map
+// this works before frame_alloc is up, so the tables are all static
+
+const WINDOW_L3_INDEX: usize = 510;
+const LARGE_PAGE_SIZE: usize = PAGE_SIZE << PTE_INDEX_SIZE;
+const WINDOW_PAGES: usize = ENTRY_COUNT * ENTRY_COUNT;
+// l1 tables stay in the l2 table once they're made, so this is how many 2MiB chunks
+// of the window can ever have 4K mappings in them
+const MAX_L1_TABLES: usize = 32;
+
+#[repr(C, align(4096))]
+struct Table([usize; ENTRY_COUNT]);
+
+static mut WINDOW_L2: Table = Table([0; ENTRY_COUNT]);
+static mut WINDOW_L1S: [Table; MAX_L1_TABLES] = [const { Table([0; ENTRY_COUNT]) }; MAX_L1_TABLES];
+
+fn window_start() -> usize {
+    pte_indices_to_addr(511, WINDOW_L3_INDEX, 0, 0).usize()
+}
+
+struct Window {
+    // a bit per page in the window, set if it's in use
+    used: [u64; WINDOW_PAGES / 64],
+    l1_tables_used: usize,
+    installed: bool,
+}
+
+impl Window {
+    const fn new() -> Self {
+        Self {
+            used: [0; WINDOW_PAGES / 64],
+            l1_tables_used: 0,
+            installed: false,
+        }
+    }
+
+    unsafe fn install(&mut self) {
+        if self.installed {
+            return;
+        }
+        let l3 = &mut *addr_of_mut!(starting_page_tables[1]);
+        let l2_frame = addr_of!(WINDOW_L2).to_phys().as_aligned();
+        l3[WINDOW_L3_INDEX]
+            .write(Entry::at_frame(l2_frame).set_flags(SubtableFlags::from_usize(WRITABLE_FLAG)));
+        self.installed = true;
+    }
+
+    fn is_used(&self, page: usize) -> bool {
+        self.used[page / 64] & (1 << (page % 64)) != 0
+    }
+
+    fn set_used(&mut self, pages: core::ops::Range<usize>, used: bool) {
+        for page in pages {
+            if used {
+                self.used[page / 64] |= 1 << (page % 64);
+            } else {
+                self.used[page / 64] &= !(1 << (page % 64));
+            }
+        }
+    }
+
+    // first fit, and runs of 2MiB or more line up with phys mod 2MiB so they can use large pages
+    fn find_pages(&self, count: usize, phys: usize) -> Option<usize> {
+        let (step, first) = if count * PAGE_SIZE >= LARGE_PAGE_SIZE {
+            (ENTRY_COUNT, (phys % LARGE_PAGE_SIZE) >> PAGE_SHIFT)
+        } else {
+            (1, 0)
+        };
+        let mut start = first;
+        while start + count <= WINDOW_PAGES {
+            match (start..start + count)
+                .rev()
+                .find(|&page| self.is_used(page))
+            {
+                None => return Some(start),
+                // nothing up to the used page can work
+                Some(used) => start += (used + 1 - start).div_ceil(step) * step,
+            }
+        }
+        None
+    }
+
+    fn take_l1_table(&mut self) -> Option<*mut usize> {
+        if self.l1_tables_used == MAX_L1_TABLES {
+            return None;
+        }
+        let table = unsafe { addr_of_mut!(WINDOW_L1S[self.l1_tables_used]) };
+        self.l1_tables_used += 1;
+        Some(table.cast())
+    }
+
+    // pages is in window pages, phys is page aligned
+    unsafe fn map(&mut self, pages: core::ops::Range<usize>, phys: usize, mode: CacheMode) -> bool {
+        let l2 = &mut (*addr_of_mut!(WINDOW_L2)).0;
+        let flags = WRITABLE_FLAG | no_exec_flag() | global_flag();
+        let mut page = pages.start;
+        while page < pages.end {
+            let frame = phys + ((page - pages.start) << PAGE_SHIFT);
+            let l2_idx = page / ENTRY_COUNT;
+            let whole_chunk = page % ENTRY_COUNT == 0 && page + ENTRY_COUNT <= pages.end;
+            // chunks that already have an l1 table just use it
+            if whole_chunk && l2[l2_idx] & PRESENT_FLAG == 0 {
+                l2[l2_idx] = Entry::at_frame(frame.phys_addr().as_aligned())
+                    .set_flags(LargePageFlags::from_usize(flags))
+                    .set_cache_mode(mode, true)
+                    .to_usize();
+                page += ENTRY_COUNT;
+                continue;
+            }
+
+            if l2[l2_idx] & PRESENT_FLAG == 0 {
+                let Some(table) = self.take_l1_table() else {
+                    return false;
+                };
+                let table_frame = table.to_phys().as_aligned();
+                l2[l2_idx] = Entry::at_frame(table_frame)
+                    .set_flags(SubtableFlags::from_usize(WRITABLE_FLAG))
+                    .to_usize();
+            }
+            let l1 = (l2[l2_idx] & PT_FRAME_BITS)
+                .phys_addr()
+                .to_virt()
+                .ptr::<usize>();
+            *l1.add(page % ENTRY_COUNT) = Entry::at_frame(frame.phys_addr().as_aligned())
+                .set_flags(PageFlags::from_usize(flags))
+                .set_cache_mode(mode, false)
+                .to_usize();
+            page += 1;
+        }
+        true
+    }
+
+    // l1 tables are kept around for the next mapping in their chunk
+    unsafe fn unmap(&mut self, pages: core::ops::Range<usize>) {
+        let l2 = &mut (*addr_of_mut!(WINDOW_L2)).0;
+        let mut page = pages.start;
+        while page < pages.end {
+            let l2_idx = page / ENTRY_COUNT;
+            let addr = window_start() + (page << PAGE_SHIFT);
+            if l2[l2_idx] & PRESENT_FLAG == 0 {
+                page += 1;
+                continue;
+            }
+            if l2[l2_idx] & LARGE_PAGE_FLAG != 0 {
+                l2[l2_idx] = 0;
+                invlpg(addr);
+                page += ENTRY_COUNT;
+                continue;
+            }
+            let l1 = (l2[l2_idx] & PT_FRAME_BITS)
+                .phys_addr()
+                .to_virt()
+                .ptr::<usize>();
+            *l1.add(page % ENTRY_COUNT) = 0;
+            invlpg(addr);
+            page += 1;
+        }
+    }
+}
+
+static WINDOW: SpinLock<Window> = SpinLock::new(Window::new());
+
+// a mapping of some physical memory, unmapped when it's dropped
+// accesses should be volatile, the compiler doesn't know device registers have side effects
+pub struct MmioRegion {
+    // where phys is mapped, not necessarily page aligned
+    virt: VirtAddr,
+    phys: PhysAddr,
+    len: usize,
+}
+
+impl MmioRegion {
+    pub fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    pub fn virt(&self) -> VirtAddr {
+        self.virt
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset + core::mem::size_of::<T>() <= self.len,
+            "offset {:#x} is outside the mapping",
+            offset
+        );
+        (self.virt.usize() + offset) as *mut T
+    }
+
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { self.ptr::<T>(offset).read_volatile() }
+    }
+
+    pub fn write<T: Copy>(&self, offset: usize, val: T) {
+        unsafe { self.ptr::<T>(offset).write_volatile(val) }
+    }
+
+    // keeps the mapping forever
+    pub fn leak(self) -> VirtAddr {
+        let virt = self.virt;
+        core::mem::forget(self);
+        virt
+    }
+}
+
+impl Drop for MmioRegion {
+    fn drop(&mut self) {
+        unsafe { iounmap(self.virt, self.len) };
+    }
+}
+
+// maps phys..phys + len, which doesn't have to be page aligned
+// None if the window is full
+pub fn ioremap(phys: PhysAddr, len: usize, mode: CacheMode) -> Option<MmioRegion> {
+    assert!(len > 0, "can't map an empty region");
+    let offset = phys.usize() % PAGE_SIZE;
+    let phys_start = phys.usize() - offset;
+    let page_count = (offset + len).div_ceil(PAGE_SIZE);
+    pat::check_mapping(
+        phys_start.phys_addr()..(phys_start + (page_count << PAGE_SHIFT)).phys_addr(),
+        mode,
+    );
+
+    let mut window = WINDOW.lock();
+    unsafe { window.install() };
+    let start = window.find_pages(page_count, phys_start)?;
+    let pages = start..start + page_count;
+    window.set_used(pages.clone(), true);
+    if !unsafe { window.map(pages.clone(), phys_start, mode) } {
+        unsafe { window.unmap(pages.clone()) };
+        window.set_used(pages, false);
+        return None;
+    }
+    Some(MmioRegion {
+        virt: (window_start() + (start << PAGE_SHIFT) + offset).virt_addr(),
+        phys,
+        len,
+    })
+}
+
+// unmaps the pages covering addr..addr + len, which have to be from one ioremap
+// for mappings that were leaked, MmioRegion's drop does this otherwise
+pub unsafe fn iounmap(addr: VirtAddr, len: usize) {
+    let offset = addr.usize().wrapping_sub(window_start());
+    assert!(
+        offset < WINDOW_PAGES << PAGE_SHIFT,
+        "{} isn't from ioremap",
+        addr
+    );
+    let start = offset >> PAGE_SHIFT;
+    let pages = start..(offset + len).div_ceil(PAGE_SIZE);
+
+    let mut window = WINDOW.lock();
+    window.unmap(pages.clone());
+    window.set_used(pages, false);
+}
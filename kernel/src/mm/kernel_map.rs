@@ -0,0 +1,304 @@
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use raw_cpuid::CpuId;
+
+use super::frame_alloc::{
+    alloc_frame_in, alloc_frame_with_order, free_frame, free_frame_with_order, FrameOrder,
+};
+use super::page_table::{
+    huge_pages_supported, table_virt, PTL1Entries, PTL2EntrySlot, PTL3EntrySlot,
+};
+use super::{tlb, vfree, vreserve};
+use crate::asm::{
+    read_cr0, read_cr3, read_cr4, read_msr, write_cr0, write_cr3, write_cr4, write_msr,
+};
+use crate::types::page::PAGE_SIZE;
+use crate::types::page_table::{
+    addr_to_pte_indices, CacheMode, Entry, Flags, LargePageFlags, PageFlags, PageTable,
+    SubtableFlags, ENTRY_COUNT, EXEC_DISABLE_FLAG, GLOBAL_FLAG, PTE_INDEX_SIZE, PT_FRAME_BITS,
+    WRITABLE_FLAG,
+};
+use crate::types::zeroable::zero_ptr;
+use crate::types::{HasPhysAddr, HasVirtAddr, ID_MAP_SIZE};
+
+// boot.asm maps the whole id-map RW and executable with 2MiB pages
+// this splits the 2MiB pages the kernel image is in, so each section gets its own permissions:
+// .text is RX, .rodata is R, and .data and .bss are RW
+// everything else in the id-map is just data, so it's RW and not executable
+
+extern "sysv64" {
+    // these are linker variables; their addresses matter, but they have no values
+    static HIGH_ID_MAP_VMA: u8;
+    static KERNEL_TEXT_START: u8;
+    static KERNEL_TEXT_END: u8;
+    static KERNEL_RODATA_START: u8;
+    static KERNEL_RODATA_END: u8;
+    static KERNEL_END_VMA: u8;
+}
+
+extern "sysv64" {
+    // boot.asm's tables: the l4 table, the l3 table for l4 entry 511,
+    // the l2 table for the id-map, and the l5 table with 5-level paging
+    pub static mut starting_page_tables: [PageTable; 4];
+}
+
+static NX_ENABLED: AtomicBool = AtomicBool::new(false);
+static GLOBAL_ENABLED: AtomicBool = AtomicBool::new(false);
+
+// for mapping data, the bit is reserved if the cpu doesn't support it
+pub fn no_exec_flag() -> usize {
+    if NX_ENABLED.load(Ordering::Relaxed) {
+        EXEC_DISABLE_FLAG
+    } else {
+        0
+    }
+}
+
+// for pages in the kernel's half, which is mapped the same in every address space
+// besides not being flushed on a switch, invlpg drops global pages from every pcid, not just the current one
+pub fn global_flag() -> usize {
+    if GLOBAL_ENABLED.load(Ordering::Relaxed) {
+        GLOBAL_FLAG
+    } else {
+        0
+    }
+}
+
+const EFER_MSR: u32 = 0xC000_0080;
+const EFER_NXE: u64 = 1 << 11;
+const CR0_WP: usize = 1 << 16;
+const CR4_PGE: usize = 1 << 7;
+
+const LARGE_PAGE_SIZE: usize = PAGE_SIZE << PTE_INDEX_SIZE;
+const LARGE_PAGE_ORDER: FrameOrder = FrameOrder(PTE_INDEX_SIZE as u8);
+const HUGE_PAGE_SIZE: usize = LARGE_PAGE_SIZE << PTE_INDEX_SIZE;
+const HUGE_PAGE_ORDER: FrameOrder = FrameOrder(2 * PTE_INDEX_SIZE as u8);
+
+// enough for a 30MiB kernel, which is a lot even for a debug build
+const MAX_IMAGE_TABLES: usize = 16;
+
+#[repr(C, align(4096))]
+struct Table([usize; ENTRY_COUNT]);
+
+static mut IMAGE_TABLES: [Table; MAX_IMAGE_TABLES] =
+    [const { Table([0; ENTRY_COUNT]) }; MAX_IMAGE_TABLES];
+
+fn page_flags(page: usize, nx: usize) -> usize {
+    // sections start page aligned, so no page has two sections in it
+    let text = addr_of!(KERNEL_TEXT_START) as usize..addr_of!(KERNEL_TEXT_END) as usize;
+    let rodata = addr_of!(KERNEL_RODATA_START) as usize..addr_of!(KERNEL_RODATA_END) as usize;
+    if text.contains(&page) {
+        0
+    } else if rodata.contains(&page) {
+        nx
+    } else {
+        WRITABLE_FLAG | nx
+    }
+}
+
+pub unsafe fn init() {
+    let cpuid = CpuId::new();
+    let has_nx = cpuid
+        .get_extended_processor_and_feature_identifiers()
+        .is_some_and(|f| f.has_execute_disable());
+    let has_pge = cpuid.get_feature_info().is_some_and(|f| f.has_pge());
+
+    // the exec disable bit is reserved until this is set
+    if has_nx {
+        write_msr(EFER_MSR, read_msr(EFER_MSR) | EFER_NXE);
+    }
+    NX_ENABLED.store(has_nx, Ordering::Relaxed);
+    let nx = no_exec_flag();
+    GLOBAL_ENABLED.store(has_pge, Ordering::Relaxed);
+    let global = global_flag();
+
+    let id_map = addr_of!(HIGH_ID_MAP_VMA) as usize;
+    let image = addr_of!(KERNEL_TEXT_START) as usize..addr_of!(KERNEL_END_VMA) as usize;
+    let ptl2: &mut PageTable = &mut *addr_of_mut!(starting_page_tables[2]);
+    let mut tables_used = 0;
+    for (i, entry) in ptl2.iter_mut().enumerate() {
+        let chunk = id_map + i * LARGE_PAGE_SIZE;
+        if chunk + LARGE_PAGE_SIZE <= image.start || image.end <= chunk {
+            let flags = LargePageFlags::from_usize(WRITABLE_FLAG | nx | global);
+            let frame = (i * LARGE_PAGE_SIZE).phys_addr().as_aligned();
+            entry.write(
+                Entry::at_frame(frame)
+                    .set_flags(flags)
+                    .set_cache_mode(CacheMode::WriteBack, true),
+            );
+            continue;
+        }
+
+        assert!(
+            tables_used < MAX_IMAGE_TABLES,
+            "kernel image is too big to remap"
+        );
+        let table = &mut *addr_of_mut!(IMAGE_TABLES[tables_used]);
+        tables_used += 1;
+
+        // fill it in before switching to it, the code doing this is in one of these pages
+        let chunk_addr = chunk.virt_addr().as_aligned();
+        let mut ptl1 = PTL1Entries::from_entries_addr(&mut table.0, chunk_addr);
+        for page in (chunk..chunk + LARGE_PAGE_SIZE).step_by(PAGE_SIZE) {
+            let flags = PageFlags::from_usize(page_flags(page, nx) | global);
+            let frame = page.virt_addr().to_phys().as_aligned();
+            ptl1.take_first().map_page(
+                Entry::at_frame(frame).set_flags(flags),
+                CacheMode::WriteBack,
+            );
+        }
+        // the permissions come from the pages, this has to allow everything
+        let table_frame = (table as *const Table).to_phys().as_aligned();
+        entry.write(
+            Entry::at_frame(table_frame).set_flags(SubtableFlags::from_usize(WRITABLE_FLAG)),
+        );
+    }
+
+    // global pages aren't turned on yet, so this flushes everything
+    write_cr3(read_cr3());
+    // make the kernel respect read-only pages too
+    write_cr0(read_cr0() | CR0_WP);
+    if has_pge {
+        write_cr4(read_cr4() | CR4_PGE);
+    }
+}
+
+// should fault now that .text is read-only
+pub fn check_text_is_read_only() {
+    let text = addr_of!(KERNEL_TEXT_START) as *mut u8;
+    assert!(
+        unsafe { crate::int::write_faults(text) },
+        "writing to .text didn't fault"
+    );
+    println!("writing to .text faults");
+}
+
+// splits one of the id-map's 2MiB pages into 4K pages and merges it back,
+// what's in it should read the same the whole time
+pub fn check_split_and_merge() {
+    let id_map = 0.phys_addr()..ID_MAP_SIZE.phys_addr();
+    let frame = alloc_frame_in(id_map.clone(), LARGE_PAGE_ORDER).unwrap();
+    let table_frame = alloc_frame_in(id_map, FrameOrder(0)).unwrap();
+    let chunk = frame.to_virt().usize();
+    // each 4K page gets its index written at the start
+    let pages = || {
+        (chunk..chunk + LARGE_PAGE_SIZE)
+            .step_by(PAGE_SIZE)
+            .enumerate()
+    };
+    let check_pages = || {
+        for (i, page) in pages() {
+            assert!(
+                unsafe { *(page as *const usize) } == i,
+                "{:#x} changed while splitting or merging",
+                page
+            );
+        }
+    };
+    for (i, page) in pages() {
+        unsafe { (page as *mut usize).write(i) };
+    }
+
+    let ptl2 = unsafe { addr_of_mut!(starting_page_tables[2]) } as *mut usize;
+    let slot = || unsafe {
+        let entry = &mut *ptl2.add((chunk / LARGE_PAGE_SIZE) % ENTRY_COUNT);
+        PTL2EntrySlot::from_entry_addr(entry, chunk.virt_addr().as_aligned())
+    };
+    unsafe {
+        let table = zero_ptr(table_frame.to_virt().ptr());
+        slot().split_large_page(table_frame, table);
+    }
+    check_pages();
+    let merged = unsafe { slot().merge_large_page() };
+    assert!(
+        merged == Some(table_frame),
+        "split 2MiB page didn't merge back"
+    );
+    check_pages();
+
+    free_frame(table_frame);
+    free_frame_with_order(frame, LARGE_PAGE_ORDER);
+    println!("2MiB pages split and merge");
+}
+
+// the same for a 1GiB page, split into 2MiB pages
+// the id-map is only 1GiB, so the page goes in a spot reserved from vmalloc instead,
+// and it's read and written through that mapping since the frame might not be in the id-map
+pub fn check_split_and_merge_huge() {
+    if !huge_pages_supported() {
+        println!("no 1GiB pages to split and merge");
+        return;
+    }
+    let Some(frame) = alloc_frame_with_order(HUGE_PAGE_ORDER) else {
+        println!("not enough memory to split and merge a 1GiB page");
+        return;
+    };
+    let table_frame =
+        alloc_frame_in(0.phys_addr()..ID_MAP_SIZE.phys_addr(), FrameOrder(0)).unwrap();
+    // the first 1GiB boundary after the start, so never vmalloc's first 1GiB, which init made tables for
+    let reserved = vreserve(2 * HUGE_PAGE_SIZE).unwrap();
+    let chunk = (reserved.usize() / HUGE_PAGE_SIZE + 1) * HUGE_PAGE_SIZE;
+
+    let (l4_idx, l3_idx, _, _) = addr_to_pte_indices(chunk.virt_addr().as_aligned());
+    let l3_entry = unsafe {
+        let l4 = addr_of_mut!(starting_page_tables[0]) as *mut usize;
+        let l3_table = (*l4.add(l4_idx) & PT_FRAME_BITS).phys_addr().as_aligned();
+        table_virt(l3_table).unwrap().add(l3_idx)
+    };
+    assert!(
+        unsafe { *l3_entry } == 0,
+        "vmalloc already has tables at {:#x}",
+        chunk
+    );
+    let slot = || unsafe {
+        PTL3EntrySlot::from_entry_addr(&mut *l3_entry, chunk.virt_addr().as_aligned())
+    };
+    unsafe {
+        let flags = LargePageFlags::from_usize(WRITABLE_FLAG | no_exec_flag());
+        slot().map_huge_page(
+            Entry::at_frame(frame).set_flags(flags),
+            CacheMode::WriteBack,
+        );
+    }
+
+    // each 2MiB page gets its index written at the start
+    let pages = || {
+        (chunk..chunk + HUGE_PAGE_SIZE)
+            .step_by(LARGE_PAGE_SIZE)
+            .enumerate()
+    };
+    let check_pages = || {
+        for (i, page) in pages() {
+            assert!(
+                unsafe { *(page as *const usize) } == i,
+                "{:#x} changed while splitting or merging",
+                page
+            );
+        }
+    };
+    for (i, page) in pages() {
+        unsafe { (page as *mut usize).write(i) };
+    }
+
+    unsafe {
+        let table = zero_ptr(table_frame.to_virt().ptr());
+        slot().split_huge_page(table_frame, table);
+    }
+    check_pages();
+    let merged = unsafe { slot().merge_huge_page() };
+    assert!(
+        merged == Some(table_frame),
+        "split 1GiB page didn't merge back"
+    );
+    check_pages();
+
+    unsafe {
+        *l3_entry = 0;
+        tlb::flush_all();
+        vfree(reserved);
+    }
+    free_frame(table_frame);
+    free_frame_with_order(frame, HUGE_PAGE_ORDER);
+    println!("1GiB pages split and merge");
+}
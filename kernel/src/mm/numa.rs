@@ -0,0 +1,233 @@
+use core::mem::MaybeUninit;
+use core::ops::Range;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use acpi::AcpiTables;
+
+use crate::acpi::{IoremapMapper, Slit, Srat, SratEntry};
+use crate::cpu::MAX_CPUS;
+use crate::types::{HasPhysAddr, PhysAddr};
+
+// which memory is close to which CPUs, from the ACPI SRAT and SLIT
+// without them, everything is one node
+
+pub const MAX_NODES: usize = 8;
+const MAX_MEMORY_RANGES: usize = 32;
+
+const LOCAL_DISTANCE: u8 = 10;
+const REMOTE_DISTANCE: u8 = 20;
+
+// nodes are numbered densely in the order the SRAT mentions them,
+// the proximity domain numbers themselves can be anything
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct NodeId(pub u8);
+
+#[derive(Clone, Copy)]
+struct MemoryAffinity {
+    start: PhysAddr,
+    end: PhysAddr,
+    node: NodeId,
+}
+
+#[derive(Clone)]
+pub struct Topology {
+    domains: [u32; MAX_NODES],
+    node_count: usize,
+    memory: [Option<MemoryAffinity>; MAX_MEMORY_RANGES],
+    cpus: [Option<NodeId>; MAX_CPUS],
+    distances: [[u8; MAX_NODES]; MAX_NODES],
+    // every node, nearest first, for each node
+    fallback: [[NodeId; MAX_NODES]; MAX_NODES],
+}
+
+impl Topology {
+    const fn uniform() -> Self {
+        Self {
+            domains: [0; MAX_NODES],
+            node_count: 1,
+            memory: [None; MAX_MEMORY_RANGES],
+            cpus: [None; MAX_CPUS],
+            distances: [[LOCAL_DISTANCE; MAX_NODES]; MAX_NODES],
+            fallback: [[NodeId(0); MAX_NODES]; MAX_NODES],
+        }
+    }
+
+    fn node_for_domain(&mut self, domain: u32) -> Option<NodeId> {
+        let count = self.node_count;
+        if let Some(idx) = self.domains[..count].iter().position(|&d| d == domain) {
+            return Some(NodeId(idx as u8));
+        }
+        if count == MAX_NODES {
+            println!("too many NUMA nodes, ignoring proximity domain {}", domain);
+            return None;
+        }
+        self.domains[count] = domain;
+        self.node_count += 1;
+        Some(NodeId(count as u8))
+    }
+
+    fn add_memory(&mut self, range: Range<PhysAddr>, node: NodeId) {
+        let Some(slot) = self.memory.iter_mut().find(|m| m.is_none()) else {
+            println!("too many NUMA memory ranges, ignoring {:?}", range);
+            return;
+        };
+        *slot = Some(MemoryAffinity {
+            start: range.start,
+            end: range.end,
+            node,
+        });
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = NodeId> {
+        (0..self.node_count as u8).map(NodeId)
+    }
+
+    // with no SRAT, node 0 has everything
+    pub fn node_memory(&self, node: NodeId) -> impl Iterator<Item = Range<PhysAddr>> + '_ {
+        let all = (self.memory[0].is_none() && node == NodeId(0))
+            .then(|| 0.phys_addr()..usize::MAX.phys_addr());
+        let ranges = self
+            .memory
+            .iter()
+            .flatten()
+            .filter(move |m| m.node == node)
+            .map(|m| m.start..m.end);
+        all.into_iter().chain(ranges)
+    }
+
+    pub fn node_of(&self, addr: PhysAddr) -> NodeId {
+        self.memory
+            .iter()
+            .flatten()
+            .find(|m| m.start <= addr && addr < m.end)
+            .map_or(NodeId(0), |m| m.node)
+    }
+
+    pub fn cpu_node(&self, apic_id: u32) -> NodeId {
+        self.cpus
+            .get(apic_id as usize)
+            .copied()
+            .flatten()
+            .unwrap_or(NodeId(0))
+    }
+
+    pub fn distance(&self, from: NodeId, to: NodeId) -> u8 {
+        self.distances[from.0 as usize][to.0 as usize]
+    }
+
+    // every node, nearest first
+    pub fn fallback_order(&self, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        self.fallback[node.0 as usize][..self.node_count]
+            .iter()
+            .copied()
+    }
+
+    fn compute_fallback_orders(&mut self) {
+        for node in self.nodes() {
+            let mut order = [NodeId(0); MAX_NODES];
+            for (i, n) in self.nodes().enumerate() {
+                order[i] = n;
+            }
+            // the node itself goes first even if the SLIT is weird
+            order[..self.node_count]
+                .sort_unstable_by_key(|&n| (n != node, self.distance(node, n), n.0));
+            self.fallback[node.0 as usize] = order;
+        }
+    }
+}
+
+// written once by init, before any other CPUs are started, and only read after that
+static UNIFORM: Topology = Topology::uniform();
+static mut PARSED: MaybeUninit<Topology> = MaybeUninit::uninit();
+static TOPOLOGY: AtomicPtr<Topology> = AtomicPtr::new(addr_of!(UNIFORM) as *mut Topology);
+
+pub fn init(tables: &AcpiTables<IoremapMapper>) {
+    let Ok(srat) = tables.find_table::<Srat>() else {
+        println!("no SRAT, treating memory as uniform");
+        return;
+    };
+
+    let mut topology = Topology::uniform();
+    topology.node_count = 0;
+    for entry in srat.entries() {
+        match entry {
+            SratEntry::Cpu { apic_id, domain } => {
+                let Some(node) = topology.node_for_domain(domain) else {
+                    continue;
+                };
+                if let Some(cpu) = topology.cpus.get_mut(apic_id as usize) {
+                    *cpu = Some(node);
+                }
+            }
+            SratEntry::Memory { range, domain } => {
+                let Some(node) = topology.node_for_domain(domain) else {
+                    continue;
+                };
+                topology.add_memory(range, node);
+            }
+            SratEntry::Other => {}
+        }
+    }
+    if topology.node_count == 0 {
+        println!("SRAT has no nodes, treating memory as uniform");
+        return;
+    }
+
+    let slit = tables.find_table::<Slit>().ok();
+    for from in topology.nodes() {
+        for to in topology.nodes() {
+            let (from_domain, to_domain) = (
+                topology.domains[from.0 as usize],
+                topology.domains[to.0 as usize],
+            );
+            let default = if from == to {
+                LOCAL_DISTANCE
+            } else {
+                REMOTE_DISTANCE
+            };
+            let distance = slit
+                .as_ref()
+                .and_then(|slit| slit.distance(from_domain, to_domain))
+                .unwrap_or(default);
+            topology.distances[from.0 as usize][to.0 as usize] = distance;
+        }
+    }
+
+    for node in topology.nodes() {
+        println!(
+            "NUMA node {} (proximity domain {}):",
+            node.0, topology.domains[node.0 as usize]
+        );
+        for range in topology.node_memory(node) {
+            println!("  memory {:?}", range);
+        }
+    }
+
+    topology.compute_fallback_orders();
+    unsafe {
+        let parsed = (*addr_of_mut!(PARSED)).write(topology);
+        TOPOLOGY.store(parsed, Ordering::Release);
+    }
+    crate::cpu::refresh_node();
+}
+
+pub fn topology() -> &'static Topology {
+    unsafe { &*TOPOLOGY.load(Ordering::Acquire) }
+}
+
+pub fn current_node() -> NodeId {
+    crate::cpu::node()
+}
+
+pub fn node_of(addr: PhysAddr) -> NodeId {
+    topology().node_of(addr)
+}
+
+pub fn fallback_order(node: NodeId) -> impl Iterator<Item = NodeId> {
+    topology().fallback_order(node)
+}
@@ -1,19 +1,180 @@
-use core::ptr::addr_of;
+use core::ptr::{addr_of, addr_of_mut, null_mut};
 
-use super::alloc_frame;
-use super::page_table::{PTL1Entries, PTL1EntrySlot, PTL2Entries, PTL3Entries, PTL1, PTL2};
+use super::kernel_map::{global_flag, no_exec_flag, starting_page_tables};
+use super::page_table::{
+    table_virt, EntryValue, PTL1Entries, PTL1EntrySlot, PTL2Entries, PTL2EntrySlot, PTL3Entries,
+    PTL1, PTL2,
+};
+use super::{alloc_frame, frame_meta, free_frame, tlb, Frame, FrameFlags};
+use crate::asm::invlpg;
 use crate::sync::SpinLock;
+use crate::types::page::PAGE_SHIFT;
 use crate::types::zeroable::zero_ptr;
 use crate::types::{
-    page_table::{pte_indices_to_addr, Entry},
+    page_table::{
+        addr_to_pte_indices, pte_indices_to_addr, CacheMode, Entry, Flags, PageFlags, ENTRY_COUNT,
+        PRESENT_FLAG, PTE_INDEX_SIZE, WRITABLE_FLAG,
+    },
     zeroable, FrameAddr, HasPhysAddr, HasVirtAddr, PageAddr,
 };
 
+// freed slots are kept in doubly linked lists threaded through the slots' own (non-present) entries,
+// so all the slots in a table can be unlinked when the table gets reclaimed
+// a free entry is FREE_FLAG | (prev + 1) << PREV_SHIFT | (next + 1) << NEXT_SHIFT,
+// with slots numbered from the start of the allocator's region and 0 meaning none
+const FREE_FLAG: usize = 1 << 1;
+// 511GiB of 4K pages is less than 2^27 slots
+const LINK_BITS: usize = 28;
+const LINK_MASK: usize = (1 << LINK_BITS) - 1;
+const PREV_SHIFT: usize = 2;
+const NEXT_SHIFT: usize = PREV_SHIFT + LINK_BITS;
+
+fn free_entry(prev: Option<usize>, next: Option<usize>) -> usize {
+    let link = |slot: Option<usize>| slot.map_or(0, |s| s + 1);
+    FREE_FLAG | link(prev) << PREV_SHIFT | link(next) << NEXT_SHIFT
+}
+
+fn is_free_entry(entry: usize) -> bool {
+    entry & (PRESENT_FLAG | FREE_FLAG) == FREE_FLAG
+}
+
+fn free_entry_links(entry: usize) -> (Option<usize>, Option<usize>) {
+    assert!(
+        is_free_entry(entry),
+        "page allocator free list is corrupted"
+    );
+    let link = |bits: usize| (bits & LINK_MASK).checked_sub(1);
+    (link(entry >> PREV_SHIFT), link(entry >> NEXT_SHIFT))
+}
+
+// the tables are all mapped somewhere in the allocator's own region,
+// and that address is recorded in the table frame's metadata when it's made
+fn table_at(entry: usize) -> *mut usize {
+    let EntryValue::Entry(entry) = EntryValue::from_usize(entry) else {
+        panic!("page allocator table isn't mapped");
+    };
+    table_virt(entry.frame()).unwrap()
+}
+
+fn record_table(virt: PageAddr, frame: FrameAddr) {
+    let meta = frame_meta(frame);
+    meta.set_flags(FrameFlags::PAGE_TABLE);
+    meta.set_owner(virt.ptr());
+}
+
+fn data_entry(frame: FrameAddr) -> Entry {
+    let flags = PageFlags::from_usize(WRITABLE_FLAG | no_exec_flag() | global_flag());
+    Entry::at_frame(frame).set_flags(flags)
+}
+
+// the entry that maps addr, in its l1 table (level 1) or its l2 table (level 2)
+unsafe fn entry_ptr(l3_table: *mut usize, addr: usize, level: usize) -> *mut usize {
+    let (_, l3_idx, l2_idx, l1_idx) = addr_to_pte_indices(addr.virt_addr().as_aligned());
+    let l2 = table_at(*l3_table.add(l3_idx));
+    if level == 2 {
+        return l2.add(l2_idx);
+    }
+    let l1 = table_at(*l2.add(l2_idx));
+    l1.add(l1_idx)
+}
+
+// the metadata of the table with the entry for addr, in an l1 table (level 1) or an l2 table (level 2)
+// it counts the table's slots that are handed out, since those can still be empty
+unsafe fn table_meta(l3_table: *mut usize, addr: usize, level: usize) -> &'static Frame {
+    let parent = if level == 1 {
+        entry_ptr(l3_table, addr, 2)
+    } else {
+        let (_, l3_idx, _, _) = addr_to_pte_indices(addr.virt_addr().as_aligned());
+        l3_table.add(l3_idx)
+    };
+    let EntryValue::Entry(entry) = EntryValue::from_usize(*parent) else {
+        panic!("page allocator table isn't mapped");
+    };
+    frame_meta(entry.frame())
+}
+
+struct FreeSlots {
+    // 1 for page slots (l1 entries), 2 for l1 table slots (l2 entries)
+    level: usize,
+    region_start: usize,
+    head: Option<usize>,
+}
+
+impl FreeSlots {
+    fn new(level: usize, region_start: usize) -> Self {
+        Self {
+            level,
+            region_start,
+            head: None,
+        }
+    }
+
+    fn shift(&self) -> usize {
+        PAGE_SHIFT + PTE_INDEX_SIZE * (self.level - 1)
+    }
+
+    fn slot_addr(&self, slot: usize) -> usize {
+        self.region_start + (slot << self.shift())
+    }
+
+    fn slot_of(&self, addr: usize) -> usize {
+        (addr - self.region_start) >> self.shift()
+    }
+
+    unsafe fn entry(&self, l3_table: *mut usize, slot: usize) -> &'static mut usize {
+        &mut *entry_ptr(l3_table, self.slot_addr(slot), self.level)
+    }
+
+    unsafe fn push(&mut self, l3_table: *mut usize, slot: usize) {
+        if let Some(head) = self.head {
+            let head_entry = self.entry(l3_table, head);
+            let (_, next) = free_entry_links(*head_entry);
+            *head_entry = free_entry(Some(slot), next);
+        }
+        *self.entry(l3_table, slot) = free_entry(None, self.head);
+        self.head = Some(slot);
+    }
+
+    // leaves the slot's entry empty
+    unsafe fn remove(&mut self, l3_table: *mut usize, slot: usize) {
+        let entry = self.entry(l3_table, slot);
+        let (prev, next) = free_entry_links(*entry);
+        *entry = 0;
+        match prev {
+            Some(prev) => {
+                let prev_entry = self.entry(l3_table, prev);
+                let (prev_prev, _) = free_entry_links(*prev_entry);
+                *prev_entry = free_entry(prev_prev, next);
+            }
+            None => self.head = next,
+        }
+        if let Some(next) = next {
+            let next_entry = self.entry(l3_table, next);
+            let (_, next_next) = free_entry_links(*next_entry);
+            *next_entry = free_entry(prev, next_next);
+        }
+    }
+
+    unsafe fn pop(&mut self, l3_table: *mut usize) -> Option<usize> {
+        let slot = self.head?;
+        self.remove(l3_table, slot);
+        Some(slot)
+    }
+}
+
 struct PageAllocator {
+    // the whole l3 table, for finding entries again when they're freed
+    l3_table: *mut usize,
     l3: PTL3Entries<'static>,
     curr_l2: PTL2Entries<'static>,
     curr_l1: PTL1Entries<'static>,
+    // virtual addresses of the tables curr_l2 and curr_l1 are in, these don't get reclaimed
+    curr_l2_table: usize,
+    curr_l1_table: usize,
+    region_start: usize,
     allocation_start: PageAddr,
+    free_pages: FreeSlots,
+    free_l1_tables: FreeSlots,
 }
 
 impl PageAllocator {
@@ -24,6 +185,13 @@ impl PageAllocator {
         init_l1: &'static mut PTL1,
         init_l1_phys: FrameAddr,
     ) -> Self {
+        let l3_table = l3.table_ptr() as *mut usize;
+        let region_start = l3.addr().usize();
+        let curr_l2_table = (init_l2 as *const PTL2).virt_addr().usize();
+        let curr_l1_table = (init_l1 as *const PTL1).virt_addr().usize();
+        record_table(curr_l2_table.virt_addr().as_aligned(), init_l2_phys);
+        record_table(curr_l1_table.virt_addr().as_aligned(), init_l1_phys);
+
         let mut init_l2 = unsafe {
             l3.take_first()
                 .map_subtable(Entry::at_frame(init_l2_phys), init_l2)
@@ -33,32 +201,62 @@ impl PageAllocator {
                 .take_first()
                 .map_subtable(Entry::at_frame(init_l1_phys), init_l1)
         };
+        frame_meta(init_l2_phys).inc_used_entries();
         Self {
+            l3_table,
             l3: l3,
             allocation_start: init_l1.addr().as_aligned(),
             curr_l2: init_l2,
             curr_l1: init_l1,
+            curr_l2_table,
+            curr_l1_table,
+            region_start,
+            free_pages: FreeSlots::new(1, region_start),
+            free_l1_tables: FreeSlots::new(2, region_start),
         }
     }
 
     fn map_page(&mut self) -> (PageAddr, FrameAddr) {
         let frame = alloc_frame().unwrap();
-        let l1_entry = self.curr_l1.take_first();
-        let entry_value = Entry::at_frame(frame);
-        (unsafe { l1_entry.map_page(entry_value) }, frame)
+        let l1_entry = self.take_untouched_slot();
+        (
+            unsafe { l1_entry.map_page(data_entry(frame), CacheMode::WriteBack) },
+            frame,
+        )
     }
 
-    fn alloc_l1_entry(&mut self) -> PTL1EntrySlot<'static> {
-        self.maybe_replenish();
+    fn take_untouched_slot(&mut self) -> PTL1EntrySlot<'static> {
+        unsafe { table_meta(self.l3_table, self.curr_l1.addr().usize(), 1).inc_used_entries() };
         self.curr_l1.take_first()
     }
 
-    fn alloc_page(&mut self) -> PageAddr {
+    // freed slots get reused before the untouched ones
+    fn take_slot(&mut self) -> PTL1EntrySlot<'static> {
+        if let Some(slot) = unsafe { self.free_pages.pop(self.l3_table) } {
+            let addr = self.free_pages.slot_addr(slot);
+            unsafe {
+                table_meta(self.l3_table, addr, 1).inc_used_entries();
+                let entry = self.free_pages.entry(self.l3_table, slot);
+                return PTL1EntrySlot::from_entry_addr(entry, addr.virt_addr().as_aligned());
+            }
+        }
         self.maybe_replenish();
-        self.map_page().0
+        self.take_untouched_slot()
+    }
+
+    fn alloc_l1_entry(&mut self) -> PTL1EntrySlot<'static> {
+        self.take_slot()
+    }
+
+    fn alloc_page(&mut self) -> PageAddr {
+        let slot = self.take_slot();
+        let frame = alloc_frame().unwrap();
+        unsafe { slot.map_page(data_entry(frame), CacheMode::WriteBack) }
     }
 
     fn maybe_replenish(&mut self) {
+        // a new l2 gets mapped even if there's free l1 table slots,
+        // so there's always somewhere to put the l1 table once the last entry is used for its page
         if self.curr_l2.len() == 0 && self.curr_l1.len() == 2 {
             self.map_new_l2();
         }
@@ -70,17 +268,137 @@ impl PageAllocator {
     fn map_new_l2(&mut self) {
         let l3_entry = self.l3.take_first();
         let (ptl2_virt, ptl2_phys) = self.map_page();
+        record_table(ptl2_virt, ptl2_phys);
         let new_l2 =
             unsafe { l3_entry.map_subtable(Entry::at_frame(ptl2_phys), zero_ptr(ptl2_virt.ptr())) };
         self.curr_l2 = new_l2;
+        self.curr_l2_table = ptl2_virt.usize();
     }
 
     fn map_new_l1(&mut self) {
-        let l2_entry = self.curr_l2.take_first();
+        let (l2_entry, addr) = match unsafe { self.free_l1_tables.pop(self.l3_table) } {
+            Some(slot) => unsafe {
+                let addr = self.free_l1_tables.slot_addr(slot);
+                let entry = self.free_l1_tables.entry(self.l3_table, slot);
+                let entry = PTL2EntrySlot::from_entry_addr(entry, addr.virt_addr().as_aligned());
+                (entry, addr)
+            },
+            None => {
+                let addr = self.curr_l2.addr().usize();
+                (self.curr_l2.take_first(), addr)
+            }
+        };
+        unsafe { table_meta(self.l3_table, addr, 2).inc_used_entries() };
         let (ptl1_virt, ptl1_phys) = self.map_page();
+        record_table(ptl1_virt, ptl1_phys);
         let new_l1 =
             unsafe { l2_entry.map_subtable(Entry::at_frame(ptl1_phys), zero_ptr(ptl1_virt.ptr())) };
         self.curr_l1 = new_l1;
+        self.curr_l1_table = ptl1_virt.usize();
+    }
+
+    fn in_region(&self, addr: usize) -> bool {
+        // l3 entry 510 is ioremap's window and 511 is the id-map, the kernel image is in there too
+        let region_end = self.region_start + (ENTRY_COUNT - 2) * (1 << 30);
+        (self.region_start..region_end).contains(&addr)
+    }
+
+    // the l1 table that has the entry for addr, if it's still there
+    unsafe fn l1_table_of(&self, addr: usize) -> Option<*mut usize> {
+        let (_, l3_idx, _, _) = addr_to_pte_indices(addr.virt_addr().as_aligned());
+        if *self.l3_table.add(l3_idx) & PRESENT_FLAG == 0 {
+            return None;
+        }
+        let l2_entry = *entry_ptr(self.l3_table, addr, 2);
+        (l2_entry & PRESENT_FLAG != 0).then(|| table_at(l2_entry))
+    }
+
+    // the tables the allocator started with are in the kernel image, not the region
+    fn can_reclaim(&self, table: *mut usize) -> bool {
+        let table = table as usize;
+        self.in_region(table) && table != self.curr_l1_table && table != self.curr_l2_table
+    }
+
+    // unmaps whatever is in the slot and puts it on the free list
+    // returns the frame that was mapped there
+    unsafe fn release_slot(&mut self, page: PageAddr) -> Option<FrameAddr> {
+        assert!(
+            self.in_region(page.usize()),
+            "{} isn't from the page allocator",
+            page
+        );
+        let entry = &mut *entry_ptr(self.l3_table, page.usize(), 1);
+        assert!(!is_free_entry(*entry), "page {} freed twice", page);
+        let frame = match EntryValue::from_usize(*entry) {
+            EntryValue::Entry(e) => Some(e.frame()),
+            EntryValue::Raw(_) => None,
+        };
+        *entry = 0;
+        invlpg(page.usize());
+        table_meta(self.l3_table, page.usize(), 1).dec_used_entries();
+        let slot = self.free_pages.slot_of(page.usize());
+        self.free_pages.push(self.l3_table, slot);
+        self.maybe_reclaim_l1(page.usize());
+        frame
+    }
+
+    unsafe fn free_table(&mut self, table: *mut usize) {
+        let frame = self.release_slot(table.virt_addr().as_aligned()).unwrap();
+        let meta = frame_meta(frame);
+        meta.set_owner(null_mut());
+        meta.clear_flags(FrameFlags::PAGE_TABLE);
+        free_frame(frame);
+    }
+
+    // an l1 table with every slot free gets unmapped and freed, and its l2 entry goes on the free list
+    unsafe fn maybe_reclaim_l1(&mut self, addr: usize) {
+        let l2_entry = &mut *entry_ptr(self.l3_table, addr, 2);
+        let table = table_at(*l2_entry);
+        if !self.can_reclaim(table) || table_meta(self.l3_table, addr, 1).used_entries() != 0 {
+            return;
+        }
+
+        let l1_slot = self.free_l1_tables.slot_of(addr);
+        let first_page_slot = l1_slot * ENTRY_COUNT;
+        for i in 0..ENTRY_COUNT {
+            if is_free_entry(*table.add(i)) {
+                self.free_pages.remove(self.l3_table, first_page_slot + i);
+            }
+        }
+        *l2_entry = 0;
+        // every pcid can have the table cached, and invlpg only drops the current one's
+        tlb::flush_global();
+        table_meta(self.l3_table, addr, 2).dec_used_entries();
+        self.free_l1_tables.push(self.l3_table, l1_slot);
+
+        self.free_table(table);
+        self.maybe_reclaim_l2(addr);
+    }
+
+    // same for l2 tables, except the l3 entry isn't reused
+    // there's 511 of them and each one is 1GiB, so it won't run out
+    unsafe fn maybe_reclaim_l2(&mut self, addr: usize) {
+        let (_, l3_idx, _, _) = addr_to_pte_indices(addr.virt_addr().as_aligned());
+        let l3_entry = &mut *self.l3_table.add(l3_idx);
+        // freeing the l1 table's page might have already reclaimed it
+        if *l3_entry & PRESENT_FLAG == 0 {
+            return;
+        }
+        let table = table_at(*l3_entry);
+        if !self.can_reclaim(table) || table_meta(self.l3_table, addr, 2).used_entries() != 0 {
+            return;
+        }
+
+        let first_l1_slot = l3_idx * ENTRY_COUNT;
+        for i in 0..ENTRY_COUNT {
+            if is_free_entry(*table.add(i)) {
+                self.free_l1_tables.remove(self.l3_table, first_l1_slot + i);
+            }
+        }
+        *l3_entry = 0;
+        tlb::flush_global();
+
+        self.free_table(table);
     }
 }
 
@@ -94,19 +412,34 @@ pub fn alloc_page() -> Option<PageAddr> {
     Some(PAGE_ALLOC.lock().as_mut().unwrap().alloc_page())
 }
 
-pub unsafe fn get_l3_entries() -> PTL3Entries<'static> {
+// unmaps a page from alloc_page and frees its frame
+pub unsafe fn free_page(page: PageAddr) {
+    let frame = PAGE_ALLOC.lock().as_mut().unwrap().release_slot(page);
+    free_frame(frame.expect("freed a page that wasn't mapped"));
+}
+
+// gives back an entry from alloc_l1_entry
+// returns the frame that was mapped in it, if there was one, for the caller to deal with
+pub unsafe fn free_l1_entry(page: PageAddr) -> Option<FrameAddr> {
+    PAGE_ALLOC.lock().as_mut().unwrap().release_slot(page)
+}
+
+unsafe fn get_l3_entries() -> PTL3Entries<'static> {
     let allocator_start_addr = pte_indices_to_addr(511, 0, 0, 0); // 511th entry of the l4 page table, then 0th entry of the l3-l1 tables
     println!("mm virt s {}", allocator_start_addr);
 
-    let l3_entries: &'static mut [usize; 511] = unsafe { &mut *(0x2000usize.to_virt()).ptr() };
+    // the boot l3 table, which has ioremap's window and the id-map in its last 2 entries
+    let l3_entries: &'static mut [usize; 510] =
+        unsafe { &mut *addr_of_mut!(starting_page_tables[1]).cast() };
     PTL3Entries::from_entries_addr(l3_entries, allocator_start_addr.as_aligned())
 }
 
+// the tables get recorded in the frame metadata, so this has to be after frame_alloc::init
 pub unsafe fn init() {
     let l3_entries = get_l3_entries();
 
     let l2_table_frame_addr = addr_of!(INIT_PTL2).to_phys().as_aligned();
-    let l1_table_frame_addr = addr_of!(INIT_PTL2).to_phys().as_aligned();
+    let l1_table_frame_addr = addr_of!(INIT_PTL1).to_phys().as_aligned();
 
     // PAGE_ALLOC needs a couple page tables to start with
     static mut INIT_PTL2: PTL2 = zeroable::zeroed();
@@ -122,3 +455,55 @@ pub unsafe fn init() {
 
     *PAGE_ALLOC.lock() = Some(page_alloc);
 }
+
+// allocates a chain of pages, each holding the address of the one before it
+fn alloc_chain(count: usize) -> usize {
+    let mut head = 0;
+    for _ in 0..count {
+        let page = alloc_page().unwrap();
+        unsafe { page.ptr::<usize>().write(head) };
+        head = page.usize();
+    }
+    head
+}
+
+unsafe fn free_chain(mut head: usize) {
+    while head != 0 {
+        let next = *(head as *const usize);
+        free_page(head.virt_addr().as_aligned());
+        head = next;
+    }
+}
+
+// freed slots should be handed out again, and an l1 table should go away once it's empty
+// enough pages are allocated that at least one full l1 table isn't the current one
+pub fn check_reuse_and_reclaim() {
+    let head = alloc_chain(3 * ENTRY_COUNT);
+
+    let page = alloc_page().unwrap();
+    unsafe { free_page(page) };
+    let again = alloc_page().unwrap();
+    assert!(page == again, "freed page slot wasn't reused");
+    unsafe { free_page(again) };
+
+    let watched = {
+        let page_alloc = PAGE_ALLOC.lock();
+        let page_alloc = page_alloc.as_ref().unwrap();
+        let mut page = head;
+        loop {
+            assert!(page != 0, "no reclaimable l1 table to watch");
+            let table = unsafe { page_alloc.l1_table_of(page) }.unwrap();
+            if page_alloc.can_reclaim(table) {
+                break page;
+            }
+            page = unsafe { *(page as *const usize) };
+        }
+    };
+    unsafe { free_chain(head) };
+    let page_alloc = PAGE_ALLOC.lock();
+    assert!(
+        unsafe { page_alloc.as_ref().unwrap().l1_table_of(watched) }.is_none(),
+        "emptied l1 table wasn't reclaimed"
+    );
+    println!("page allocator reuses slots and reclaims empty tables");
+}
@@ -1,7 +1,16 @@
+mod dump;
 mod entries;
 mod tables;
 mod values;
+mod walk;
 
-pub use entries::{EntrySlot, PTL1EntrySlot, PTL2EntrySlot, PTL3EntrySlot, PTL4EntrySlot};
-pub use tables::{PTL1Entries, PTL2Entries, PTL3Entries, PTL4Entries, PTL1, PTL2, PTL3, PTL4};
+pub use dump::{check_snapshot_diff, diff, dump, dump_current, regions, Region, Snapshot};
+pub use entries::{
+    detect_huge_pages, huge_pages_supported, EntrySlot, PTL1EntrySlot, PTL2EntrySlot,
+    PTL3EntrySlot, PTL4EntrySlot, PTL5EntrySlot,
+};
+pub use tables::{
+    PTL1Entries, PTL2Entries, PTL3Entries, PTL4Entries, PTL5Entries, PTL1, PTL2, PTL3, PTL4, PTL5,
+};
 pub use values::{EntryValue, NonPresentUsize};
+pub use walk::{table_virt, Mapping, MappingFlags, Mappings, PageSize, Walker};
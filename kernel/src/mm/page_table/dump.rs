@@ -0,0 +1,237 @@
+use core::fmt::{self, Display, Write};
+use core::iter::Peekable;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::walk::{MappingFlags, PageSize, Walker};
+use crate::types::page_table::CacheMode;
+use crate::types::{HasPhysAddr, HasVirtAddr};
+
+// printing page tables for debugging
+// nothing here allocates or takes locks (except taking a snapshot and the boot check), so it works from the panic handler
+
+// a run of mappings with contiguous virtual and physical addresses and the same page size and flags
+// the id-map goes right up to the top of the address space, so this is a start and length, not a range
+#[derive(Clone, PartialEq, Eq)]
+pub struct Region {
+    pub virt: usize,
+    pub len: usize,
+    pub phys: usize,
+    pub page_size: PageSize,
+    pub flags: MappingFlags,
+}
+
+impl Region {
+    fn last(&self) -> usize {
+        self.virt + (self.len - 1)
+    }
+
+    // same mapping, ignoring where the region starts and ends
+    fn same_mapping(&self, other: &Region) -> bool {
+        self.phys.wrapping_sub(self.virt) == other.phys.wrapping_sub(other.virt)
+            && self.page_size == other.page_size
+            && self.flags == other.flags
+    }
+
+    // the part of the region from start to last, inclusive
+    fn clamp(&self, start: usize, last: usize) -> Region {
+        Region {
+            virt: start,
+            len: last - start + 1,
+            phys: self.phys + (start - self.virt),
+            page_size: self.page_size,
+            flags: self.flags,
+        }
+    }
+}
+
+pub struct Regions<I: Iterator<Item = Region>> {
+    mappings: Peekable<I>,
+}
+
+impl<I: Iterator<Item = Region>> Iterator for Regions<I> {
+    type Item = Region;
+
+    fn next(&mut self) -> Option<Region> {
+        let mut region = self.mappings.next()?;
+        while let Some(next) = self.mappings.next_if(|next| {
+            region.virt.wrapping_add(region.len) == next.virt
+                && region.phys + region.len == next.phys
+                && region.page_size == next.page_size
+                && region.flags == next.flags
+        }) {
+            region.len += next.len;
+        }
+        Some(region)
+    }
+}
+
+pub fn regions(walker: Walker) -> Regions<impl Iterator<Item = Region>> {
+    let mappings = walker.all_mappings().map(|m| Region {
+        virt: m.virt.usize(),
+        len: m.size.bytes(),
+        phys: m.phys.usize(),
+        page_size: m.size,
+        flags: m.flags,
+    });
+    Regions {
+        mappings: mappings.peekable(),
+    }
+}
+
+// the regions of an address space at one point in time, to diff against later
+pub struct Snapshot(Vec<Region>);
+
+impl Snapshot {
+    pub fn take(walker: Walker) -> Self {
+        // big vecs come from vmalloc, so growing this partway through the walk would change what's being walked
+        // twice the regions leaves room for the ones holding the vec itself
+        let mut snapshot = Vec::with_capacity(regions(walker).count() * 2);
+        snapshot.extend(regions(walker));
+        Self(snapshot)
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = Region> + '_ {
+        self.0.iter().cloned()
+    }
+}
+
+impl Display for MappingFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            if self.writable { "RW" } else { "R-" },
+            if self.no_exec { "NX" } else { "X-" },
+            if self.user { "US" } else { "K-" },
+            if self.global { "G" } else { "-" },
+            CacheMode::from_pat_index(self.pat_index as usize),
+        )
+    }
+}
+
+struct Size(usize);
+
+impl Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (shift, unit) in [(30, "G"), (20, "M"), (10, "K")] {
+            if self.0 >= 1 << shift && self.0 % (1 << shift) == 0 {
+                return write!(f, "{}{}", self.0 >> shift, unit);
+            }
+        }
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:016x}-{:016x} -> {:013x} {:>5} in {:>2} pages  {}",
+            self.virt,
+            self.last(),
+            self.phys,
+            Size(self.len),
+            Size(self.page_size.bytes()),
+            self.flags
+        )
+    }
+}
+
+pub fn dump(out: &mut impl Write, walker: Walker) -> fmt::Result {
+    for region in regions(walker) {
+        writeln!(out, "{}", region)?;
+    }
+    Ok(())
+}
+
+// the address space cr3 is currently pointing at
+pub fn dump_current(out: &mut impl Write) -> fmt::Result {
+    let cr3 = unsafe { crate::asm::read_cr3() };
+    writeln!(out, "page tables at {}", cr3.phys_addr())?;
+    dump(out, unsafe { Walker::from_cr3(cr3) })
+}
+
+// prints what's only in old with a -, what's only in new with a +,
+// and what's mapped in both but differently as a - and + pair
+// either side can be a snapshot or regions(walker) for the live tables
+pub fn diff(
+    out: &mut impl Write,
+    old: impl Iterator<Item = Region>,
+    new: impl Iterator<Item = Region>,
+) -> fmt::Result {
+    let mut old = old.peekable();
+    let mut new = new.peekable();
+    // None once the top of the address space has been reached
+    let mut addr = Some(0);
+    while let Some(at) = addr {
+        while old.next_if(|r| r.last() < at).is_some() {}
+        while new.next_if(|r| r.last() < at).is_some() {}
+        let (o, n) = (old.peek().cloned(), new.peek().cloned());
+
+        // the next piece where neither side changes which region covers it
+        let start = match (&o, &n) {
+            (None, None) => break,
+            (Some(o), None) => o.virt.max(at),
+            (None, Some(n)) => n.virt.max(at),
+            (Some(o), Some(n)) => o.virt.min(n.virt).max(at),
+        };
+        let mut last = usize::MAX;
+        for r in [&o, &n].into_iter().flatten() {
+            last = last.min(if r.virt > start { r.virt - 1 } else { r.last() });
+        }
+        let covering = |r: Option<Region>| r.filter(|r| r.virt <= start);
+
+        match (covering(o), covering(n)) {
+            (Some(o), Some(n)) if o.same_mapping(&n) => {}
+            (o, n) => {
+                if let Some(o) = o {
+                    writeln!(out, "- {}", o.clamp(start, last))?;
+                }
+                if let Some(n) = n {
+                    writeln!(out, "+ {}", n.clamp(start, last))?;
+                }
+            }
+        }
+        addr = last.checked_add(1);
+    }
+    Ok(())
+}
+
+// maps and unmaps a page and checks that the diffs show just that page coming and going
+// the snapshots are diffed against the live tables, so a snapshot mapping its own vec doesn't show up
+pub fn check_snapshot_diff() {
+    let walker = unsafe { Walker::from_cr3(crate::asm::read_cr3()) };
+    let before = Snapshot::take(walker);
+    let page = crate::mm::alloc_page().unwrap();
+    let range = format!("{:016x}-{:016x} ", page.usize(), page.usize() + 0xfff);
+    let mut out = String::new();
+    diff(&mut out, before.regions(), regions(walker)).unwrap();
+    assert!(
+        out.lines().count() == 1 && out.starts_with(&format!("+ {}", range)),
+        "mapping a page diffed as:\n{}",
+        out
+    );
+
+    let mapped = Snapshot::take(walker);
+    unsafe { crate::mm::free_page(page) };
+    out.clear();
+    diff(&mut out, mapped.regions(), regions(walker)).unwrap();
+    assert!(
+        out.lines().count() == 1 && out.starts_with(&format!("- {}", range)),
+        "unmapping a page diffed as:\n{}",
+        out
+    );
+
+    drop(mapped);
+    out.clear();
+    diff(&mut out, before.regions(), regions(walker)).unwrap();
+    assert!(
+        out.is_empty(),
+        "mapping and unmapping a page left a diff:\n{}",
+        out
+    );
+    println!("page table snapshots diff a mapped page as a + and a - line");
+}
@@ -1,9 +1,141 @@
-use super::{EntryValue, NonPresentUsize, PTL1Entries, PTL2Entries, PTL3Entries, PTL1, PTL2, PTL3};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use raw_cpuid::CpuId;
+
+use super::walk::{table_virt, PageSize};
+use super::{
+    EntryValue, NonPresentUsize, PTL1Entries, PTL2Entries, PTL3Entries, PTL4Entries, PTL1, PTL2,
+    PTL3, PTL4,
+};
+use crate::asm::invlpg;
 use crate::types::{
-    page_table::{Entry, Flags, HighLevelEntryFlags},
-    HasVirtAddr, PTL2PageAddr, PTL3PageAddr, PTL4PageAddr, PageAddr,
+    page_table::{
+        CacheMode, Entry, Flags, HighLevelEntryFlags, SubtableFlags, ACCESSED_FLAG, DIRTY_FLAG,
+        ENTRY_COUNT, EXEC_DISABLE_FLAG, LARGE_PAGE_FLAG, LARGE_PAT_FLAG, PAT_FLAG, PRESENT_FLAG,
+        PT_FRAME_BITS, USER_FLAG, WRITABLE_FLAG,
+    },
+    FrameAddr, HasPhysAddr, HasVirtAddr, PTL2PageAddr, PTL3PageAddr, PTL4PageAddr, PTL5PageAddr,
+    PageAddr,
 };
 
+static HUGE_PAGES: AtomicBool = AtomicBool::new(false);
+
+pub fn detect_huge_pages() {
+    let supported = CpuId::new()
+        .get_extended_processor_and_feature_identifiers()
+        .is_some_and(|f| f.has_1gib_pages());
+    HUGE_PAGES.store(supported, Ordering::Relaxed);
+}
+
+// 1GiB pages are optional, the large page bit in an l3 entry is reserved without them
+pub fn huge_pages_supported() -> bool {
+    HUGE_PAGES.load(Ordering::Relaxed)
+}
+
+// the bits of a leaf entry that aren't the frame, for a page of this size
+fn leaf_frame_bits(size: PageSize) -> usize {
+    PT_FRAME_BITS & !(size.bytes() - 1)
+}
+
+// the PAT bit is in a different place in 4K entries than in large ones,
+// and in 4K entries the large page bit is the PAT bit
+fn large_to_small_flags(flags: usize) -> usize {
+    let pat = if flags & LARGE_PAT_FLAG != 0 {
+        PAT_FLAG
+    } else {
+        0
+    };
+    flags & !(LARGE_PAT_FLAG | LARGE_PAGE_FLAG) | pat
+}
+
+fn small_to_large_flags(flags: usize) -> usize {
+    let pat = if flags & PAT_FLAG != 0 {
+        LARGE_PAT_FLAG
+    } else {
+        0
+    };
+    flags & !PAT_FLAG | LARGE_PAGE_FLAG | pat
+}
+
+// turns the leaf entry into a table's worth of leaf entries of the next size down
+// the subtable entry gets the leaf's permissions so the pieces end up with the same ones
+unsafe fn split_leaf(
+    slot: &mut usize,
+    addr: usize,
+    size: PageSize,
+    piece_size: PageSize,
+    subtable_frame: FrameAddr,
+    subtable: &mut [usize; ENTRY_COUNT],
+) {
+    let leaf = *slot;
+    assert!(
+        leaf & PRESENT_FLAG != 0 && leaf & LARGE_PAGE_FLAG != 0,
+        "entry for {:#x} isn't a large page",
+        addr
+    );
+    let base = leaf & leaf_frame_bits(size);
+    let mut flags = leaf & !leaf_frame_bits(size);
+    if piece_size == PageSize::Size4K {
+        flags = large_to_small_flags(flags);
+    }
+    for (i, piece) in subtable.iter_mut().enumerate() {
+        *piece = (base + i * piece_size.bytes()) | flags;
+    }
+    *slot = Entry::at_frame(subtable_frame)
+        .set_flags(SubtableFlags::from_usize(
+            leaf & (WRITABLE_FLAG | USER_FLAG),
+        ))
+        .to_usize();
+    // the old page was one tlb entry, so one invlpg gets it
+    invlpg(addr);
+}
+
+// if the subtable maps one aligned block with the same flags everywhere, replaces it with
+// a single leaf entry for the whole block and returns the subtable's frame for the caller to free
+unsafe fn merge_leaves(
+    slot: &mut usize,
+    addr: usize,
+    size: PageSize,
+    piece_size: PageSize,
+) -> Option<FrameAddr> {
+    let entry = *slot;
+    if entry & PRESENT_FLAG == 0 || entry & LARGE_PAGE_FLAG != 0 {
+        return None;
+    }
+    let subtable_frame = (entry & PT_FRAME_BITS).phys_addr().as_aligned();
+    let subtable = core::slice::from_raw_parts(table_virt(subtable_frame)?, ENTRY_COUNT);
+
+    let frame_bits = leaf_frame_bits(piece_size);
+    // the cpu sets these whenever, so they don't stop a merge
+    let ignored = ACCESSED_FLAG | DIRTY_FLAG;
+    let first = subtable[0];
+    let base = first & frame_bits;
+    let is_leaf = piece_size == PageSize::Size4K || first & LARGE_PAGE_FLAG != 0;
+    if first & PRESENT_FLAG == 0 || !is_leaf || base % size.bytes() != 0 {
+        return None;
+    }
+    let same = subtable.iter().enumerate().all(|(i, &piece)| {
+        piece & frame_bits == base + i * piece_size.bytes()
+            && (piece ^ first) & !frame_bits & !ignored == 0
+    });
+    if !same {
+        return None;
+    }
+
+    let mut flags = first & !frame_bits | subtable.iter().fold(0, |bits, p| bits | p & ignored);
+    if piece_size == PageSize::Size4K {
+        flags = small_to_large_flags(flags);
+    }
+    // the subtable entry's permissions applied to every piece too
+    flags &= !(WRITABLE_FLAG | USER_FLAG) | entry;
+    flags |= entry & EXEC_DISABLE_FLAG;
+    *slot = base | flags;
+    for piece in 0..ENTRY_COUNT {
+        invlpg(addr + piece * piece_size.bytes());
+    }
+    Some(subtable_frame)
+}
+
 pub struct EntrySlot<'a>(&'a mut usize);
 
 pub struct PTL1EntrySlot<'a> {
@@ -26,6 +158,11 @@ pub struct PTL4EntrySlot<'a> {
     pub(super) addr: PTL4PageAddr,
 }
 
+pub struct PTL5EntrySlot<'a> {
+    pub(super) entry: &'a mut usize,
+    pub(super) addr: PTL5PageAddr,
+}
+
 impl EntrySlot<'_> {
     pub fn get(&self) -> EntryValue {
         EntryValue::from_usize(*self.0)
@@ -106,8 +243,8 @@ macro_rules! impl_map_subtable_methods {
 impl<'a> PTL1EntrySlot<'a> {
     impl_entry_methods!('a, PageAddr);
 
-    pub unsafe fn map_page(mut self, entry: Entry) -> PageAddr {
-        self.entry().set_entry(entry);
+    pub unsafe fn map_page(mut self, mut entry: Entry, cache: CacheMode) -> PageAddr {
+        self.entry().set_entry(entry.set_cache_mode(cache, false));
         self.addr
     }
 }
@@ -117,17 +254,91 @@ impl<'a> PTL2EntrySlot<'a> {
 
     impl_map_subtable_methods!('a, PTL1, PTL1Entries);
 
-    pub unsafe fn map_large_page(mut self, entry: Entry) -> PTL2PageAddr {
+    pub unsafe fn map_large_page(mut self, mut entry: Entry, cache: CacheMode) -> PTL2PageAddr {
         assert!(entry.flags::<HighLevelEntryFlags>().is_large_page());
-        self.entry().set_entry(entry);
+        self.entry().set_entry(entry.set_cache_mode(cache, true));
         self.addr
     }
+
+    // maps the 2MiB page with 4K pages in subtable instead, with the same flags
+    pub unsafe fn split_large_page(
+        self,
+        subtable_frame: FrameAddr,
+        subtable: &'a mut PTL1,
+    ) -> PTL1Entries<'a> {
+        let addr = self.addr.usize();
+        split_leaf(
+            self.entry,
+            addr,
+            PageSize::Size2M,
+            PageSize::Size4K,
+            subtable_frame,
+            &mut subtable.entries,
+        );
+        PTL1Entries {
+            entries: &mut subtable.entries,
+            addr: self.addr.as_aligned(),
+        }
+    }
+
+    // the other way, if the l1 table maps a whole aligned 2MiB page the same way
+    // returns the l1 table's frame, which isn't used anymore
+    pub unsafe fn merge_large_page(&mut self) -> Option<FrameAddr> {
+        merge_leaves(
+            self.entry,
+            self.addr.usize(),
+            PageSize::Size2M,
+            PageSize::Size4K,
+        )
+    }
 }
 
 impl<'a> PTL3EntrySlot<'a> {
     impl_entry_methods!('a, PTL3PageAddr);
 
     impl_map_subtable_methods!('a, PTL2, PTL2Entries);
+
+    pub unsafe fn map_huge_page(mut self, mut entry: Entry, cache: CacheMode) -> PTL3PageAddr {
+        assert!(huge_pages_supported(), "1GiB pages aren't supported");
+        assert!(entry.flags::<HighLevelEntryFlags>().is_large_page());
+        self.entry().set_entry(entry.set_cache_mode(cache, true));
+        self.addr
+    }
+
+    // maps the 1GiB page with 2MiB pages in subtable instead, with the same flags
+    pub unsafe fn split_huge_page(
+        self,
+        subtable_frame: FrameAddr,
+        subtable: &'a mut PTL2,
+    ) -> PTL2Entries<'a> {
+        let addr = self.addr.usize();
+        split_leaf(
+            self.entry,
+            addr,
+            PageSize::Size1G,
+            PageSize::Size2M,
+            subtable_frame,
+            &mut subtable.entries,
+        );
+        PTL2Entries {
+            entries: &mut subtable.entries,
+            addr: self.addr.as_aligned(),
+        }
+    }
+
+    // the other way, if the l2 table maps a whole aligned 1GiB page with 2MiB pages the same way
+    // returns the l2 table's frame, which isn't used anymore
+    pub unsafe fn merge_huge_page(&mut self) -> Option<FrameAddr> {
+        if !huge_pages_supported() {
+            return None;
+        }
+        merge_leaves(
+            self.entry,
+            self.addr.usize(),
+            PageSize::Size1G,
+            PageSize::Size2M,
+        )
+    }
 }
 
 impl<'a> PTL4EntrySlot<'a> {
@@ -135,3 +346,9 @@ impl<'a> PTL4EntrySlot<'a> {
 
     impl_map_subtable_methods!('a, PTL3, PTL3Entries);
 }
+
+impl<'a> PTL5EntrySlot<'a> {
+    impl_entry_methods!('a, PTL5PageAddr);
+
+    impl_map_subtable_methods!('a, PTL4, PTL4Entries);
+}
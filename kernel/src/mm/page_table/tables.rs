@@ -1,6 +1,9 @@
-use super::{EntryValue, PTL1EntrySlot, PTL2EntrySlot, PTL3EntrySlot, PTL4EntrySlot};
+use super::{
+    EntryValue, PTL1EntrySlot, PTL2EntrySlot, PTL3EntrySlot, PTL4EntrySlot, PTL5EntrySlot,
+};
 use crate::types::{
-    page_table::ENTRY_COUNT, PTL2PageAddr, PTL3PageAddr, PTL4PageAddr, PageAddr, Zeroable,
+    page_table::ENTRY_COUNT, PTL2PageAddr, PTL3PageAddr, PTL4PageAddr, PTL5PageAddr, PageAddr,
+    Zeroable,
 };
 
 #[repr(C, align(4096))]
@@ -23,10 +26,17 @@ pub struct PTL4 {
     pub(super) entries: [usize; ENTRY_COUNT],
 }
 
+// only with 5-level paging
+#[repr(C, align(4096))]
+pub struct PTL5 {
+    pub(super) entries: [usize; ENTRY_COUNT],
+}
+
 unsafe impl Zeroable for PTL1 {}
 unsafe impl Zeroable for PTL2 {}
 unsafe impl Zeroable for PTL3 {}
 unsafe impl Zeroable for PTL4 {}
+unsafe impl Zeroable for PTL5 {}
 
 pub struct PTL1Entries<'a> {
     pub(super) entries: &'a mut [usize],
@@ -48,6 +58,11 @@ pub struct PTL4Entries<'a> {
     pub(super) addr: PTL4PageAddr,
 }
 
+pub struct PTL5Entries<'a> {
+    pub(super) entries: &'a mut [usize],
+    pub(super) addr: PTL5PageAddr,
+}
+
 fn take_first_mut<'a, T>(s: &mut &'a mut [T]) -> &'a mut T {
     let slice = core::mem::take(s);
     let (s1, s2) = slice.split_first_mut().unwrap();
@@ -141,3 +156,4 @@ impl_handle_methods!(PTL1Entries, PTL1EntrySlot, PTL1, PageAddr);
 impl_handle_methods!(PTL2Entries, PTL2EntrySlot, PTL2, PTL2PageAddr);
 impl_handle_methods!(PTL3Entries, PTL3EntrySlot, PTL3, PTL3PageAddr);
 impl_handle_methods!(PTL4Entries, PTL4EntrySlot, PTL4, PTL4PageAddr);
+impl_handle_methods!(PTL5Entries, PTL5EntrySlot, PTL5, PTL5PageAddr);
@@ -0,0 +1,209 @@
+use core::ops::Range;
+
+use crate::asm::read_cr3;
+use crate::mm::{try_frame_meta, FrameFlags};
+use crate::types::page::{PAGE_SHIFT, PAGE_SIZE};
+use crate::types::page_table::{
+    canonical, paging_levels, DISABLE_CACHE_FLAG, ENTRY_COUNT, EXEC_DISABLE_FLAG, GLOBAL_FLAG,
+    LARGE_PAGE_FLAG, LARGE_PAT_FLAG, PAT_FLAG, PRESENT_FLAG, PTE_INDEX_SIZE, PT_FRAME_BITS,
+    USER_FLAG, WRITABLE_FLAG, WRITE_THROUGH_FLAG,
+};
+use crate::types::{
+    virt_addr_bits, FrameAddr, HasPhysAddr, HasVirtAddr, PhysAddr, VirtAddr, ID_MAP_SIZE,
+};
+
+// page tables have to be somewhere the kernel can read them:
+// wherever page_alloc recorded in the frame's metadata, or else the 1GiB id-map
+pub fn table_virt(frame: FrameAddr) -> Option<*mut usize> {
+    if let Some(meta) = try_frame_meta(frame) {
+        if meta.flags().contains(FrameFlags::PAGE_TABLE) && !meta.owner().is_null() {
+            return Some(meta.owner() as *mut usize);
+        }
+    }
+    (frame.usize() < ID_MAP_SIZE).then(|| frame.to_virt().ptr())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PageSize {
+    Size4K,
+    Size2M,
+    Size1G,
+}
+
+impl PageSize {
+    pub fn bytes(self) -> usize {
+        match self {
+            Self::Size4K => PAGE_SIZE,
+            Self::Size2M => PAGE_SIZE << PTE_INDEX_SIZE,
+            Self::Size1G => PAGE_SIZE << (PTE_INDEX_SIZE * 2),
+        }
+    }
+}
+
+// what a mapping actually allows after combining every level of the walk:
+// writable and user have to be set at every level, exec disable at any one
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MappingFlags {
+    pub writable: bool,
+    pub user: bool,
+    pub no_exec: bool,
+    pub global: bool,
+    // which PAT entry the page uses, from the PAT, PCD and PWT bits of the last level
+    pub pat_index: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Mapping {
+    pub virt: VirtAddr,
+    pub phys: PhysAddr,
+    pub size: PageSize,
+    pub flags: MappingFlags,
+}
+
+impl Mapping {
+    // the last page can end at the very top of the address space, so no ranges here
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr.usize().wrapping_sub(self.virt.usize()) < self.size.bytes()
+    }
+
+    pub fn translate(&self, addr: VirtAddr) -> PhysAddr {
+        assert!(self.contains(addr));
+        (self.phys.usize() + (addr.usize() - self.virt.usize())).phys_addr()
+    }
+}
+
+// a read-only view of the page tables under a top-level table, not necessarily the current one
+// that's an l5 table with 5-level paging and an l4 table otherwise
+#[derive(Clone, Copy)]
+pub struct Walker {
+    root: FrameAddr,
+}
+
+impl Walker {
+    // the flag bits of cr3 are ignored
+    pub unsafe fn from_cr3(cr3: usize) -> Self {
+        Self {
+            root: (cr3 & PT_FRAME_BITS).phys_addr().as_aligned(),
+        }
+    }
+
+    pub unsafe fn current() -> Self {
+        Self::from_cr3(read_cr3())
+    }
+
+    // the mapping that covers addr
+    // or if there isn't one, the size of the span around it that has nothing mapped
+    // tables that can't be read are treated as unmapped
+    fn walk(&self, addr: usize) -> Result<Mapping, usize> {
+        let mut table = self.root;
+        let mut writable = true;
+        let mut user = true;
+        let mut no_exec = false;
+        for level in (1..=paging_levels()).rev() {
+            let shift = PAGE_SHIFT + PTE_INDEX_SIZE * (level - 1);
+            let Some(entries) = table_virt(table) else {
+                // everything under the entry that pointed here, or half the address space for the root
+                let span = 1 << (shift + PTE_INDEX_SIZE);
+                return Err(span.min(1 << (virt_addr_bits() - 1)));
+            };
+            let idx = (addr >> shift) % ENTRY_COUNT;
+            let entry = unsafe { *entries.add(idx) };
+            if entry & PRESENT_FLAG == 0 {
+                return Err(1 << shift);
+            }
+            writable &= entry & WRITABLE_FLAG != 0;
+            user &= entry & USER_FLAG != 0;
+            no_exec |= entry & EXEC_DISABLE_FLAG != 0;
+
+            let is_leaf = level == 1 || (level <= 3 && entry & LARGE_PAGE_FLAG != 0);
+            if !is_leaf {
+                table = (entry & PT_FRAME_BITS).phys_addr().as_aligned();
+                continue;
+            }
+
+            let (size, pat_flag) = match level {
+                1 => (PageSize::Size4K, PAT_FLAG),
+                2 => (PageSize::Size2M, LARGE_PAT_FLAG),
+                _ => (PageSize::Size1G, LARGE_PAT_FLAG),
+            };
+            let offset_mask = size.bytes() - 1;
+            let pat_index = (entry & WRITE_THROUGH_FLAG != 0) as u8
+                | ((entry & DISABLE_CACHE_FLAG != 0) as u8) << 1
+                | ((entry & pat_flag != 0) as u8) << 2;
+            return Ok(Mapping {
+                virt: canonical(addr & !offset_mask).virt_addr(),
+                phys: (entry & PT_FRAME_BITS & !offset_mask).phys_addr(),
+                size,
+                flags: MappingFlags {
+                    writable,
+                    user,
+                    no_exec,
+                    global: entry & GLOBAL_FLAG != 0,
+                    pat_index,
+                },
+            });
+        }
+        unreachable!()
+    }
+
+    pub fn mapping_at(&self, addr: VirtAddr) -> Option<Mapping> {
+        self.walk(addr.usize()).ok()
+    }
+
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        Some(self.mapping_at(addr)?.translate(addr))
+    }
+
+    // every mapping that overlaps the range, in address order
+    pub fn mappings(&self, range: Range<VirtAddr>) -> Mappings {
+        Mappings {
+            walker: *self,
+            next: Some(canonical(range.start.usize())),
+            end: Some(range.end.usize()),
+        }
+    }
+
+    pub fn all_mappings(&self) -> Mappings {
+        Mappings {
+            walker: *self,
+            next: Some(0),
+            end: None,
+        }
+    }
+}
+
+pub struct Mappings {
+    walker: Walker,
+    next: Option<usize>,
+    // None for the top of the address space
+    end: Option<usize>,
+}
+
+impl Iterator for Mappings {
+    type Item = Mapping;
+
+    fn next(&mut self) -> Option<Mapping> {
+        loop {
+            let addr = self.next?;
+            if self.end.is_some_and(|end| addr >= end) {
+                self.next = None;
+                return None;
+            }
+            let (mapping, span) = match self.walker.walk(addr) {
+                Ok(mapping) => (Some(mapping), mapping.size.bytes()),
+                Err(span) => (None, span),
+            };
+            // skip over the non-canonical hole in the middle
+            self.next = (addr & !(span - 1)).checked_add(span).map(|next| {
+                if next == 1 << (virt_addr_bits() - 1) {
+                    canonical(next)
+                } else {
+                    next
+                }
+            });
+            if mapping.is_some() {
+                return mapping;
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use core::arch::asm;
+use core::ops::Range;
+
+use raw_cpuid::CpuId;
+
+use crate::asm::{read_cr3, read_msr, write_cr3, write_msr};
+use crate::types::page_table::{set_pat_programmed, CacheMode};
+use crate::types::{HasPhysAddr, PhysAddr};
+
+// the PAT decides what memory type each CacheMode gets,
+// and the MTRRs (set up by the firmware) say what type each range of physical memory should be
+// the two get combined, so a mapping can end up with a different type than it asked for
+
+const PAT_MSR: u32 = 0x277;
+const MTRR_CAP_MSR: u32 = 0xFE;
+const MTRR_DEF_TYPE_MSR: u32 = 0x2FF;
+// base is 0x200 + 2n, mask is right after it
+const MTRR_PHYS_BASE_MSR: u32 = 0x200;
+const MTRR_FIX_64K_MSR: u32 = 0x250;
+const MTRR_FIX_16K_MSR: u32 = 0x258;
+const MTRR_FIX_4K_MSR: u32 = 0x268;
+
+const MTRR_CAP_FIXED: u64 = 1 << 8;
+const MTRR_DEF_FIXED_ENABLE: u64 = 1 << 10;
+const MTRR_DEF_ENABLE: u64 = 1 << 11;
+const MTRR_MASK_VALID: u64 = 1 << 11;
+
+// the fixed range MTRRs cover the first 1MiB
+const FIXED_RANGES_END: usize = 0x10_0000;
+
+// the memory type encodings the PAT and MTRRs both use
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemType {
+    Uncached,
+    WriteCombining,
+    WriteThrough,
+    WriteProtected,
+    WriteBack,
+    // only in the PAT, it's UC unless the MTRRs say WC
+    UncachedMinus,
+}
+
+impl MemType {
+    fn from_bits(bits: u8) -> Option<Self> {
+        Some(match bits {
+            0 => Self::Uncached,
+            1 => Self::WriteCombining,
+            4 => Self::WriteThrough,
+            5 => Self::WriteProtected,
+            6 => Self::WriteBack,
+            7 => Self::UncachedMinus,
+            _ => return None,
+        })
+    }
+
+    fn to_bits(self) -> u64 {
+        match self {
+            Self::Uncached => 0,
+            Self::WriteCombining => 1,
+            Self::WriteThrough => 4,
+            Self::WriteProtected => 5,
+            Self::WriteBack => 6,
+            Self::UncachedMinus => 7,
+        }
+    }
+}
+
+impl From<CacheMode> for MemType {
+    fn from(mode: CacheMode) -> Self {
+        match mode {
+            CacheMode::WriteBack => Self::WriteBack,
+            CacheMode::WriteThrough => Self::WriteThrough,
+            CacheMode::UncachedMinus => Self::UncachedMinus,
+            CacheMode::Uncached => Self::Uncached,
+            CacheMode::WriteCombining => Self::WriteCombining,
+        }
+    }
+}
+
+pub fn init() {
+    let cpuid = CpuId::new();
+    if !cpuid.get_feature_info().is_some_and(|f| f.has_pat()) {
+        // without the PAT, only the first 4 modes exist, and that's what the layout starts with
+        println!("no PAT, write-combining mappings will be UC-");
+        return;
+    }
+
+    let pat = CacheMode::PAT_LAYOUT
+        .iter()
+        .enumerate()
+        .map(|(i, &mode)| MemType::from(mode).to_bits() << (i * 8))
+        .fold(0, |pat, entry| pat | entry);
+
+    // the first 4 entries don't change, so existing mappings stay the same
+    // but anything cached under an old type has to go
+    unsafe {
+        write_msr(PAT_MSR, pat);
+        asm!("wbinvd");
+        write_cr3(read_cr3());
+    }
+    set_pat_programmed();
+}
+
+// the type the PAT gives a mode
+fn pat_type(mode: CacheMode) -> MemType {
+    MemType::from(CacheMode::from_pat_index(mode.pat_index()))
+}
+
+struct Mtrrs {
+    default: MemType,
+    fixed_enabled: bool,
+    variable_count: usize,
+    phys_addr_mask: usize,
+}
+
+fn read_mtrrs() -> Option<Mtrrs> {
+    let cpuid = CpuId::new();
+    if !cpuid.get_feature_info().is_some_and(|f| f.has_mtrr()) {
+        return None;
+    }
+    let phys_addr_bits = cpuid
+        .get_processor_capacity_feature_info()
+        .map_or(36, |f| f.physical_address_bits());
+
+    let (cap, def) = unsafe { (read_msr(MTRR_CAP_MSR), read_msr(MTRR_DEF_TYPE_MSR)) };
+    if def & MTRR_DEF_ENABLE == 0 {
+        return None;
+    }
+    Some(Mtrrs {
+        default: MemType::from_bits(def as u8).unwrap_or(MemType::Uncached),
+        fixed_enabled: cap & MTRR_CAP_FIXED != 0 && def & MTRR_DEF_FIXED_ENABLE != 0,
+        variable_count: (cap & 0xFF) as usize,
+        phys_addr_mask: (1 << phys_addr_bits) - 1,
+    })
+}
+
+fn fixed_type(addr: usize) -> MemType {
+    // 8 64K ranges, then 16 16K ranges, then 64 4K ranges, 8 to an msr
+    let (msr, byte) = if addr < 0x8_0000 {
+        (MTRR_FIX_64K_MSR, addr >> 16)
+    } else if addr < 0xC_0000 {
+        let i = (addr - 0x8_0000) >> 14;
+        (MTRR_FIX_16K_MSR + (i / 8) as u32, i % 8)
+    } else {
+        let i = (addr - 0xC_0000) >> 12;
+        (MTRR_FIX_4K_MSR + (i / 8) as u32, i % 8)
+    };
+    let bits = unsafe { read_msr(msr) } >> (byte * 8);
+    MemType::from_bits(bits as u8).unwrap_or(MemType::Uncached)
+}
+
+// when ranges overlap, UC wins, and WT wins over WB
+// anything else overlapping is undefined, so it's None
+fn combine(a: Option<MemType>, b: MemType) -> Option<MemType> {
+    use MemType::*;
+    match (a?, b) {
+        (a, b) if a == b => Some(a),
+        (Uncached, _) | (_, Uncached) => Some(Uncached),
+        (WriteThrough, WriteBack) | (WriteBack, WriteThrough) => Some(WriteThrough),
+        _ => None,
+    }
+}
+
+// the type the MTRRs give a range, None if it's not all one type (or it's undefined)
+// with the MTRRs off everything is UC
+pub fn mtrr_type(range: Range<PhysAddr>) -> Option<MemType> {
+    let Some(mtrrs) = read_mtrrs() else {
+        return Some(MemType::Uncached);
+    };
+    let range = range.start.usize()..range.end.usize();
+
+    let mut fixed: Option<Option<MemType>> = None;
+    if mtrrs.fixed_enabled && range.start < FIXED_RANGES_END {
+        if range.end > FIXED_RANGES_END {
+            return None;
+        }
+        for page in range.clone().step_by(0x1000) {
+            let ty = fixed_type(page);
+            match fixed {
+                None => fixed = Some(Some(ty)),
+                Some(Some(prev)) if prev == ty => {}
+                _ => return None,
+            }
+        }
+        // fixed ranges take priority over the variable ones
+        return fixed.flatten();
+    }
+
+    let mut ty = None;
+    for i in 0..mtrrs.variable_count {
+        let msr = MTRR_PHYS_BASE_MSR + 2 * i as u32;
+        let (base, mask) = unsafe { (read_msr(msr), read_msr(msr + 1)) };
+        if mask & MTRR_MASK_VALID == 0 {
+            continue;
+        }
+        let Some(var_type) = MemType::from_bits(base as u8) else {
+            continue;
+        };
+        // variable ranges are a power of 2 in size and aligned to it
+        let mask = mask as usize & mtrrs.phys_addr_mask & !0xFFF;
+        let start = base as usize & mask;
+        let size = (!mask & mtrrs.phys_addr_mask) + 1;
+        let var_range = start..start + size;
+
+        let overlaps = var_range.start < range.end && range.start < var_range.end;
+        let covers = var_range.start <= range.start && range.end <= var_range.end;
+        if covers {
+            ty = Some(combine(ty.unwrap_or(Some(var_type)), var_type));
+        } else if overlaps {
+            return None;
+        }
+    }
+    ty.unwrap_or(Some(mtrrs.default))
+}
+
+// what a mapping actually gets after combining the PAT and MTRR types
+pub fn effective_type(mode: CacheMode, mtrr: MemType) -> MemType {
+    use MemType::*;
+    match (pat_type(mode), mtrr) {
+        (UncachedMinus, WriteCombining) => WriteCombining,
+        (UncachedMinus, _) => Uncached,
+        (WriteCombining, _) => WriteCombining,
+        (WriteBack, mtrr) => mtrr,
+        (WriteThrough, WriteBack | WriteThrough) => WriteThrough,
+        (WriteThrough, WriteProtected) => WriteProtected,
+        (WriteThrough, _) => Uncached,
+        (Uncached, _) | (WriteProtected, _) => Uncached,
+    }
+}
+
+// warns if a mapping with this mode won't get the type it asked for
+// returns the type it'll actually get, if that's known
+pub fn check_mapping(range: Range<PhysAddr>, mode: CacheMode) -> Option<MemType> {
+    let Some(mtrr) = mtrr_type(range.clone()) else {
+        println!(
+            "{}..{} has mixed or undefined MTRR types, mapping it as {} anyways",
+            range.start, range.end, mode
+        );
+        return None;
+    };
+    let effective = effective_type(mode, mtrr);
+    let wanted = match pat_type(mode) {
+        MemType::UncachedMinus => MemType::Uncached,
+        ty => ty,
+    };
+    if effective != wanted {
+        println!(
+            "{}..{} mapped as {} but the MTRRs say {:?}, so it's {:?}",
+            range.start, range.end, mode, mtrr, effective
+        );
+    }
+    Some(effective)
+}
@@ -8,6 +8,7 @@ use core::{
 };
 
 use super::bump_alloc::BumpAllocator;
+use crate::data_structures::radix_tree::NodeAlloc;
 
 const SMALL_ALLOC_SEG_SIZE: usize = 1 << 21;
 const SMALL_ALLOC_SLAB_SHIFT: usize = 16;
@@ -73,6 +74,30 @@ impl Slab {
     }
 }
 
+// a slab that only hands out one radix tree's nodes
+pub struct TreeSlab(&'static Slab);
+
+impl TreeSlab {
+    // Safety: slab must fit radix_tree::node_layout() and must not be used by anything else
+    // the tree's writer lock stands in for the slab being CPU-owned
+    pub unsafe fn new(slab: &'static Slab) -> Self {
+        Self(slab)
+    }
+}
+
+// only the tree's writer uses it, with the writer lock held
+unsafe impl Send for TreeSlab {}
+
+unsafe impl NodeAlloc for TreeSlab {
+    fn alloc(&mut self) -> Option<*mut ()> {
+        unsafe { self.0.alloc_fast() }
+    }
+
+    unsafe fn dealloc(&mut self, node: *mut ()) {
+        self.0.dealloc(node)
+    }
+}
+
 struct SlabSlot {
     buffer: *mut [u8],
     slab: UnsafeCell<Option<Slab>>,
@@ -0,0 +1,198 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use raw_cpuid::CpuId;
+
+use crate::asm::{invlpg, invpcid, read_cr3, read_cr4, write_cr3, write_cr4};
+use crate::sync::SpinLock;
+use crate::types::page_table::PT_FRAME_BITS;
+use crate::types::{FrameAddr, HasPhysAddr};
+
+// with PCIDs, the tlb keeps entries for several address spaces at once, tagged with their pcid,
+// so switching address spaces doesn't have to throw everything away
+// without them (or when they run out), address spaces use pcid 0 and every switch flushes
+// this only deals with this cpu's tlb
+
+const CR4_PGE: usize = 1 << 7;
+const CR4_PCIDE: usize = 1 << 17;
+// in the value written to cr3, keeps the new pcid's tlb entries
+const CR3_NO_FLUSH: usize = 1 << 63;
+const PCID_MASK: usize = 0xFFF;
+const PCID_COUNT: usize = 1 << 12;
+
+const INVPCID_ADDR: usize = 0;
+const INVPCID_SINGLE: usize = 1;
+const INVPCID_ALL_NON_GLOBAL: usize = 3;
+
+static PCID_ENABLED: AtomicBool = AtomicBool::new(false);
+static HAS_INVPCID: AtomicBool = AtomicBool::new(false);
+
+struct PcidSet {
+    used: [u64; PCID_COUNT / 64],
+    // pcids that might still have entries from something else in the tlb,
+    // they get flushed the next time they're switched to
+    stale: [u64; PCID_COUNT / 64],
+}
+
+impl PcidSet {
+    fn get(bits: &[u64], pcid: u16) -> bool {
+        bits[pcid as usize / 64] & (1 << (pcid % 64)) != 0
+    }
+
+    fn set(bits: &mut [u64], pcid: u16, val: bool) {
+        if val {
+            bits[pcid as usize / 64] |= 1 << (pcid % 64);
+        } else {
+            bits[pcid as usize / 64] &= !(1 << (pcid % 64));
+        }
+    }
+}
+
+// pcid 0 is for the boot page tables and anything that didn't get a pcid of its own
+static PCIDS: SpinLock<PcidSet> = SpinLock::new(PcidSet {
+    used: {
+        let mut used = [0; PCID_COUNT / 64];
+        used[0] = 1;
+        used
+    },
+    stale: [0; PCID_COUNT / 64],
+});
+
+pub fn init() {
+    let cpuid = CpuId::new();
+    let has_pcid = cpuid.get_feature_info().is_some_and(|f| f.has_pcid());
+    let has_invpcid = cpuid
+        .get_extended_feature_info()
+        .is_some_and(|f| f.has_invpcid());
+    if !has_pcid {
+        println!("no PCIDs, every address space switch flushes the tlb");
+        return;
+    }
+    // cr3 has to have pcid 0 in it when this gets turned on
+    unsafe {
+        assert!(read_cr3() & PCID_MASK == 0, "cr3 has flag bits set");
+        write_cr4(read_cr4() | CR4_PCIDE);
+    }
+    HAS_INVPCID.store(has_invpcid, Ordering::Relaxed);
+    PCID_ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn pcids_enabled() -> bool {
+    PCID_ENABLED.load(Ordering::Relaxed)
+}
+
+// a tag for one address space's tlb entries, given back when it's dropped
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pcid(u16);
+
+impl Pcid {
+    // None if there's no PCIDs or they're all taken, the address space should use pcid 0 then
+    pub fn alloc() -> Option<Self> {
+        if !pcids_enabled() {
+            return None;
+        }
+        let mut pcids = PCIDS.lock();
+        let pcid = (1..PCID_COUNT as u16).find(|&p| !PcidSet::get(&pcids.used, p))?;
+        PcidSet::set(&mut pcids.used, pcid, true);
+        Some(Self(pcid))
+    }
+
+    pub fn get(&self) -> u16 {
+        self.0
+    }
+}
+
+impl Drop for Pcid {
+    fn drop(&mut self) {
+        let mut pcids = PCIDS.lock();
+        PcidSet::set(&mut pcids.used, self.0, false);
+        // whatever gets it next shouldn't see this address space's entries
+        PcidSet::set(&mut pcids.stale, self.0, true);
+    }
+}
+
+fn current_pcid() -> u16 {
+    (unsafe { read_cr3() } & PCID_MASK) as u16
+}
+
+// switches to the page tables under root (the l4 table, or the l5 table with 5-level paging),
+// keeping the tlb entries tagged with pcid if there are any
+// pcid 0 is shared, so switching to it always flushes
+pub unsafe fn switch_address_space(root: FrameAddr, pcid: Option<&Pcid>) {
+    let pcid = pcid.map_or(0, Pcid::get);
+    let mut cr3 = root.usize() | pcid as usize;
+    if pcid != 0 {
+        let mut pcids = PCIDS.lock();
+        if !PcidSet::get(&pcids.stale, pcid) {
+            cr3 |= CR3_NO_FLUSH;
+        }
+        PcidSet::set(&mut pcids.stale, pcid, false);
+    }
+    write_cr3(cr3);
+}
+
+// the top-level table of the address space the cpu is in
+pub fn current_root() -> FrameAddr {
+    (unsafe { read_cr3() } & PT_FRAME_BITS)
+        .phys_addr()
+        .as_aligned()
+}
+
+// drops the entry for addr in the current address space
+// kernel pages are global, so for them that's every address space
+pub unsafe fn flush_page(addr: usize) {
+    invlpg(addr);
+}
+
+// drops the entry for addr in the address space tagged with pcid, which doesn't have to be current
+pub unsafe fn flush_page_in(pcid: &Pcid, addr: usize) {
+    if pcid.get() == current_pcid() {
+        invlpg(addr);
+    } else if HAS_INVPCID.load(Ordering::Relaxed) {
+        invpcid(INVPCID_ADDR, pcid.get(), addr);
+    } else {
+        // without invpcid there's no way to reach another pcid's entries
+        PcidSet::set(&mut PCIDS.lock().stale, pcid.get(), true);
+    }
+}
+
+// drops every entry tagged with pcid
+pub unsafe fn flush_pcid(pcid: &Pcid) {
+    if HAS_INVPCID.load(Ordering::Relaxed) {
+        invpcid(INVPCID_SINGLE, pcid.get(), 0);
+    } else if pcid.get() == current_pcid() {
+        // without the no-flush bit, writing cr3 flushes the new pcid's entries
+        write_cr3(read_cr3() & !CR3_NO_FLUSH);
+    } else {
+        PcidSet::set(&mut PCIDS.lock().stale, pcid.get(), true);
+    }
+}
+
+// drops every entry, global pages too, in every address space
+// for when tables that global pages were reached through change
+pub unsafe fn flush_global() {
+    let cr4 = read_cr4();
+    if cr4 & CR4_PGE == 0 {
+        // then nothing is global
+        flush_all();
+        return;
+    }
+    // turning global pages off flushes everything, in every pcid
+    write_cr4(cr4 & !CR4_PGE);
+    write_cr4(cr4);
+}
+
+// drops every entry that isn't for a global page, in every address space
+pub unsafe fn flush_all() {
+    if !pcids_enabled() {
+        write_cr3(read_cr3());
+    } else if HAS_INVPCID.load(Ordering::Relaxed) {
+        invpcid(INVPCID_ALL_NON_GLOBAL, 0, 0);
+    } else {
+        let mut pcids = PCIDS.lock();
+        pcids.stale = [u64::MAX; PCID_COUNT / 64];
+        // the current one has to go now, and pcid 0 always flushes on the way in anyways
+        let current = current_pcid();
+        PcidSet::set(&mut pcids.stale, current, false);
+        write_cr3(read_cr3() & !CR3_NO_FLUSH);
+    }
+}
@@ -0,0 +1,147 @@
+use core::ops::Range;
+
+use alloc::boxed::Box;
+
+use super::address_space::{AddressSpace, Region};
+use super::frame_alloc::alloc_frame;
+use super::kernel_map::{global_flag, no_exec_flag};
+use crate::sync::{SpinLock, SpinLockGuard};
+use crate::types::page::{PAGE_SHIFT, PAGE_SIZE};
+use crate::types::page_table::{
+    pte_indices_to_addr, CacheMode, Entry, Flags, PageFlags, PTE_INDEX_SIZE, WRITABLE_FLAG,
+};
+use crate::types::{HasVirtAddr, PageAddr};
+
+// big allocations that only need to be contiguous in virtual memory
+// they get individual frames, so they work no matter how fragmented physical memory is
+// the region is l4 entry 510, right below page_alloc's
+// every allocation has an unmapped guard page after it, so running off the end faults
+
+const VMALLOC_L4_INDEX: usize = 510;
+const GUARD_PAGES: usize = 1;
+
+fn region_bounds() -> Range<usize> {
+    let start = pte_indices_to_addr(VMALLOC_L4_INDEX, 0, 0, 0).usize();
+    start..start + (1 << (PAGE_SHIFT + PTE_INDEX_SIZE * 3))
+}
+
+// the regions are in every address space, so this is the kernel's tables either way
+static VMALLOC: SpinLock<Option<AddressSpace>> = SpinLock::new(None);
+
+// the table under l4 entry 510 is made now, so every address space made after this shares it
+// has to be before the first AddressSpace::new, which copies the kernel's top-level entries
+pub fn init() {
+    let space = AddressSpace::new_kernel(region_bounds());
+    unsafe { space.l1_entry(region_bounds().start, true) }.expect("no memory for vmalloc's tables");
+    *VMALLOC.lock() = Some(space);
+}
+
+unsafe fn map_pages(space: &AddressSpace, pages: Range<usize>) -> Option<()> {
+    let flags = PageFlags::from_usize(WRITABLE_FLAG | no_exec_flag() | global_flag());
+    for page in pages.step_by(PAGE_SIZE) {
+        let entry = space.l1_entry(page, true)?;
+        let frame = alloc_frame()?;
+        *entry = Entry::at_frame(frame)
+            .set_flags(flags)
+            .set_cache_mode(CacheMode::WriteBack, false)
+            .to_usize();
+    }
+    Some(())
+}
+
+// boxes region and finds it a spot, the caller still has the lock afterwards
+fn insert(region: Region) -> Option<(SpinLockGuard<'static, Option<AddressSpace>>, PageAddr)> {
+    // boxed before taking the lock, since the heap might come back here
+    let region = Box::leak(Box::new(region.with_guard(GUARD_PAGES << PAGE_SHIFT)));
+
+    let mut vmalloc = VMALLOC.lock();
+    let space = vmalloc.as_mut().unwrap();
+    match space.insert_anywhere(region) {
+        Ok(start) => Some((vmalloc, start)),
+        Err(region) => {
+            drop(unsafe { Box::from_raw(region) });
+            None
+        }
+    }
+}
+
+// None if there isn't enough memory or room in the region
+pub fn vmalloc(size: usize) -> Option<PageAddr> {
+    assert!(size > 0, "can't vmalloc nothing");
+    let size = size.next_multiple_of(PAGE_SIZE);
+    let (mut vmalloc, start) = insert(Region::new(size))?;
+    let space = vmalloc.as_mut().unwrap();
+
+    let mapped = start.usize()..start.usize() + size;
+    if unsafe { map_pages(space, mapped.clone()) }.is_none() {
+        unsafe { space.unmap_range(mapped) };
+        let region = space.remove_containing(start.usize()).unwrap();
+        drop(unsafe { Box::from_raw(region) });
+        return None;
+    }
+    Some(start)
+}
+
+// like vmalloc, but nothing is mapped until it's touched, and then it's zeroed
+// so it only costs the pages that actually get used
+// not for kernel stacks, a fault can't push the interrupt frame onto a page that isn't there
+// None if there isn't room in the region
+pub fn vreserve(size: usize) -> Option<PageAddr> {
+    assert!(size > 0, "can't vreserve nothing");
+    let size = size.next_multiple_of(PAGE_SIZE);
+    let (_, start) = insert(Region::demand_zero(size, true, false))?;
+    Some(start)
+}
+
+// addr has to be exactly what vmalloc or vreserve returned
+pub unsafe fn vfree(addr: PageAddr) {
+    let mut vmalloc = VMALLOC.lock();
+    let space = vmalloc
+        .as_mut()
+        .unwrap_or_else(|| panic!("{} isn't from vmalloc", addr));
+    let region = space
+        .remove_containing(addr.usize())
+        .unwrap_or_else(|| panic!("{} isn't from vmalloc", addr));
+    assert!(
+        region.start() == addr,
+        "{} is in the middle of a vmalloc allocation",
+        addr
+    );
+    space.unmap_range(addr.usize()..addr.usize() + region.size());
+    drop(Box::from_raw(region));
+}
+
+// for faults in the kernel's half, backs demand-zero pages from vreserve
+pub unsafe fn handle_fault(addr: usize, error: u64) -> bool {
+    // anything else in the kernel's half is a real fault, which doesn't need the lock
+    if !region_bounds().contains(&addr) {
+        return false;
+    }
+    VMALLOC
+        .lock()
+        .as_mut()
+        .is_some_and(|s| s.handle_fault(addr, error))
+}
+
+// vreserve pages should read as zero until they're written, and keep what's written after
+// the first page is read before it's written, so it starts out on the zero page
+pub fn check_demand_zero() {
+    let start = vreserve(2 * PAGE_SIZE).unwrap();
+    let first = start.ptr::<usize>();
+    let second = (start.usize() + PAGE_SIZE) as *mut usize;
+    unsafe {
+        assert!(first.read_volatile() == 0, "vreserve page wasn't zero");
+        first.write_volatile(1);
+        second.write_volatile(2);
+        assert!(
+            first.read_volatile() == 1 && second.read_volatile() == 2,
+            "vreserve pages didn't keep what was written"
+        );
+        assert!(
+            *second.add(1) == 0,
+            "vreserve page wasn't zeroed when it was written"
+        );
+        vfree(start);
+    }
+    println!("vreserve pages are zero until written");
+}
@@ -12,6 +12,7 @@ struct InfoHeader {
     _reserved: u32,
 }
 
+#[derive(Clone, Copy)]
 pub struct Info(&'static [u8]);
 
 impl Info {
@@ -77,7 +78,7 @@ impl Tag for BootloaderName {
 pub struct Rsdp(acpi::rsdp::Rsdp);
 
 impl Rsdp {
-    pub fn get_tables(&self) -> AcpiTables<crate::acpi::IdMapper> {
+    pub fn get_tables(&self) -> AcpiTables<crate::acpi::IoremapMapper> {
         // hopefully theres no way to pass a bad rsdp here
         unsafe { crate::acpi::acpi_tables_from_rsdp(self.0).unwrap() }
     }
@@ -164,7 +165,7 @@ impl Tag for MMapEntryIterator {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum MMapEntryKind {
     Available,
     Reserved,
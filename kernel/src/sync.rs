@@ -1,66 +1 @@
-use core::cell::UnsafeCell;
-use core::sync::atomic::{AtomicBool, Ordering};
-
-pub struct SpinLock<T> {
-    locked: AtomicBool,
-    val: UnsafeCell<T>,
-}
-
-pub struct SpinLockGuard<'a, T>(&'a SpinLock<T>);
-
-impl<T> SpinLock<T> {
-    pub const fn new(val: T) -> Self {
-        Self {
-            locked: AtomicBool::new(false),
-            val: UnsafeCell::new(val),
-        }
-    }
-
-    pub fn lock(&self) -> SpinLockGuard<T> {
-        while self
-            .locked
-            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .is_err()
-        {
-            core::hint::spin_loop();
-        }
-        SpinLockGuard(self)
-    }
-
-    pub fn try_lock(&self) -> Option<SpinLockGuard<T>> {
-        if self
-            .locked
-            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .is_ok()
-        {
-            Some(SpinLockGuard(self))
-        } else {
-            None
-        }
-    }
-
-    unsafe fn unlock(&self) {
-        self.locked.store(false, Ordering::Release);
-    }
-}
-
-unsafe impl<T> core::marker::Sync for SpinLock<T> {}
-
-impl<'a, T> Drop for SpinLockGuard<'a, T> {
-    fn drop(&mut self) {
-        unsafe { self.0.unlock() }
-    }
-}
-
-impl<'a, T> core::ops::Deref for SpinLockGuard<'a, T> {
-    type Target = T;
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.0.val.get() }
-    }
-}
-
-impl<'a, T> core::ops::DerefMut for SpinLockGuard<'a, T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.0.val.get() }
-    }
-}
+pub use sparkle_lib::sync::{SpinLock, SpinLockGuard};
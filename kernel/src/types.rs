@@ -4,9 +4,11 @@ pub mod page_table;
 mod ptr;
 pub mod zeroable;
 
+pub use addr::{detect_paging_mode, five_level_paging, virt_addr_bits};
 pub use addr::{
     AlignedPhys, AlignedVirt, FrameAddr, HasPhysAddr, HasVirtAddr, PTL2FrameAddr, PTL2PageAddr,
-    PTL3FrameAddr, PTL3PageAddr, PTL4FrameAddr, PTL4PageAddr, PageAddr, PhysAddr, VirtAddr,
+    PTL3FrameAddr, PTL3PageAddr, PTL4FrameAddr, PTL4PageAddr, PTL5FrameAddr, PTL5PageAddr,
+    PageAddr, PhysAddr, VirtAddr, ID_MAP_SIZE,
 };
 pub use page::Page;
 pub use ptr::{ptr_from_option_mut, ptr_from_option_ref};
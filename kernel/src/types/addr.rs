@@ -2,16 +2,44 @@ use super::page::PAGE_SHIFT;
 use super::page_table::PTE_INDEX_SIZE;
 use crate::util::align::Alignment;
 use core::fmt::Display;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-const ID_MAP_SIZE: usize = 1 << 30;
+pub const ID_MAP_SIZE: usize = 1 << 30;
 // must equal HIGH_ID_MAP_VMA from linker
 const HIGH_ID_MAP_ADDR: usize = 0xFFFF_FFFF_C000_0000;
-const VIRT_ADDR_BITS: usize = 48;
+
+// boot.asm turns on 5-level paging if the cpu has it, which makes addresses 57 bits instead of 48
+// the kernel is at the top either way, so its addresses are the same in both
+const CR4_LA57: usize = 1 << 12;
+
+static FIVE_LEVEL_PAGING: AtomicBool = AtomicBool::new(false);
+
+// has to happen before anything uses addresses outside the top and bottom 128TiB
+pub fn detect_paging_mode() {
+    let la57 = unsafe { crate::asm::read_cr4() } & CR4_LA57 != 0;
+    FIVE_LEVEL_PAGING.store(la57, Ordering::Relaxed);
+    // tagged pointers get whatever address bits aren't used
+    sparkle_lib::data_structures::tagged::set_addr_bits(virt_addr_bits());
+}
+
+pub fn five_level_paging() -> bool {
+    FIVE_LEVEL_PAGING.load(Ordering::Relaxed)
+}
+
+#[inline(always)]
+pub fn virt_addr_bits() -> usize {
+    if five_level_paging() {
+        57
+    } else {
+        48
+    }
+}
 
 const PAGE_ALIGNMENT: Alignment = Alignment::new_from_shift(PAGE_SHIFT);
 const PTL2_ALIGNMENT: Alignment = Alignment::new_from_shift(PAGE_SHIFT + PTE_INDEX_SIZE);
 const PTL3_ALIGNMENT: Alignment = Alignment::new_from_shift(PAGE_SHIFT + PTE_INDEX_SIZE * 2);
 const PTL4_ALIGNMENT: Alignment = Alignment::new_from_shift(PAGE_SHIFT + PTE_INDEX_SIZE * 3);
+const PTL5_ALIGNMENT: Alignment = Alignment::new_from_shift(PAGE_SHIFT + PTE_INDEX_SIZE * 4);
 
 pub trait HasPhysAddr {
     fn usize(&self) -> usize;
@@ -199,43 +227,52 @@ make_addr_struct!(FrameAddr);
 make_addr_struct!(PTL2FrameAddr);
 make_addr_struct!(PTL3FrameAddr);
 make_addr_struct!(PTL4FrameAddr);
+make_addr_struct!(PTL5FrameAddr);
 impl_display!(FrameAddr, phys_addr);
 impl_display!(PTL2FrameAddr, phys_addr);
 impl_display!(PTL3FrameAddr, phys_addr);
 impl_display!(PTL4FrameAddr, phys_addr);
+impl_display!(PTL5FrameAddr, phys_addr);
 impl_addr_trait!(FrameAddr, HasPhysAddr);
 impl_addr_trait!(PTL2FrameAddr, HasPhysAddr);
 impl_addr_trait!(PTL3FrameAddr, HasPhysAddr);
 impl_addr_trait!(PTL4FrameAddr, HasPhysAddr);
+impl_addr_trait!(PTL5FrameAddr, HasPhysAddr);
 impl_aligned!(FrameAddr, AlignedPhys, PAGE_ALIGNMENT);
 impl_aligned!(PTL2FrameAddr, AlignedPhys, PTL2_ALIGNMENT);
 impl_aligned!(PTL3FrameAddr, AlignedPhys, PTL3_ALIGNMENT);
-impl_aligned!(PTL4FrameAddr, AlignedPhys, PTL3_ALIGNMENT);
+impl_aligned!(PTL4FrameAddr, AlignedPhys, PTL4_ALIGNMENT);
+impl_aligned!(PTL5FrameAddr, AlignedPhys, PTL5_ALIGNMENT);
 
 make_addr_struct!(PageAddr);
 make_addr_struct!(PTL2PageAddr);
 make_addr_struct!(PTL3PageAddr);
 make_addr_struct!(PTL4PageAddr);
+make_addr_struct!(PTL5PageAddr);
 impl_display!(PageAddr, virt_addr);
 impl_display!(PTL2PageAddr, virt_addr);
 impl_display!(PTL3PageAddr, virt_addr);
 impl_display!(PTL4PageAddr, virt_addr);
+impl_display!(PTL5PageAddr, virt_addr);
 impl_addr_trait!(PageAddr, HasVirtAddr);
 impl_addr_trait!(PTL2PageAddr, HasVirtAddr);
 impl_addr_trait!(PTL3PageAddr, HasVirtAddr);
 impl_addr_trait!(PTL4PageAddr, HasVirtAddr);
+impl_addr_trait!(PTL5PageAddr, HasVirtAddr);
 impl_aligned!(PageAddr, AlignedVirt, PAGE_ALIGNMENT);
 impl_aligned!(PTL2PageAddr, AlignedVirt, PTL2_ALIGNMENT);
 impl_aligned!(PTL3PageAddr, AlignedVirt, PTL3_ALIGNMENT);
-impl_aligned!(PTL4PageAddr, AlignedVirt, PTL3_ALIGNMENT);
+impl_aligned!(PTL4PageAddr, AlignedVirt, PTL4_ALIGNMENT);
+impl_aligned!(PTL5PageAddr, AlignedVirt, PTL5_ALIGNMENT);
 
 impl HasVirtAddr for usize {
     #[inline(always)]
     fn usize(&self) -> usize {
         let mut addr = *self;
         // sign extend to upper bits
-        if addr & (1 << (VIRT_ADDR_BITS - 1)) != 0 {
-            addr |= usize::MAX << VIRT_ADDR_BITS;
+        let bits = virt_addr_bits();
+        if addr & (1 << (bits - 1)) != 0 {
+            addr |= usize::MAX << bits;
         }
         addr
     }
@@ -1,5 +1,10 @@
-use super::{page::PAGE_SHIFT, AlignedPhys, FrameAddr, HasPhysAddr, HasVirtAddr, PageAddr};
+use super::{
+    five_level_paging, page::PAGE_SHIFT, virt_addr_bits, AlignedPhys, FrameAddr, HasPhysAddr,
+    HasVirtAddr, PageAddr,
+};
+use core::fmt::Display;
 use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
 use paste::paste;
 
 // 52 bits in a phys addr, but lower 12 are all 0
@@ -13,14 +18,22 @@ const L4_TABLE_ADDR: usize = 0x1000;
 // TODO should the present flag always be set?
 // something without it doesn't function as an entry
 pub const PRESENT_FLAG: usize = 1 << 0;
-const WRITABLE_FLAG: usize = 1 << 1;
-const SUPERVISOR_ONLY_FLAG: usize = 1 << 2;
-const WRITE_THROUGH_FLAG: usize = 1 << 3;
-const DISABLE_CACHE_FLAG: usize = 1 << 4;
-const ACCESSED_FLAG: usize = 1 << 5;
-const AVAILABLE_FLAG: usize = 1 << 6; // not used for anything in hardware, free for the os
-const LARGE_PAGE_FLAG: usize = 1 << 7;
-const EXEC_DISABLE_FLAG: usize = 1 << 63;
+pub const WRITABLE_FLAG: usize = 1 << 1;
+// set means user mode can access it too
+pub const USER_FLAG: usize = 1 << 2;
+pub const WRITE_THROUGH_FLAG: usize = 1 << 3;
+pub const DISABLE_CACHE_FLAG: usize = 1 << 4;
+pub const ACCESSED_FLAG: usize = 1 << 5;
+// not used for anything in hardware, free for the os
+const AVAILABLE_FLAG: usize = 1 << 6;
+// same bit, but in entries that map a page the cpu sets it when the page is written to
+pub const DIRTY_FLAG: usize = 1 << 6;
+pub const LARGE_PAGE_FLAG: usize = 1 << 7;
+// only in entries that map a page, the PAT bit moves for large pages
+pub const PAT_FLAG: usize = 1 << 7;
+pub const GLOBAL_FLAG: usize = 1 << 8;
+pub const LARGE_PAT_FLAG: usize = 1 << 12;
+pub const EXEC_DISABLE_FLAG: usize = 1 << 63;
 
 macro_rules! add_flag_methods {
     ($name:ident, $mask:ident) => {
@@ -95,6 +108,75 @@ macro_rules! make_flags_type {
     };
 }
 
+// the memory type a mapping gets, picked with the PAT, PCD and PWT bits
+// set by mm::pat once the PAT matches PAT_LAYOUT
+static PAT_PROGRAMMED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_pat_programmed() {
+    PAT_PROGRAMMED.store(true, Ordering::Relaxed);
+}
+
+pub fn pat_programmed() -> bool {
+    PAT_PROGRAMMED.load(Ordering::Relaxed)
+}
+
+// mm::pat programs the PAT to match PAT_LAYOUT
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CacheMode {
+    WriteBack,
+    WriteThrough,
+    UncachedMinus,
+    Uncached,
+    WriteCombining,
+}
+
+impl CacheMode {
+    // the first 4 are the power-on defaults, so entries from before the PAT is set keep their meaning
+    pub const PAT_LAYOUT: [CacheMode; 8] = [
+        Self::WriteBack,
+        Self::WriteThrough,
+        Self::UncachedMinus,
+        Self::Uncached,
+        Self::WriteCombining,
+        Self::WriteThrough,
+        Self::UncachedMinus,
+        Self::Uncached,
+    ];
+
+    // the PAT entry a mapping with this mode uses
+    // without the PAT, the upper 4 don't exist and the PAT bit is reserved, so they get UC- instead
+    pub fn pat_index(self) -> usize {
+        let index = Self::PAT_LAYOUT.iter().position(|&m| m == self).unwrap();
+        if index >= 4 && !pat_programmed() {
+            return Self::UncachedMinus.pat_index();
+        }
+        index
+    }
+
+    pub fn from_pat_index(index: usize) -> Self {
+        Self::PAT_LAYOUT[index % Self::PAT_LAYOUT.len()]
+    }
+
+    fn entry_bits(self, pat_flag: usize) -> usize {
+        let index = self.pat_index();
+        let bit = |n: usize, flag: usize| if index & (1 << n) != 0 { flag } else { 0 };
+        bit(0, WRITE_THROUGH_FLAG) | bit(1, DISABLE_CACHE_FLAG) | bit(2, pat_flag)
+    }
+}
+
+impl Display for CacheMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = match self {
+            Self::WriteBack => "WB",
+            Self::WriteThrough => "WT",
+            Self::UncachedMinus => "UC-",
+            Self::Uncached => "UC",
+            Self::WriteCombining => "WC",
+        };
+        f.pad(name)
+    }
+}
+
 #[derive(Copy, Clone)]
 #[repr(transparent)]
 pub struct Entry(usize);
@@ -136,6 +218,14 @@ impl Entry {
         self.set_frame_with_size(addr, PT_FRAME_BITS)
     }
 
+    // large is for entries that map a 2MiB or 1GiB page, the PAT bit is somewhere else in those
+    pub fn set_cache_mode(&mut self, mode: CacheMode, large: bool) -> Self {
+        let pat_flag = if large { LARGE_PAT_FLAG } else { PAT_FLAG };
+        self.0 &= !(WRITE_THROUGH_FLAG | DISABLE_CACHE_FLAG | pat_flag);
+        self.0 |= mode.entry_bits(pat_flag);
+        *self
+    }
+
     pub fn flags<F: Flags>(self) -> F {
         assert!(self.to_usize() & F::ALWAYS_SET == F::ALWAYS_SET);
         F::from_usize(self.to_usize())
@@ -174,14 +264,34 @@ pub const ENTRY_COUNT: usize = super::page::PAGE_SIZE / core::mem::size_of::<usi
 // ENTRY_COUNT * sizeof(GenericEntry) = PAGE_SIZE
 pub type PageTable = [MaybeUninit<Entry>; ENTRY_COUNT];
 
+// 4 or 5, depending on what boot.asm could turn on
+pub fn paging_levels() -> usize {
+    if five_level_paging() {
+        5
+    } else {
+        4
+    }
+}
+
+// l4 entries 0-255 are at the bottom of the address space and 256-511 are at the top,
+// which with 5 levels is in l5 entries 0 and 511
 pub fn pte_indices_to_addr(l4: usize, l3: usize, l2: usize, l1: usize) -> PageAddr {
     let addr = ((l4 << PTE_INDEX_SIZE * 3)
         + (l3 << PTE_INDEX_SIZE * 2)
         + (l2 << PTE_INDEX_SIZE * 1)
         + (l1 << PTE_INDEX_SIZE * 0))
         << PAGE_SHIFT;
-    // sign extend to the top 16 bits
-    addr.virt_addr().as_aligned()
+    sign_extend(addr, 48).virt_addr().as_aligned()
+}
+
+fn sign_extend(addr: usize, bits: usize) -> usize {
+    let unused_bits = usize::BITS as usize - bits;
+    ((addr << unused_bits) as isize >> unused_bits) as usize
+}
+
+// sign extends the top bit of the address to the unused top bits
+pub fn canonical(addr: usize) -> usize {
+    sign_extend(addr, virt_addr_bits())
 }
 
 pub fn addr_to_pte_indices(addr: PageAddr) -> (usize, usize, usize, usize) {
@@ -1 +1 @@
-pub mod align;
+pub use sparkle_lib::align;
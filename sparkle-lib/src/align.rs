@@ -42,7 +42,7 @@ impl Alignment {
     }
 
     pub fn align_up_offset(self, addr: usize) -> usize {
-        (addr.wrapping_add(self.mask())) & self.mask()
+        addr.wrapping_neg() & self.mask()
     }
 
     pub fn checked_align_up(self, addr: usize) -> Option<usize> {
@@ -0,0 +1,545 @@
+use core::ops::Range;
+
+use crate::align::Alignment;
+use crate::bump_alloc::BumpAllocator;
+use crate::frame_alloc::{
+    run_blocks, FitPolicy, FrameAllocator, FrameOrder, FrameStats, MAX_PAGE_ORDER,
+};
+
+// the frames are split into groups of 4^size frames, for every size up to one group covering
+// everything, and each group stores its avail: one more than the order of the biggest free block
+// in it, or 0 if it's full
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+struct GroupSize(u8);
+
+impl GroupSize {
+    // enough to hold max_avail, or Avail::max() for the big ones
+    fn bits(self) -> usize {
+        match self.0 {
+            0 => 1,
+            1 => 2,
+            2..=7 => 4,
+            _ => 8,
+        }
+    }
+
+    fn avails_per_entry(self) -> usize {
+        64 / self.bits()
+    }
+
+    fn idx_offset(self, idx: usize) -> (usize, usize) {
+        let entry_idx = idx / self.avails_per_entry();
+        let bit_offset = (idx % self.avails_per_entry()) * self.bits();
+        (entry_idx, bit_offset)
+    }
+
+    fn mask(self) -> u64 {
+        (1 << self.bits()) - 1
+    }
+
+    fn max_avail(self) -> Avail {
+        Avail(self.0 * 2 + 1)
+    }
+
+    fn sub_size(self) -> Self {
+        Self(self.0 - 1)
+    }
+
+    fn frame_shift(self) -> usize {
+        self.0 as usize * 2
+    }
+
+    // for groups too big to be a single block, this never matches; their avail is capped
+    fn is_fully_free(self, avail: Avail) -> bool {
+        self.0 * 2 <= MAX_PAGE_ORDER && avail == self.max_avail()
+    }
+
+    fn super_size(self) -> Self {
+        Self(self.0 + 1)
+    }
+}
+
+impl FrameOrder {
+    fn is_single_group(self) -> bool {
+        self.0 % 2 == 0
+    }
+
+    fn group_size(self) -> GroupSize {
+        GroupSize((self.0 + 1) / 2)
+    }
+
+    fn free_avail(self) -> Avail {
+        Avail(self.0 + 1)
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+struct Avail(u8);
+
+impl Avail {
+    fn empty() -> Self {
+        Self(0)
+    }
+    fn max() -> Self {
+        Self(MAX_PAGE_ORDER + 1)
+    }
+
+    fn two(&self) -> Self {
+        Self(self.0 + 1)
+    }
+
+    fn four(&self) -> Self {
+        Self(self.0 + 2)
+    }
+
+    fn merge(size: GroupSize, sub_avails: [Avail; 4]) -> Self {
+        use core::cmp::{max, min};
+        let sub_group_max_order = size.sub_size().max_avail();
+
+        let left_max = sub_avails[0] == sub_group_max_order && sub_avails[1] == sub_group_max_order;
+        let right_max =
+            sub_avails[2] == sub_group_max_order && sub_avails[3] == sub_group_max_order;
+        if left_max && right_max {
+            return min(sub_group_max_order.four(), Self::max());
+        } else if left_max || right_max {
+            return min(sub_group_max_order.two(), Self::max());
+        }
+        let max = max(
+            max(sub_avails[0], sub_avails[1]),
+            max(sub_avails[2], sub_avails[3]),
+        );
+        max
+    }
+}
+
+// covers frames base..base + frame_count
+// base is aligned to the biggest block, so blocks are aligned the same way locally and globally
+pub struct BitmapFrameAllocator {
+    group_maps: [&'static mut [u64]; 16],
+    max_group_size: GroupSize,
+    max_size_group_count: usize,
+    base: usize,
+    frame_count: usize,
+    fit: FitPolicy,
+}
+
+impl BitmapFrameAllocator {
+    // idx is in units of the order's size, starting from base
+    fn frame_at_idx(&self, order: FrameOrder, idx: usize) -> usize {
+        self.base + (idx << order.0)
+    }
+
+    fn idx_of_frame(&self, order: FrameOrder, frame: usize) -> usize {
+        (frame - self.base) >> order.0
+    }
+
+    // the part of a range of global frame indices that this covers, in local indices
+    fn local_range(&self, frames: Range<usize>) -> Range<usize> {
+        let end = self.base + self.frame_count;
+        let start = frames.start.clamp(self.base, end) - self.base;
+        let end = frames.end.clamp(self.base, end) - self.base;
+        start..core::cmp::max(start, end)
+    }
+
+    fn get_group_avail(&self, size: GroupSize, idx: usize) -> Avail {
+        let (entry_idx, bit_offset) = size.idx_offset(idx);
+        let res = ((self.group_maps[size.0 as usize][entry_idx] >> bit_offset) & size.mask()) as u8;
+        Avail(res)
+    }
+
+    fn set_group_avail(&mut self, size: GroupSize, idx: usize, avail: Avail) {
+        let (entry_idx, bit_offset) = size.idx_offset(idx);
+        self.group_maps[size.0 as usize][entry_idx] &= !(size.mask() << bit_offset);
+        self.group_maps[size.0 as usize][entry_idx] |= (avail.0 as u64) << bit_offset;
+    }
+
+    fn get_sub_group_avails(&self, size: GroupSize, idx: usize) -> [Avail; 4] {
+        [0, 1, 2, 3].map(|i| self.get_group_avail(size.sub_size(), 4 * idx + i))
+    }
+
+    fn update_group_avail(&mut self, size: GroupSize, idx: usize) -> bool {
+        let sub_avails = self.get_sub_group_avails(size, idx);
+        let avail = Avail::merge(size, sub_avails);
+        if avail != self.get_group_avail(size, idx) {
+            self.set_group_avail(size, idx, avail);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn update_all_parents(&mut self, mut size: GroupSize, mut idx: usize) {
+        while size < self.max_group_size {
+            size = size.super_size();
+            idx /= 4;
+            if !self.update_group_avail(size, idx) {
+                // if nothing changes, we're done
+                return;
+            }
+        }
+    }
+
+    fn pick_group(
+        &self,
+        size: GroupSize,
+        mut groups: impl Iterator<Item = usize>,
+        avail: Avail,
+    ) -> Option<usize> {
+        match self.fit {
+            FitPolicy::FirstFit => groups.find(|&i| self.get_group_avail(size, i) >= avail),
+            FitPolicy::BestFit => {
+                // the smallest avail that's still enough, so big free blocks don't get broken up
+                let mut best: Option<(Avail, usize)> = None;
+                for i in groups {
+                    let group_avail = self.get_group_avail(size, i);
+                    if group_avail < avail || best.is_some_and(|(b, _)| b <= group_avail) {
+                        continue;
+                    }
+                    best = Some((group_avail, i));
+                    if group_avail == avail {
+                        break;
+                    }
+                }
+                best.map(|(_, i)| i)
+            }
+        }
+    }
+
+    // searches from the top of memory down, so low memory stays free for whatever needs it
+    fn find_group_of_size_with_avail(&self, size: GroupSize, avail: Avail) -> Option<usize> {
+        if size > self.max_group_size {
+            return None;
+        }
+        let top_groups = (0..self.max_size_group_count).rev();
+        let mut curr_group_pos = self.pick_group(self.max_group_size, top_groups, avail)?;
+        let mut curr_size: GroupSize = self.max_group_size;
+        while curr_size > size {
+            curr_size = curr_size.sub_size();
+            let sub_groups = (0..4).rev().map(|i| 4 * curr_group_pos + i);
+            curr_group_pos = self.pick_group(curr_size, sub_groups, avail)?;
+        }
+        Some(curr_group_pos)
+    }
+
+    // marks a free block as used, idx is in units of the block's size
+    fn take_block(&mut self, idx: usize, order: FrameOrder) {
+        if order.is_single_group() {
+            let size = order.group_size();
+            self.set_group_avail(size, idx, Avail::empty());
+            self.update_all_parents(size, idx);
+        } else {
+            let size = order.group_size().sub_size();
+            let group_idx = idx * 2;
+            self.set_group_avail(size, group_idx, Avail::empty());
+            self.set_group_avail(size, group_idx + 1, Avail::empty());
+            self.update_all_parents(size, group_idx);
+            self.update_all_parents(size, group_idx + 1);
+        }
+    }
+
+    // finds a free block of the order that's entirely inside frames, in the group or its sub-groups
+    // groups that don't overlap frames or don't have enough avail are skipped without looking inside
+    fn find_block_in(
+        &self,
+        size: GroupSize,
+        idx: usize,
+        order: FrameOrder,
+        frames: &Range<usize>,
+    ) -> Option<usize> {
+        let group = idx << size.frame_shift()..(idx + 1) << size.frame_shift();
+        if group.end <= frames.start || frames.end <= group.start {
+            return None;
+        }
+        if self.get_group_avail(size, idx) < order.free_avail() {
+            return None;
+        }
+
+        if size > order.group_size() {
+            return (0..4)
+                .rev()
+                .find_map(|i| self.find_block_in(size.sub_size(), 4 * idx + i, order, frames));
+        }
+
+        if order.is_single_group() {
+            let inside = frames.start <= group.start && group.end <= frames.end;
+            return inside.then_some(idx);
+        }
+        let half_size = size.sub_size();
+        let sub_avails = self.get_sub_group_avails(size, idx);
+        [1, 0].into_iter().find_map(|half| {
+            let start = group.start + (half << order.0);
+            let inside = frames.start <= start && start + (1 << order.0) <= frames.end;
+            let free = sub_avails[2 * half] == half_size.max_avail()
+                && sub_avails[2 * half + 1] == half_size.max_avail();
+            (inside && free).then_some(2 * idx + half)
+        })
+    }
+
+    // finds the biggest group starting at or containing frame_idx that's either all free or all used
+    // lower levels are stale under an allocated block, so this has to go top-down
+    fn uniform_group_at(&self, frame_idx: usize) -> (GroupSize, bool) {
+        let mut size = self.max_group_size;
+        loop {
+            let avail = self.get_group_avail(size, frame_idx >> size.frame_shift());
+            if avail == Avail::empty() {
+                return (size, false);
+            }
+            if size.is_fully_free(avail) {
+                return (size, true);
+            }
+            size = size.sub_size();
+        }
+    }
+
+    // finds the first run of count free frames that starts at a multiple of align
+    fn find_free_run(&self, count: usize, align: Alignment) -> Option<usize> {
+        let mut run_start = 0;
+        let mut pos = 0;
+        while pos - run_start < count {
+            if pos >= self.frame_count {
+                return None;
+            }
+            let (size, free) = self.uniform_group_at(pos);
+            let group_end = ((pos >> size.frame_shift()) + 1) << size.frame_shift();
+            if free {
+                pos = group_end;
+            } else {
+                run_start = align.align_up(group_end);
+                pos = run_start;
+            }
+        }
+        Some(run_start)
+    }
+
+    // masks for the bits of each entry in group_maps[0] that the range covers
+    fn frame_range_masks(range: Range<usize>) -> impl Iterator<Item = (usize, u64)> {
+        let mut pos = range.start;
+        core::iter::from_fn(move || {
+            if pos >= range.end {
+                return None;
+            }
+            let bit = pos % 64;
+            let len = core::cmp::min(64 - bit, range.end - pos);
+            let mask = if len == 64 {
+                u64::MAX
+            } else {
+                ((1 << len) - 1) << bit
+            };
+            let res = (pos / 64, mask);
+            pos += len;
+            Some(res)
+        })
+    }
+
+    fn free_frame_range(&mut self, range: Range<usize>) {
+        for (entry_idx, mask) in Self::frame_range_masks(range) {
+            self.group_maps[0][entry_idx] |= mask;
+        }
+    }
+
+    fn count_free_blocks(&self, size: GroupSize, idx: usize, stats: &mut FrameStats) {
+        let avail = self.get_group_avail(size, idx);
+        if avail == Avail::empty() {
+            return;
+        }
+        let order = size.0 * 2;
+        if order <= MAX_PAGE_ORDER && avail == size.max_avail() {
+            stats.add_block(order);
+            return;
+        }
+
+        let sub_size = size.sub_size();
+        let sub_avails = self.get_sub_group_avails(size, idx);
+        for half in 0..2 {
+            let half_free = sub_avails[2 * half] == sub_size.max_avail()
+                && sub_avails[2 * half + 1] == sub_size.max_avail();
+            if order - 1 <= MAX_PAGE_ORDER && half_free {
+                stats.add_block(order - 1);
+            } else {
+                self.count_free_blocks(sub_size, 4 * idx + 2 * half, stats);
+                self.count_free_blocks(sub_size, 4 * idx + 2 * half + 1, stats);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct GroupSizesIter {
+    group_count: usize,
+    group_size: usize,
+}
+
+impl GroupSizesIter {
+    fn new(frame_count: usize) -> Self {
+        Self {
+            group_count: frame_count,
+            group_size: 0,
+        }
+    }
+}
+
+impl Iterator for GroupSizesIter {
+    type Item = (usize, usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.group_count == 0 || self.group_size == 16 {
+            return None;
+        }
+
+        let bits_per_group = GroupSize(self.group_size as u8).bits();
+        let entry_count = (bits_per_group * self.group_count + 63) / 64;
+        let res = (self.group_size, self.group_count, entry_count);
+
+        if self.group_count == 1 {
+            // a single group covers everything, no point going bigger
+            self.group_count = 0;
+        } else {
+            self.group_size += 1;
+            self.group_count = (self.group_count + 3) / 4;
+        }
+
+        Some(res)
+    }
+}
+
+fn get_bitmap_size(frame_count: usize) -> usize {
+    let groups = GroupSizesIter::new(frame_count);
+    groups.map(|(_, _, entry_count)| entry_count).sum()
+}
+
+impl FrameAllocator for BitmapFrameAllocator {
+    fn bookkeeping_size(frame_count: usize) -> usize {
+        get_bitmap_size(frame_count) * core::mem::size_of::<u64>()
+    }
+
+    fn new(map_alloc: &mut BumpAllocator<'static>, span: Range<usize>) -> Self {
+        // like [None; 16], but that doesn't work since &mut isn't Copy
+        let mut group_maps: [&'static mut [u64]; 16] = [(); 16].map(|_| &mut [][..]);
+        let mut max_group_size = 0;
+        let mut max_size_group_count = 0;
+
+        let groups = GroupSizesIter::new(span.len());
+        for (group_size, group_count, entry_count) in groups {
+            // initialize with 0, which means not free
+            group_maps[group_size] = map_alloc.alloc_slice_default(entry_count);
+
+            max_group_size = group_size;
+            max_size_group_count = group_count;
+        }
+
+        Self {
+            group_maps,
+            max_group_size: GroupSize(max_group_size as u8),
+            max_size_group_count,
+            base: span.start,
+            frame_count: span.len(),
+            fit: FitPolicy::BestFit,
+        }
+    }
+
+    fn span(&self) -> Range<usize> {
+        self.base..self.base + self.frame_count
+    }
+
+    // update_all would be wrong after init, since the sub-groups of allocated blocks are stale
+    // so this only updates the groups that overlap the range
+    fn add_free_range(&mut self, frames: Range<usize>) -> usize {
+        let range = self.local_range(frames);
+        if range.is_empty() {
+            return 0;
+        }
+        self.free_frame_range(range.clone());
+        for size in 1..=self.max_group_size.0 {
+            let size = GroupSize(size);
+            let groups = range.start >> size.frame_shift()..=(range.end - 1) >> size.frame_shift();
+            for idx in groups {
+                self.update_group_avail(size, idx);
+            }
+        }
+        range.len()
+    }
+
+    fn alloc(&mut self, order: FrameOrder) -> Option<usize> {
+        let size = order.group_size();
+        let idx = self.find_group_of_size_with_avail(size, order.free_avail())?;
+
+        let frame_idx = if order.is_single_group() {
+            idx
+        } else {
+            let req_sub_avail = size.sub_size().max_avail();
+            let sub_avails = self.get_sub_group_avails(size, idx);
+            let sub_idx = if sub_avails[2] == req_sub_avail && sub_avails[3] == req_sub_avail {
+                1
+            } else {
+                debug_assert!(sub_avails[0] == req_sub_avail && sub_avails[1] == req_sub_avail);
+                0
+            };
+            idx * 2 + sub_idx
+        };
+        self.take_block(frame_idx, order);
+        Some(self.frame_at_idx(order, frame_idx))
+    }
+
+    fn free(&mut self, frame: usize, order: FrameOrder) {
+        if order.is_single_group() {
+            let size = order.group_size();
+
+            // update the avail of the page's group
+            let idx = self.idx_of_frame(order, frame);
+            self.set_group_avail(size, idx, order.free_avail());
+
+            // update the avail of the groups containing it
+            self.update_all_parents(size, idx);
+        } else {
+            let size = order.group_size().sub_size();
+
+            let frame_idx = self.idx_of_frame(order, frame);
+            let group_idx = frame_idx * 2;
+            // the two halves are each fully free groups
+            self.set_group_avail(size, group_idx, size.max_avail());
+            self.set_group_avail(size, group_idx + 1, size.max_avail());
+            self.update_all_parents(size, group_idx);
+            self.update_all_parents(size, group_idx + 1);
+        }
+    }
+
+    fn alloc_in(&mut self, frames: Range<usize>, order: FrameOrder) -> Option<usize> {
+        if order.group_size() > self.max_group_size {
+            return None;
+        }
+        let frames = self.local_range(frames);
+        let idx = (0..self.max_size_group_count)
+            .rev()
+            .find_map(|i| self.find_block_in(self.max_group_size, i, order, &frames))?;
+        self.take_block(idx, order);
+        Some(self.frame_at_idx(order, idx))
+    }
+
+    fn alloc_run(&mut self, count: usize, align: Alignment) -> Option<usize> {
+        let start = self.find_free_run(count, align)?;
+        for (idx, order) in run_blocks(start..start + count) {
+            self.take_block(idx, order);
+        }
+        Some(self.frame_at_idx(FrameOrder(0), start))
+    }
+
+    fn free_run(&mut self, frame: usize, count: usize) {
+        let start = self.idx_of_frame(FrameOrder(0), frame);
+        for (idx, order) in run_blocks(start..start + count) {
+            self.free(self.frame_at_idx(order, idx), order);
+        }
+    }
+
+    fn stats(&self) -> FrameStats {
+        let mut stats = FrameStats::empty();
+        for idx in 0..self.max_size_group_count {
+            self.count_free_blocks(self.max_group_size, idx, &mut stats);
+        }
+        stats
+    }
+
+    fn set_fit_policy(&mut self, fit: FitPolicy) -> FitPolicy {
+        core::mem::replace(&mut self.fit, fit)
+    }
+}
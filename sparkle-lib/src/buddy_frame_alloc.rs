@@ -0,0 +1,218 @@
+use core::ops::Range;
+
+use crate::align::Alignment;
+use crate::bump_alloc::BumpAllocator;
+use crate::frame_alloc::{run_blocks, FrameAllocator, FrameOrder, FrameStats, MAX_PAGE_ORDER};
+
+// the textbook version: a free list per order, and a freed block merges with its buddy if that's free
+// the list links live in an array next to the frames instead of in them,
+// since most of physical memory isn't mapped
+
+// end of a list
+const NONE: u32 = u32::MAX;
+// for frames that don't start a free block
+const NOT_FREE: u8 = u8::MAX;
+
+#[derive(Clone, Copy)]
+struct BuddyEntry {
+    prev: u32,
+    next: u32,
+    free_order: u8,
+}
+
+impl Default for BuddyEntry {
+    fn default() -> Self {
+        Self {
+            prev: NONE,
+            next: NONE,
+            free_order: NOT_FREE,
+        }
+    }
+}
+
+pub struct BuddyFrameAllocator {
+    // one per frame, by local frame index
+    entries: &'static mut [BuddyEntry],
+    heads: [u32; MAX_PAGE_ORDER as usize + 1],
+    counts: [usize; MAX_PAGE_ORDER as usize + 1],
+    base: usize,
+}
+
+impl BuddyFrameAllocator {
+    fn frame_at(&self, idx: usize) -> usize {
+        self.base + idx
+    }
+
+    fn idx_of(&self, frame: usize) -> usize {
+        frame - self.base
+    }
+
+    // the part of a range of global frame indices that this covers, in local indices
+    fn local_range(&self, frames: Range<usize>) -> Range<usize> {
+        let end = self.base + self.entries.len();
+        let start = frames.start.clamp(self.base, end) - self.base;
+        let end = frames.end.clamp(self.base, end) - self.base;
+        start..core::cmp::max(start, end)
+    }
+
+    fn push(&mut self, idx: usize, order: u8) {
+        let head = self.heads[order as usize];
+        self.entries[idx] = BuddyEntry {
+            prev: NONE,
+            next: head,
+            free_order: order,
+        };
+        if head != NONE {
+            self.entries[head as usize].prev = idx as u32;
+        }
+        self.heads[order as usize] = idx as u32;
+        self.counts[order as usize] += 1;
+    }
+
+    fn remove(&mut self, idx: usize) {
+        let BuddyEntry {
+            prev,
+            next,
+            free_order,
+        } = self.entries[idx];
+        debug_assert!(free_order != NOT_FREE);
+        if prev == NONE {
+            self.heads[free_order as usize] = next;
+        } else {
+            self.entries[prev as usize].next = next;
+        }
+        if next != NONE {
+            self.entries[next as usize].prev = prev;
+        }
+        self.entries[idx] = BuddyEntry::default();
+        self.counts[free_order as usize] -= 1;
+    }
+
+    // takes a free block off its list, and puts back everything in it but the block of order at target
+    fn split_off(&mut self, block: usize, block_order: u8, target: usize, order: u8) {
+        self.remove(block);
+        let mut start = block;
+        for half_order in (order..block_order).rev() {
+            let half = 1 << half_order;
+            if target < start + half {
+                self.push(start + half, half_order);
+            } else {
+                self.push(start, half_order);
+                start += half;
+            }
+        }
+        debug_assert!(start == target);
+    }
+
+    fn free_block(&mut self, mut idx: usize, mut order: u8) {
+        debug_assert!(self.entries[idx].free_order == NOT_FREE, "double free");
+        while order < MAX_PAGE_ORDER {
+            let buddy = idx ^ (1 << order);
+            // only whole blocks are ever on the lists, so this is enough to know the buddy is free
+            if buddy >= self.entries.len() || self.entries[buddy].free_order != order {
+                break;
+            }
+            self.remove(buddy);
+            idx &= !(1 << order);
+            order += 1;
+        }
+        self.push(idx, order);
+    }
+}
+
+impl FrameAllocator for BuddyFrameAllocator {
+    fn bookkeeping_size(frame_count: usize) -> usize {
+        frame_count * core::mem::size_of::<BuddyEntry>() + core::mem::align_of::<BuddyEntry>()
+    }
+
+    fn new(map_alloc: &mut BumpAllocator<'static>, span: Range<usize>) -> Self {
+        // the links are u32s
+        assert!(span.len() < NONE as usize);
+        Self {
+            entries: map_alloc.alloc_slice_default(span.len()),
+            heads: [NONE; MAX_PAGE_ORDER as usize + 1],
+            counts: [0; MAX_PAGE_ORDER as usize + 1],
+            base: span.start,
+        }
+    }
+
+    fn span(&self) -> Range<usize> {
+        self.base..self.base + self.entries.len()
+    }
+
+    fn add_free_range(&mut self, frames: Range<usize>) -> usize {
+        let range = self.local_range(frames);
+        for (idx, order) in run_blocks(range.clone()) {
+            self.free_block(idx << order.0, order.0);
+        }
+        range.len()
+    }
+
+    // takes the first block off the smallest list that has one, and splits it down
+    fn alloc(&mut self, order: FrameOrder) -> Option<usize> {
+        let block_order = (order.0..=MAX_PAGE_ORDER).find(|&o| self.heads[o as usize] != NONE)?;
+        let block = self.heads[block_order as usize] as usize;
+        self.split_off(block, block_order, block, order.0);
+        Some(self.frame_at(block))
+    }
+
+    // the lists aren't sorted by address, so this has to look at every free block
+    fn alloc_in(&mut self, frames: Range<usize>, order: FrameOrder) -> Option<usize> {
+        if order.0 > MAX_PAGE_ORDER {
+            return None;
+        }
+        let frames = self.local_range(frames);
+        let size = Alignment::new(1 << order.0);
+        for block_order in order.0..=MAX_PAGE_ORDER {
+            let mut next = self.heads[block_order as usize];
+            while next != NONE {
+                let block = next as usize;
+                next = self.entries[block].next;
+
+                // the highest aligned spot that's in both the block and frames
+                let top = core::cmp::min(block + (1 << block_order), frames.end);
+                if top < size.as_usize() {
+                    continue;
+                }
+                let target = size.align_down(top - size.as_usize());
+                if target >= core::cmp::max(block, frames.start) {
+                    self.split_off(block, block_order, target, order.0);
+                    return Some(self.frame_at(target));
+                }
+            }
+        }
+        None
+    }
+
+    // a whole power of two block is allocated and the tail is given back,
+    // so this needs a bigger free block than count alone would
+    fn alloc_run(&mut self, count: usize, align: Alignment) -> Option<usize> {
+        let order = core::cmp::max(count.next_power_of_two(), align.as_usize()).ilog2() as u8;
+        let frame = self.alloc(FrameOrder(order))?;
+        let start = self.idx_of(frame);
+        for (idx, block_order) in run_blocks(start + count..start + (1 << order)) {
+            self.free_block(idx << block_order.0, block_order.0);
+        }
+        Some(self.frame_at(start))
+    }
+
+    fn free(&mut self, frame: usize, order: FrameOrder) {
+        self.free_block(self.idx_of(frame), order.0);
+    }
+
+    fn free_run(&mut self, frame: usize, count: usize) {
+        let start = self.idx_of(frame);
+        for (idx, order) in run_blocks(start..start + count) {
+            self.free_block(idx << order.0, order.0);
+        }
+    }
+
+    fn stats(&self) -> FrameStats {
+        let mut stats = FrameStats::empty();
+        for (order, &count) in self.counts.iter().enumerate() {
+            stats.free_blocks[order] = count;
+            stats.free_frames += count << order;
+        }
+        stats
+    }
+}
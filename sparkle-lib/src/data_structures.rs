@@ -0,0 +1,12 @@
+pub mod atomic_stack;
+pub mod intrusive_dlist;
+pub mod intrusive_list;
+pub mod intrusive_tree;
+pub mod mpsc_queue;
+pub mod radix_tree;
+mod slot;
+mod storage;
+pub mod tagged;
+
+pub use slot::{IsSlotOf, ObjectSlot};
+pub use storage::{AtomicStore, Store, Stores};
@@ -0,0 +1,103 @@
+use core::mem::ManuallyDrop;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::tagged::{untag_raw, Tagged};
+use super::{AtomicStore, IsSlotOf, Stores};
+
+// like ListLink, but the link can be read while other CPUs are modifying it
+// the link is stored as an address in a usize, 0 meaning None
+pub trait AtomicListLink: Sized {
+    type Slot: IsSlotOf<Self, Value = AtomicStore<AtomicUsize, Option<Self>>>;
+}
+
+// reads the link out of a stored item without taking ownership of it
+// Safety: raw must be a stored Some(item), and the item's memory must still be valid
+unsafe fn next_raw_of<Link: AtomicListLink>(raw: usize, order: Ordering) -> usize
+where
+    usize: Stores<Option<Link>>,
+{
+    let item = ManuallyDrop::new(<usize as Stores<Option<Link>>>::extract(raw));
+    Link::Slot::get(item.as_ref().unwrap()).load_raw(order)
+}
+
+// treiber stack
+// popped items may still be read by other CPUs that are in the middle of a pop
+// so their memory has to stay mapped (slab objects are fine, freed frames aren't)
+// the head is tagged against ABA, see tagged.rs for how much that protects
+pub struct AtomicStack<Link> {
+    head: AtomicStore<AtomicUsize, Tagged<Option<Link>>>,
+}
+
+unsafe impl<Link: Send> Sync for AtomicStack<Link> {}
+
+impl<Link: AtomicListLink> AtomicStack<Link>
+where
+    usize: Stores<Option<Link>>,
+{
+    pub const fn empty() -> Self {
+        Self {
+            // no item and tag 0
+            head: unsafe { AtomicStore::from_raw(0) },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        untag_raw(self.head.load_raw(Ordering::Relaxed)) == 0
+    }
+
+    pub fn push(&self, mut item: Link) {
+        let mut old = self.head.load_raw(Ordering::Relaxed);
+        loop {
+            // item isn't visible to anyone else yet, so a plain store is fine
+            unsafe { Link::Slot::get(&item).store_raw(untag_raw(old), Ordering::Relaxed) };
+            match self.head.compare_exchange_weak(
+                old,
+                Tagged::after(old, Some(item)),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(old_head) => {
+                    // the old head is owned by item's link now
+                    core::mem::forget(old_head);
+                    return;
+                }
+                Err((curr, new)) => {
+                    old = curr;
+                    item = new.val.unwrap();
+                }
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<Link> {
+        let mut old = self.head.load_raw(Ordering::Acquire);
+        loop {
+            if untag_raw(old) == 0 {
+                return None;
+            }
+
+            // the head might get popped (and pushed again) by someone else while we read it
+            // if that happens, the tag changed and the cas fails
+            let next_raw = unsafe { next_raw_of::<Link>(untag_raw(old), Ordering::Relaxed) };
+            let next = unsafe { <usize as Stores<Option<Link>>>::extract(next_raw) };
+            match self.head.compare_exchange_weak(
+                old,
+                Tagged::after(old, next),
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(old_head) => {
+                    let mut item = old_head.val.unwrap();
+                    // don't hand out items with their link still set
+                    core::mem::forget(Link::Slot::get_mut(&mut item).swap(None, Ordering::Relaxed));
+                    return Some(item);
+                }
+                Err((curr, new)) => {
+                    // new.val is still owned by the stack
+                    core::mem::forget(new);
+                    old = curr;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use core::cell::Cell;
+use core::marker::PhantomPinned;
+use core::mem::ManuallyDrop;
+use core::ops::DerefMut;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{IsSlotOf, Stores};
+
+// the prev and next links of an item in an IntrusiveDList
+// items have to be reachable from both of their neighbors, so the links are stored raw
+// they're only ever changed by the list the item is in
+pub struct DListLinks<Raw> {
+    prev: Cell<Option<Raw>>,
+    next: Cell<Option<Raw>>,
+    // the id of the list the item is in, 0 if it isn't in one
+    // atomic so other lists can check it without racing with the one that owns the item
+    list: AtomicUsize,
+    // the item's neighbors point at it, so it can't move while it's in a list
+    _pinned: PhantomPinned,
+}
+
+impl<Raw> DListLinks<Raw> {
+    pub const fn new() -> Self {
+        Self {
+            prev: Cell::new(None),
+            next: Cell::new(None),
+            list: AtomicUsize::new(0),
+            _pinned: PhantomPinned,
+        }
+    }
+
+    pub fn is_linked(&self) -> bool {
+        self.list.load(Ordering::Relaxed) != 0
+    }
+}
+
+impl<Raw> Default for DListLinks<Raw> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Link has to be a pointer-like type, and its links have to be stored in the pointee,
+// not in the Link value itself
+pub trait DListLink: Sized {
+    type Slot: IsSlotOf<Self, Value = DListLinks<Self::Raw>>;
+    type Raw: Stores<Self>;
+}
+
+// 0 is for items that aren't in a list
+static NEXT_LIST_ID: AtomicUsize = AtomicUsize::new(1);
+
+fn into_raw<Link: DListLink>(item: Link) -> Link::Raw {
+    let raw = unsafe { Link::Raw::store(&item) };
+    core::mem::forget(item);
+    raw
+}
+
+// Safety: raw must be a stored Link that's owned by a list
+unsafe fn alias_of<Link: DListLink>(raw: Link::Raw) -> ManuallyDrop<Link> {
+    ManuallyDrop::new(Link::Raw::extract(raw))
+}
+
+// Safety: raw must be a stored Link that's owned by a list
+unsafe fn links_of<'a, Link: DListLink>(raw: Link::Raw) -> &'a DListLinks<Link::Raw> {
+    let item = alias_of::<Link>(raw);
+    // points into the item's pointee, so it outlives the alias
+    &*(Link::Slot::get(&item) as *const DListLinks<Link::Raw>)
+}
+
+pub struct IntrusiveDList<Link: DListLink> {
+    head: Option<Link::Raw>,
+    tail: Option<Link::Raw>,
+    len: usize,
+    id: usize,
+}
+
+impl<Link: DListLink> IntrusiveDList<Link> {
+    pub fn empty() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            len: 0,
+            id: NEXT_LIST_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    pub fn contains(&self, item: &Link) -> bool {
+        Link::Slot::get(item).list.load(Ordering::Relaxed) == self.id
+    }
+
+    // marks item as being in this list
+    fn claim(&self, item: Link) -> Link::Raw {
+        let claimed = Link::Slot::get(&item).list.compare_exchange(
+            0,
+            self.id,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+        assert!(claimed.is_ok(), "item is already in a list");
+        into_raw(item)
+    }
+
+    // puts the chain first..=last between prev and next
+    // None means the start or end of the list
+    unsafe fn link_between(
+        &mut self,
+        prev: Option<Link::Raw>,
+        next: Option<Link::Raw>,
+        first: Link::Raw,
+        last: Link::Raw,
+        count: usize,
+    ) {
+        links_of::<Link>(first).prev.set(prev);
+        links_of::<Link>(last).next.set(next);
+        match prev {
+            Some(prev) => links_of::<Link>(prev).next.set(Some(first)),
+            None => self.head = Some(first),
+        }
+        match next {
+            Some(next) => links_of::<Link>(next).prev.set(Some(last)),
+            None => self.tail = Some(last),
+        }
+        self.len += count;
+    }
+
+    // Safety: raw must be in this list
+    unsafe fn unlink(&mut self, raw: Link::Raw) -> Link {
+        let links = links_of::<Link>(raw);
+        let prev = links.prev.take();
+        let next = links.next.take();
+        match prev {
+            Some(prev) => links_of::<Link>(prev).next.set(next),
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => links_of::<Link>(next).prev.set(prev),
+            None => self.tail = prev,
+        }
+        self.len -= 1;
+        links.list.store(0, Ordering::Relaxed);
+        Link::Raw::extract(raw)
+    }
+
+    // takes other's items, marking them as being in this list
+    // that's a walk over all of them, so splicing is O(n) in other's length
+    fn take_all_of(&self, other: &mut Self) -> Option<(Link::Raw, Link::Raw, usize)> {
+        let first = other.head.take()?;
+        let last = other.tail.take().unwrap();
+        let count = core::mem::replace(&mut other.len, 0);
+        let mut curr = Some(first);
+        while let Some(raw) = curr {
+            let links = unsafe { links_of::<Link>(raw) };
+            links.list.store(self.id, Ordering::Relaxed);
+            curr = links.next.get();
+        }
+        Some((first, last, count))
+    }
+
+    pub fn push_front(&mut self, item: Link) {
+        let raw = self.claim(item);
+        unsafe { self.link_between(None, self.head, raw, raw, 1) };
+    }
+
+    pub fn push_back(&mut self, item: Link) {
+        let raw = self.claim(item);
+        unsafe { self.link_between(self.tail, None, raw, raw, 1) };
+    }
+
+    pub fn pop_front(&mut self) -> Option<Link> {
+        let head = self.head?;
+        Some(unsafe { self.unlink(head) })
+    }
+
+    pub fn pop_back(&mut self) -> Option<Link> {
+        let tail = self.tail?;
+        Some(unsafe { self.unlink(tail) })
+    }
+
+    // O(1), uses the item's own links to find its neighbors
+    // None if item isn't in this list
+    pub fn remove(&mut self, item: &Link) -> Option<Link> {
+        if !self.contains(item) {
+            return None;
+        }
+        Some(unsafe { self.unlink(Link::Raw::store(item)) })
+    }
+
+    // moves all of other's items to the end of this list
+    pub fn splice_back(&mut self, other: &mut Self) {
+        if let Some((first, last, count)) = self.take_all_of(other) {
+            unsafe { self.link_between(self.tail, None, first, last, count) };
+        }
+    }
+
+    // moves all of other's items to the start of this list
+    pub fn splice_front(&mut self, other: &mut Self) {
+        if let Some((first, last, count)) = self.take_all_of(other) {
+            unsafe { self.link_between(None, self.head, first, last, count) };
+        }
+    }
+
+    pub fn cursor_front(&self) -> Cursor<'_, Link> {
+        Cursor::new(self, self.head)
+    }
+
+    pub fn cursor_back(&self) -> Cursor<'_, Link> {
+        Cursor::new(self, self.tail)
+    }
+
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, Link> {
+        let head = self.head;
+        CursorMut::new(self, head)
+    }
+
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, Link> {
+        let tail = self.tail;
+        CursorMut::new(self, tail)
+    }
+
+    // None if item isn't in this list
+    pub fn cursor_at_mut(&mut self, item: &Link) -> Option<CursorMut<'_, Link>> {
+        if !self.contains(item) {
+            return None;
+        }
+        let raw = unsafe { Link::Raw::store(item) };
+        Some(CursorMut::new(self, Some(raw)))
+    }
+}
+
+// the items get dropped, and they can go in another list if they're still around
+impl<Link: DListLink> Drop for IntrusiveDList<Link> {
+    fn drop(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+}
+
+// cursors point at an item, or at the "ghost" position between the tail and the head
+// moving past either end of the list goes to the ghost, and moving again wraps around
+pub struct Cursor<'a, Link: DListLink> {
+    list: &'a IntrusiveDList<Link>,
+    curr: Option<Link::Raw>,
+    item: Option<ManuallyDrop<Link>>,
+}
+
+impl<'a, Link: DListLink> Cursor<'a, Link> {
+    fn new(list: &'a IntrusiveDList<Link>, curr: Option<Link::Raw>) -> Self {
+        let mut res = Self {
+            list,
+            curr: None,
+            item: None,
+        };
+        res.set_curr(curr);
+        res
+    }
+
+    fn set_curr(&mut self, curr: Option<Link::Raw>) {
+        self.curr = curr;
+        self.item = curr.map(|raw| unsafe { alias_of::<Link>(raw) });
+    }
+
+    pub fn current(&self) -> Option<&Link> {
+        self.item.as_deref()
+    }
+
+    pub fn move_next(&mut self) {
+        let next = match self.curr {
+            Some(curr) => unsafe { links_of::<Link>(curr).next.get() },
+            None => self.list.head,
+        };
+        self.set_curr(next);
+    }
+
+    pub fn move_prev(&mut self) {
+        let prev = match self.curr {
+            Some(curr) => unsafe { links_of::<Link>(curr).prev.get() },
+            None => self.list.tail,
+        };
+        self.set_curr(prev);
+    }
+}
+
+pub struct CursorMut<'a, Link: DListLink> {
+    list: &'a mut IntrusiveDList<Link>,
+    curr: Option<Link::Raw>,
+    item: Option<ManuallyDrop<Link>>,
+}
+
+impl<'a, Link: DListLink> CursorMut<'a, Link> {
+    fn new(list: &'a mut IntrusiveDList<Link>, curr: Option<Link::Raw>) -> Self {
+        let mut res = Self {
+            list,
+            curr: None,
+            item: None,
+        };
+        res.set_curr(curr);
+        res
+    }
+
+    fn set_curr(&mut self, curr: Option<Link::Raw>) {
+        self.curr = curr;
+        self.item = curr.map(|raw| unsafe { alias_of::<Link>(raw) });
+    }
+
+    fn next_raw(&self) -> Option<Link::Raw> {
+        match self.curr {
+            Some(curr) => unsafe { links_of::<Link>(curr).next.get() },
+            None => self.list.head,
+        }
+    }
+
+    fn prev_raw(&self) -> Option<Link::Raw> {
+        match self.curr {
+            Some(curr) => unsafe { links_of::<Link>(curr).prev.get() },
+            None => self.list.tail,
+        }
+    }
+
+    // the Link itself is shared, swapping it out would leave the list with a dangling item
+    pub fn current(&self) -> Option<&Link> {
+        self.item.as_deref()
+    }
+
+    // pinned, since moving the item would break its neighbors' links
+    pub fn current_mut(&mut self) -> Option<Pin<&mut Link::Target>>
+    where
+        Link: DerefMut,
+    {
+        let item = self.item.as_deref_mut()?;
+        Some(unsafe { Pin::new_unchecked(&mut **item) })
+    }
+
+    pub fn move_next(&mut self) {
+        self.set_curr(self.next_raw());
+    }
+
+    pub fn move_prev(&mut self) {
+        self.set_curr(self.prev_raw());
+    }
+
+    // removes the current item and moves to the one after it
+    pub fn remove_current(&mut self) -> Option<Link> {
+        let curr = self.curr?;
+        let next = self.next_raw();
+        self.set_curr(next);
+        Some(unsafe { self.list.unlink(curr) })
+    }
+
+    // at the ghost position, this inserts at the front
+    pub fn insert_after(&mut self, item: Link) {
+        let raw = self.list.claim(item);
+        let next = self.next_raw();
+        unsafe { self.list.link_between(self.curr, next, raw, raw, 1) };
+    }
+
+    // at the ghost position, this inserts at the back
+    pub fn insert_before(&mut self, item: Link) {
+        let raw = self.list.claim(item);
+        let prev = self.prev_raw();
+        unsafe { self.list.link_between(prev, self.curr, raw, raw, 1) };
+    }
+
+    pub fn splice_after(&mut self, other: &mut IntrusiveDList<Link>) {
+        if let Some((first, last, count)) = self.list.take_all_of(other) {
+            let next = self.next_raw();
+            unsafe { self.list.link_between(self.curr, next, first, last, count) };
+        }
+    }
+
+    pub fn splice_before(&mut self, other: &mut IntrusiveDList<Link>) {
+        if let Some((first, last, count)) = self.list.take_all_of(other) {
+            let prev = self.prev_raw();
+            unsafe { self.list.link_between(prev, self.curr, first, last, count) };
+        }
+    }
+}
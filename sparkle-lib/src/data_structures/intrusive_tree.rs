@@ -0,0 +1,523 @@
+use core::cmp::{max, min};
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, Range};
+
+use super::{IsSlotOf, Stores};
+
+// AVL tree of items ordered by the start of their range
+// each node also keeps some info about its subtree, so free gaps between ranges can be found quickly
+pub struct TreeLinks<Raw> {
+    parent: Option<Raw>,
+    left: Option<Raw>,
+    right: Option<Raw>,
+    height: u8,
+    // lowest start and highest end of any range in the subtree
+    min_start: usize,
+    max_end: usize,
+    // largest gap between consecutive ranges in the subtree
+    max_gap: usize,
+}
+
+impl<Raw> TreeLinks<Raw> {
+    pub const fn new() -> Self {
+        Self {
+            parent: None,
+            left: None,
+            right: None,
+            height: 0,
+            min_start: 0,
+            max_end: 0,
+            max_gap: 0,
+        }
+    }
+}
+
+// like DListLink, Link has to be a pointer-like type with its links stored in the pointee
+// range must not change while the item is in a tree
+// items with equal starts are allowed, but lookups by address assume ranges don't overlap
+pub trait TreeLink: Sized {
+    type Slot: IsSlotOf<Self, Value = TreeLinks<Self::Raw>>;
+    type Raw: Stores<Self> + PartialEq;
+
+    fn range(&self) -> Range<usize>;
+}
+
+fn into_raw<Link: TreeLink>(item: Link) -> Link::Raw {
+    let raw = unsafe { Link::Raw::store(&item) };
+    core::mem::forget(item);
+    raw
+}
+
+// Safety: raw must be a stored Link that's owned by a tree
+unsafe fn alias_of<Link: TreeLink>(raw: Link::Raw) -> ManuallyDrop<Link> {
+    ManuallyDrop::new(Link::Raw::extract(raw))
+}
+
+// Safety: raw must be a stored Link that's owned by a tree
+unsafe fn links_of<'a, Link: TreeLink>(raw: Link::Raw) -> &'a mut TreeLinks<Link::Raw> {
+    let mut item = alias_of::<Link>(raw);
+    // points into the item's pointee, so it outlives the alias
+    &mut *(Link::Slot::get_mut(&mut item) as *mut TreeLinks<Link::Raw>)
+}
+
+unsafe fn range_of<Link: TreeLink>(raw: Link::Raw) -> Range<usize> {
+    alias_of::<Link>(raw).range()
+}
+
+unsafe fn height_of<Link: TreeLink>(raw: Option<Link::Raw>) -> u8 {
+    raw.map_or(0, |raw| links_of::<Link>(raw).height)
+}
+
+unsafe fn leftmost<Link: TreeLink>(mut raw: Link::Raw) -> Link::Raw {
+    while let Some(left) = links_of::<Link>(raw).left {
+        raw = left;
+    }
+    raw
+}
+
+unsafe fn rightmost<Link: TreeLink>(mut raw: Link::Raw) -> Link::Raw {
+    while let Some(right) = links_of::<Link>(raw).right {
+        raw = right;
+    }
+    raw
+}
+
+unsafe fn successor<Link: TreeLink>(mut raw: Link::Raw) -> Option<Link::Raw> {
+    if let Some(right) = links_of::<Link>(raw).right {
+        return Some(leftmost::<Link>(right));
+    }
+    // go up until we come from a left child
+    while let Some(parent) = links_of::<Link>(raw).parent {
+        if links_of::<Link>(parent).left == Some(raw) {
+            return Some(parent);
+        }
+        raw = parent;
+    }
+    None
+}
+
+unsafe fn predecessor<Link: TreeLink>(mut raw: Link::Raw) -> Option<Link::Raw> {
+    if let Some(left) = links_of::<Link>(raw).left {
+        return Some(rightmost::<Link>(left));
+    }
+    while let Some(parent) = links_of::<Link>(raw).parent {
+        if links_of::<Link>(parent).right == Some(raw) {
+            return Some(parent);
+        }
+        raw = parent;
+    }
+    None
+}
+
+// recalculates the height and subtree info of raw from its children
+unsafe fn update<Link: TreeLink>(raw: Link::Raw) {
+    let range = range_of::<Link>(raw);
+    let links = links_of::<Link>(raw);
+    let left = links.left.map(|l| links_of::<Link>(l));
+    let right = links.right.map(|r| links_of::<Link>(r));
+
+    let left_height = left.as_ref().map_or(0, |l| l.height);
+    let right_height = right.as_ref().map_or(0, |r| r.height);
+    let height = max(left_height, right_height) + 1;
+
+    let mut min_start = range.start;
+    let mut max_end = range.end;
+    let mut max_gap = 0;
+    if let Some(left) = left {
+        min_start = left.min_start;
+        max_gap = max(left.max_gap, range.start.saturating_sub(left.max_end));
+        max_end = max(max_end, left.max_end);
+    }
+    if let Some(right) = right {
+        max_gap = max(max_gap, right.max_gap);
+        max_gap = max(max_gap, right.min_start.saturating_sub(max_end));
+        max_end = max(max_end, right.max_end);
+    }
+
+    links.height = height;
+    links.min_start = min_start;
+    links.max_end = max_end;
+    links.max_gap = max_gap;
+}
+
+fn fits(start: usize, end: usize, size: usize) -> bool {
+    end >= start && end - start >= size
+}
+
+pub struct IntrusiveTree<Link: TreeLink> {
+    root: Option<Link::Raw>,
+    len: usize,
+}
+
+impl<Link: TreeLink> IntrusiveTree<Link> {
+    pub fn empty() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    unsafe fn replace_child(
+        &mut self,
+        parent: Option<Link::Raw>,
+        old: Link::Raw,
+        new: Option<Link::Raw>,
+    ) {
+        match parent {
+            None => self.root = new,
+            Some(parent) => {
+                let links = links_of::<Link>(parent);
+                if links.left == Some(old) {
+                    links.left = new;
+                } else {
+                    links.right = new;
+                }
+            }
+        }
+    }
+
+    // returns the new root of the subtree
+    unsafe fn rotate_left(&mut self, x: Link::Raw) -> Link::Raw {
+        let x_links = links_of::<Link>(x);
+        let y = x_links.right.unwrap();
+        let y_links = links_of::<Link>(y);
+
+        x_links.right = y_links.left;
+        if let Some(mid) = y_links.left {
+            links_of::<Link>(mid).parent = Some(x);
+        }
+        y_links.parent = x_links.parent;
+        self.replace_child(x_links.parent, x, Some(y));
+        y_links.left = Some(x);
+        x_links.parent = Some(y);
+
+        update::<Link>(x);
+        update::<Link>(y);
+        y
+    }
+
+    unsafe fn rotate_right(&mut self, x: Link::Raw) -> Link::Raw {
+        let x_links = links_of::<Link>(x);
+        let y = x_links.left.unwrap();
+        let y_links = links_of::<Link>(y);
+
+        x_links.left = y_links.right;
+        if let Some(mid) = y_links.right {
+            links_of::<Link>(mid).parent = Some(x);
+        }
+        y_links.parent = x_links.parent;
+        self.replace_child(x_links.parent, x, Some(y));
+        y_links.right = Some(x);
+        x_links.parent = Some(y);
+
+        update::<Link>(x);
+        update::<Link>(y);
+        y
+    }
+
+    // rebalances and updates subtree info from node up to the root
+    unsafe fn fix_up(&mut self, mut node: Option<Link::Raw>) {
+        while let Some(mut raw) = node {
+            update::<Link>(raw);
+            let links = links_of::<Link>(raw);
+            let balance =
+                height_of::<Link>(links.left) as i32 - height_of::<Link>(links.right) as i32;
+
+            if balance > 1 {
+                let left = links.left.unwrap();
+                let left_links = links_of::<Link>(left);
+                if height_of::<Link>(left_links.left) < height_of::<Link>(left_links.right) {
+                    self.rotate_left(left);
+                }
+                raw = self.rotate_right(raw);
+            } else if balance < -1 {
+                let right = links.right.unwrap();
+                let right_links = links_of::<Link>(right);
+                if height_of::<Link>(right_links.right) < height_of::<Link>(right_links.left) {
+                    self.rotate_right(right);
+                }
+                raw = self.rotate_left(raw);
+            }
+
+            node = links_of::<Link>(raw).parent;
+        }
+    }
+
+    pub fn insert(&mut self, item: Link) {
+        let start = item.range().start;
+        let raw = into_raw(item);
+
+        unsafe {
+            *links_of::<Link>(raw) = TreeLinks::new();
+
+            let mut parent = None;
+            let mut curr = self.root;
+            while let Some(node) = curr {
+                parent = Some(node);
+                // equal starts go to the right, so they stay in insertion order
+                curr = if start < range_of::<Link>(node).start {
+                    links_of::<Link>(node).left
+                } else {
+                    links_of::<Link>(node).right
+                };
+            }
+
+            links_of::<Link>(raw).parent = parent;
+            match parent {
+                None => self.root = Some(raw),
+                Some(parent) => {
+                    if start < range_of::<Link>(parent).start {
+                        links_of::<Link>(parent).left = Some(raw);
+                    } else {
+                        links_of::<Link>(parent).right = Some(raw);
+                    }
+                }
+            }
+
+            self.fix_up(Some(raw));
+        }
+        self.len += 1;
+    }
+
+    // Safety: raw must be in this tree
+    unsafe fn unlink(&mut self, raw: Link::Raw) -> Link {
+        let links = links_of::<Link>(raw);
+        let parent = links.parent;
+
+        match (links.left, links.right) {
+            (Some(left), Some(right)) => {
+                // put the successor where raw was
+                let succ = leftmost::<Link>(right);
+                let succ_links = links_of::<Link>(succ);
+                let fix_from = if succ == right {
+                    succ
+                } else {
+                    let succ_parent = succ_links.parent.unwrap();
+                    links_of::<Link>(succ_parent).left = succ_links.right;
+                    if let Some(succ_right) = succ_links.right {
+                        links_of::<Link>(succ_right).parent = Some(succ_parent);
+                    }
+                    succ_links.right = Some(right);
+                    links_of::<Link>(right).parent = Some(succ);
+                    succ_parent
+                };
+                succ_links.left = Some(left);
+                links_of::<Link>(left).parent = Some(succ);
+                succ_links.parent = parent;
+                self.replace_child(parent, raw, Some(succ));
+                self.fix_up(Some(fix_from));
+            }
+            (child, None) | (None, child) => {
+                if let Some(child) = child {
+                    links_of::<Link>(child).parent = parent;
+                }
+                self.replace_child(parent, raw, child);
+                self.fix_up(parent);
+            }
+        }
+
+        *links_of::<Link>(raw) = TreeLinks::new();
+        self.len -= 1;
+        Link::Raw::extract(raw)
+    }
+
+    // Safety: item must be in this tree
+    pub unsafe fn remove(&mut self, item: &Link) -> Link {
+        self.unlink(Link::Raw::store(item))
+    }
+
+    pub fn pop_first(&mut self) -> Option<Link> {
+        let first = unsafe { leftmost::<Link>(self.root?) };
+        Some(unsafe { self.unlink(first) })
+    }
+
+    // removes the item whose range contains addr
+    pub fn take_containing(&mut self, addr: usize) -> Option<Link> {
+        let raw = self.containing_raw(addr)?;
+        Some(unsafe { self.unlink(raw) })
+    }
+
+    fn item_ref(&self, raw: Option<Link::Raw>) -> Option<ItemRef<'_, Link>> {
+        raw.map(|raw| ItemRef {
+            item: unsafe { alias_of::<Link>(raw) },
+            marker: PhantomData,
+        })
+    }
+
+    pub fn first(&self) -> Option<ItemRef<'_, Link>> {
+        self.item_ref(self.root.map(|root| unsafe { leftmost::<Link>(root) }))
+    }
+
+    pub fn last(&self) -> Option<ItemRef<'_, Link>> {
+        self.item_ref(self.root.map(|root| unsafe { rightmost::<Link>(root) }))
+    }
+
+    // Safety: item must be in this tree
+    pub unsafe fn next(&self, item: &Link) -> Option<ItemRef<'_, Link>> {
+        self.item_ref(successor::<Link>(Link::Raw::store(item)))
+    }
+
+    // Safety: item must be in this tree
+    pub unsafe fn prev(&self, item: &Link) -> Option<ItemRef<'_, Link>> {
+        self.item_ref(predecessor::<Link>(Link::Raw::store(item)))
+    }
+
+    // last item that starts at or before addr
+    fn floor_raw(&self, addr: usize) -> Option<Link::Raw> {
+        let mut res = None;
+        let mut curr = self.root;
+        while let Some(node) = curr {
+            let links = unsafe { links_of::<Link>(node) };
+            if unsafe { range_of::<Link>(node) }.start <= addr {
+                res = Some(node);
+                curr = links.right;
+            } else {
+                curr = links.left;
+            }
+        }
+        res
+    }
+
+    // first item that starts at or after addr
+    fn ceil_raw(&self, addr: usize) -> Option<Link::Raw> {
+        let mut res = None;
+        let mut curr = self.root;
+        while let Some(node) = curr {
+            let links = unsafe { links_of::<Link>(node) };
+            if unsafe { range_of::<Link>(node) }.start >= addr {
+                res = Some(node);
+                curr = links.left;
+            } else {
+                curr = links.right;
+            }
+        }
+        res
+    }
+
+    fn containing_raw(&self, addr: usize) -> Option<Link::Raw> {
+        let raw = self.floor_raw(addr)?;
+        unsafe { range_of::<Link>(raw) }
+            .contains(&addr)
+            .then_some(raw)
+    }
+
+    pub fn floor(&self, addr: usize) -> Option<ItemRef<'_, Link>> {
+        self.item_ref(self.floor_raw(addr))
+    }
+
+    pub fn ceil(&self, addr: usize) -> Option<ItemRef<'_, Link>> {
+        self.item_ref(self.ceil_raw(addr))
+    }
+
+    pub fn find(&self, addr: usize) -> Option<ItemRef<'_, Link>> {
+        self.item_ref(self.containing_raw(addr))
+    }
+
+    // all items that overlap range, in order
+    pub fn range(&self, range: Range<usize>) -> Iter<'_, Link> {
+        let first = match self.floor_raw(range.start) {
+            Some(raw) if unsafe { range_of::<Link>(raw) }.end > range.start => Some(raw),
+            _ => self.ceil_raw(range.start),
+        };
+        Iter {
+            next: first,
+            end: range.end,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, Link> {
+        Iter {
+            next: self.root.map(|root| unsafe { leftmost::<Link>(root) }),
+            end: usize::MAX,
+            marker: PhantomData,
+        }
+    }
+
+    // lowest address in bounds where size bytes fit without overlapping any item
+    pub fn find_gap(&self, size: usize, bounds: Range<usize>) -> Option<usize> {
+        let prev_end = match unsafe { self.find_gap_in(self.root, bounds.start, size, bounds.end) }
+        {
+            Ok(addr) => return Some(addr),
+            Err(prev_end) => prev_end,
+        };
+        fits(prev_end, bounds.end, size).then_some(prev_end)
+    }
+
+    // searches node's subtree, given that everything before it ends at prev_end
+    // on failure, returns where everything up to the end of the subtree ends
+    unsafe fn find_gap_in(
+        &self,
+        node: Option<Link::Raw>,
+        prev_end: usize,
+        size: usize,
+        limit: usize,
+    ) -> Result<usize, usize> {
+        let Some(node) = node else {
+            return Err(prev_end);
+        };
+        let links = links_of::<Link>(node);
+        if prev_end >= limit {
+            return Err(prev_end);
+        }
+
+        // no gap in or right before this subtree is big enough, skip all of it
+        if links.max_gap < size && !fits(prev_end, links.min_start, size) {
+            return Err(max(prev_end, links.max_end));
+        }
+
+        let prev_end = match self.find_gap_in(links.left, prev_end, size, limit) {
+            Ok(addr) => return Ok(addr),
+            Err(prev_end) => prev_end,
+        };
+        let range = range_of::<Link>(node);
+        if fits(prev_end, min(range.start, limit), size) {
+            return Ok(prev_end);
+        }
+        self.find_gap_in(links.right, max(prev_end, range.end), size, limit)
+    }
+}
+
+// a reference to an item in a tree
+// the Link itself stays owned by the tree
+pub struct ItemRef<'a, Link> {
+    item: ManuallyDrop<Link>,
+    marker: PhantomData<&'a Link>,
+}
+
+impl<Link> Deref for ItemRef<'_, Link> {
+    type Target = Link;
+
+    fn deref(&self) -> &Link {
+        &self.item
+    }
+}
+
+pub struct Iter<'a, Link: TreeLink> {
+    next: Option<Link::Raw>,
+    end: usize,
+    marker: PhantomData<&'a IntrusiveTree<Link>>,
+}
+
+impl<'a, Link: TreeLink> Iterator for Iter<'a, Link> {
+    type Item = ItemRef<'a, Link>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let raw = self.next?;
+        if unsafe { range_of::<Link>(raw) }.start >= self.end {
+            self.next = None;
+            return None;
+        }
+        self.next = unsafe { successor::<Link>(raw) };
+        Some(ItemRef {
+            item: unsafe { alias_of::<Link>(raw) },
+            marker: PhantomData,
+        })
+    }
+}
@@ -0,0 +1,82 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::atomic_stack::AtomicListLink;
+use super::{AtomicStore, IsSlotOf, Stores};
+
+// producers push onto a shared stack, the consumer takes the whole stack at once
+// and reverses it into FIFO order
+// nobody pops single items off the shared stack, so there's no ABA problem and no tag
+pub struct MpscQueue<Link> {
+    incoming: AtomicStore<AtomicUsize, Option<Link>>,
+    // only touched by the consumer
+    outgoing: UnsafeCell<Option<Link>>,
+}
+
+unsafe impl<Link: Send> Sync for MpscQueue<Link> {}
+
+impl<Link: AtomicListLink> MpscQueue<Link>
+where
+    usize: Stores<Option<Link>>,
+{
+    pub const fn empty() -> Self {
+        Self {
+            incoming: unsafe { AtomicStore::from_raw(0) },
+            outgoing: UnsafeCell::new(None),
+        }
+    }
+
+    pub fn push(&self, mut item: Link) {
+        let mut old = self.incoming.load_raw(Ordering::Relaxed);
+        loop {
+            unsafe { Link::Slot::get(&item).store_raw(old, Ordering::Relaxed) };
+            match self.incoming.compare_exchange_weak(
+                old,
+                Some(item),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(old_head) => {
+                    // the old head is owned by item's link now
+                    core::mem::forget(old_head);
+                    return;
+                }
+                Err((curr, new)) => {
+                    old = curr;
+                    item = new.unwrap();
+                }
+            }
+        }
+    }
+
+    // Safety: must only be called by the consumer
+    pub unsafe fn is_empty(&self) -> bool {
+        (&*self.outgoing.get()).is_none() && self.incoming.load_raw(Ordering::Relaxed) == 0
+    }
+
+    // Safety: must only be called by the consumer
+    pub unsafe fn pop(&self) -> Option<Link> {
+        let outgoing = &mut *self.outgoing.get();
+        if outgoing.is_none() {
+            *outgoing = self.take_incoming_reversed();
+        }
+
+        let mut item = outgoing.take()?;
+        *outgoing = Link::Slot::get_mut(&mut item).swap(None, Ordering::Relaxed);
+        Some(item)
+    }
+
+    fn take_incoming_reversed(&self) -> Option<Link> {
+        if self.incoming.load_raw(Ordering::Relaxed) == 0 {
+            return None;
+        }
+
+        let mut rest = self.incoming.swap(None, Ordering::Acquire);
+        let mut reversed = None;
+        while let Some(mut item) = rest {
+            rest = Link::Slot::get_mut(&mut item).swap(reversed, Ordering::Relaxed);
+            reversed = Some(item);
+        }
+        reversed
+    }
+}
@@ -0,0 +1,428 @@
+use core::alloc::Layout;
+use core::cmp::max;
+use core::marker::PhantomData;
+use core::ops::Range;
+use core::ptr::{null_mut, NonNull};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::sync::SpinLock;
+
+// sparse map from usize indices to pointers, like linux's xarray
+// lookups and iteration don't take any locks, they only do acquire loads
+// writers are serialized by a lock, and publish new nodes with release stores
+// removed nodes aren't freed right away, since readers might still be looking at them
+
+const SLOT_SHIFT: usize = 6;
+const SLOT_COUNT: usize = 1 << SLOT_SHIFT;
+const SLOT_MASK: usize = SLOT_COUNT - 1;
+const MAX_DEPTH: usize = (usize::BITS as usize + SLOT_SHIFT - 1) / SLOT_SHIFT;
+
+pub const MARK_COUNT: usize = 3;
+
+// where the tree gets its nodes, the kernel hands out objects from a Slab
+// Safety: alloc must return memory that fits node_layout() and that nothing else uses until it's given to dealloc
+pub unsafe trait NodeAlloc {
+    fn alloc(&mut self) -> Option<*mut ()>;
+
+    // Safety: node must have come from alloc on this allocator
+    unsafe fn dealloc(&mut self, node: *mut ());
+}
+
+pub fn node_layout() -> Layout {
+    Layout::new::<Node>()
+}
+
+// a tag bit that can be set on entries, what it means is up to the user (dirty, writeback, ...)
+#[derive(Clone, Copy)]
+pub struct Mark(pub u8);
+
+impl Mark {
+    fn idx(self) -> usize {
+        assert!((self.0 as usize) < MARK_COUNT, "bad radix tree mark");
+        self.0 as usize
+    }
+}
+
+struct Node {
+    // child nodes, or entries if shift is 0
+    slots: [AtomicUsize; SLOT_COUNT],
+    // which slots are non-empty
+    present: AtomicU64,
+    // which slots have an entry with the mark somewhere under them
+    marks: [AtomicU64; MARK_COUNT],
+    // the lowest index bit this node uses to pick a slot
+    shift: usize,
+    // only touched by writers, for the list of removed nodes
+    next_retired: *mut Node,
+}
+
+impl Node {
+    fn new(shift: usize) -> Self {
+        Self {
+            slots: [const { AtomicUsize::new(0) }; SLOT_COUNT],
+            present: AtomicU64::new(0),
+            marks: [const { AtomicU64::new(0) }; MARK_COUNT],
+            shift,
+            next_retired: null_mut(),
+        }
+    }
+
+    fn slot_of(&self, index: usize) -> usize {
+        (index >> self.shift) & SLOT_MASK
+    }
+
+    fn covers(&self, index: usize) -> bool {
+        index
+            .checked_shr((self.shift + SLOT_SHIFT) as u32)
+            .unwrap_or(0)
+            == 0
+    }
+}
+
+unsafe fn node_ref<'a>(raw: usize) -> &'a Node {
+    &*(raw as *const Node)
+}
+
+// the nodes from the root down to the leaf for an index, and the slot used in each
+struct Path {
+    steps: [(usize, usize); MAX_DEPTH],
+    len: usize,
+}
+
+impl Path {
+    fn leaf(&self) -> (&Node, usize) {
+        let (node, slot) = self.steps[self.len - 1];
+        (unsafe { node_ref(node) }, slot)
+    }
+}
+
+struct Writer<A> {
+    nodes: A,
+    retired: *mut Node,
+}
+
+impl<A: NodeAlloc> Writer<A> {
+    fn alloc_node(&mut self, shift: usize) -> Option<usize> {
+        let ptr = self.nodes.alloc()? as *mut Node;
+        unsafe { ptr.write(Node::new(shift)) };
+        Some(ptr as usize)
+    }
+
+    fn retire(&mut self, raw: usize) {
+        let node = raw as *mut Node;
+        unsafe { (*node).next_retired = self.retired };
+        self.retired = node;
+    }
+}
+
+pub struct RadixTree<T, A> {
+    root: AtomicUsize,
+    writer: SpinLock<Writer<A>>,
+    marker: PhantomData<*mut T>,
+}
+
+unsafe impl<T: Send, A: Send> Send for RadixTree<T, A> {}
+unsafe impl<T: Send, A: Send> Sync for RadixTree<T, A> {}
+
+impl<T, A: NodeAlloc> RadixTree<T, A> {
+    // nodes is only used with the tree's writer lock held
+    pub const fn new(nodes: A) -> Self {
+        Self {
+            root: AtomicUsize::new(0),
+            writer: SpinLock::new(Writer {
+                nodes,
+                retired: null_mut(),
+            }),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn load(&self, index: usize) -> Option<NonNull<T>> {
+        let mut raw = self.root.load(Ordering::Acquire);
+        if raw == 0 || !unsafe { node_ref(raw) }.covers(index) {
+            return None;
+        }
+        loop {
+            let node = unsafe { node_ref(raw) };
+            let child = node.slots[node.slot_of(index)].load(Ordering::Acquire);
+            if node.shift == 0 {
+                return NonNull::new(child as *mut T);
+            }
+            if child == 0 {
+                return None;
+            }
+            raw = child;
+        }
+    }
+
+    pub fn get_mark(&self, index: usize, mark: Mark) -> bool {
+        let Some(path) = self.path(index) else {
+            return false;
+        };
+        let (leaf, slot) = path.leaf();
+        leaf.marks[mark.idx()].load(Ordering::Acquire) & (1 << slot) != 0
+    }
+
+    // without the writer lock the path might be stale by the time it's used, which is only ok for readers
+    fn path(&self, index: usize) -> Option<Path> {
+        let mut path = Path {
+            steps: [(0, 0); MAX_DEPTH],
+            len: 0,
+        };
+        let mut raw = self.root.load(Ordering::Acquire);
+        if raw == 0 || !unsafe { node_ref(raw) }.covers(index) {
+            return None;
+        }
+        loop {
+            let node = unsafe { node_ref(raw) };
+            let slot = node.slot_of(index);
+            path.steps[path.len] = (raw, slot);
+            path.len += 1;
+            if node.shift == 0 {
+                return Some(path);
+            }
+            raw = node.slots[slot].load(Ordering::Acquire);
+            if raw == 0 {
+                return None;
+            }
+        }
+    }
+
+    // adds levels on top of the root until it covers index
+    fn grow(&self, writer: &mut Writer<A>, index: usize) -> Option<()> {
+        let mut root = self.root.load(Ordering::Relaxed);
+        if root == 0 {
+            let mut shift = 0;
+            while index.checked_shr((shift + SLOT_SHIFT) as u32).unwrap_or(0) != 0 {
+                shift += SLOT_SHIFT;
+            }
+            root = writer.alloc_node(shift)?;
+            self.root.store(root, Ordering::Release);
+        }
+
+        while !unsafe { node_ref(root) }.covers(index) {
+            let old_root = unsafe { node_ref(root) };
+            let new_root = writer.alloc_node(old_root.shift + SLOT_SHIFT)?;
+            let new_root_node = unsafe { node_ref(new_root) };
+            new_root_node.slots[0].store(root, Ordering::Relaxed);
+            new_root_node.present.store(1, Ordering::Relaxed);
+            for (new_mark, old_mark) in new_root_node.marks.iter().zip(old_root.marks.iter()) {
+                if old_mark.load(Ordering::Relaxed) != 0 {
+                    new_mark.store(1, Ordering::Relaxed);
+                }
+            }
+            self.root.store(new_root, Ordering::Release);
+            root = new_root;
+        }
+        Some(())
+    }
+
+    // like path, but creates any missing nodes
+    fn create_path(&self, writer: &mut Writer<A>, index: usize) -> Option<Path> {
+        self.grow(writer, index)?;
+        let mut raw = self.root.load(Ordering::Relaxed);
+        loop {
+            let node = unsafe { node_ref(raw) };
+            if node.shift == 0 {
+                return self.path(index);
+            }
+            let slot = node.slot_of(index);
+            let mut child = node.slots[slot].load(Ordering::Relaxed);
+            if child == 0 {
+                child = writer.alloc_node(node.shift - SLOT_SHIFT)?;
+                node.slots[slot].store(child, Ordering::Release);
+                node.present.fetch_or(1 << slot, Ordering::Release);
+            }
+            raw = child;
+        }
+    }
+
+    // on success, returns the entry that was replaced
+    // if a node couldn't be allocated, gives val back
+    pub fn store(&self, index: usize, val: NonNull<T>) -> Result<Option<NonNull<T>>, NonNull<T>> {
+        let mut writer = self.writer.lock();
+        let Some(path) = self.create_path(&mut writer, index) else {
+            return Err(val);
+        };
+        let (leaf, slot) = path.leaf();
+        let old = leaf.slots[slot].swap(val.as_ptr() as usize, Ordering::Release);
+        leaf.present.fetch_or(1 << slot, Ordering::Release);
+        Ok(NonNull::new(old as *mut T))
+    }
+
+    pub fn erase(&self, index: usize) -> Option<NonNull<T>> {
+        let mut writer = self.writer.lock();
+        let path = self.path(index)?;
+        let (leaf, slot) = path.leaf();
+        let old = NonNull::new(leaf.slots[slot].swap(0, Ordering::Release) as *mut T)?;
+        leaf.present.fetch_and(!(1 << slot), Ordering::Release);
+        for mark in 0..MARK_COUNT {
+            self.clear_mark_on_path(&path, mark);
+        }
+
+        // free nodes that are now empty, from the leaf upwards
+        for depth in (0..path.len).rev() {
+            let (raw, _) = path.steps[depth];
+            if unsafe { node_ref(raw) }.present.load(Ordering::Relaxed) != 0 {
+                break;
+            }
+            if depth == 0 {
+                self.root.store(0, Ordering::Release);
+            } else {
+                let (parent_raw, parent_slot) = path.steps[depth - 1];
+                let parent = unsafe { node_ref(parent_raw) };
+                parent.slots[parent_slot].store(0, Ordering::Release);
+                parent
+                    .present
+                    .fetch_and(!(1 << parent_slot), Ordering::Release);
+            }
+            writer.retire(raw);
+        }
+        Some(old)
+    }
+
+    // returns false if there's no entry at index
+    pub fn set_mark(&self, index: usize, mark: Mark) -> bool {
+        let _writer = self.writer.lock();
+        let Some(path) = self.path(index) else {
+            return false;
+        };
+        let (leaf, slot) = path.leaf();
+        if leaf.present.load(Ordering::Relaxed) & (1 << slot) == 0 {
+            return false;
+        }
+        for &(raw, slot) in path.steps[..path.len].iter().rev() {
+            let old =
+                unsafe { node_ref(raw) }.marks[mark.idx()].fetch_or(1 << slot, Ordering::Release);
+            if old != 0 {
+                // the parents already know something under this node is marked
+                break;
+            }
+        }
+        true
+    }
+
+    pub fn clear_mark(&self, index: usize, mark: Mark) {
+        let _writer = self.writer.lock();
+        if let Some(path) = self.path(index) {
+            self.clear_mark_on_path(&path, mark.idx());
+        }
+    }
+
+    fn clear_mark_on_path(&self, path: &Path, mark: usize) {
+        for &(raw, slot) in path.steps[..path.len].iter().rev() {
+            let node = unsafe { node_ref(raw) };
+            let new = node.marks[mark].fetch_and(!(1 << slot), Ordering::Release) & !(1 << slot);
+            if new != 0 {
+                // something else under this node is still marked
+                break;
+            }
+        }
+    }
+
+    // frees the nodes removed by erase
+    // Safety: every reader that might have seen those nodes must be done
+    pub unsafe fn free_retired(&self) {
+        let mut writer = self.writer.lock();
+        while !writer.retired.is_null() {
+            let node = writer.retired;
+            writer.retired = (*node).next_retired;
+            writer.nodes.dealloc(node as *mut ());
+        }
+    }
+
+    // first entry at or after from and before end
+    fn find_next(
+        &self,
+        from: usize,
+        end: usize,
+        mark: Option<Mark>,
+    ) -> Option<(usize, NonNull<T>)> {
+        let root = self.root.load(Ordering::Acquire);
+        if from >= end || root == 0 {
+            return None;
+        }
+        let root = unsafe { node_ref(root) };
+        if !root.covers(from) {
+            return None;
+        }
+        unsafe { Self::find_next_in(root, from, end, mark) }
+    }
+
+    // from must be inside node's range
+    unsafe fn find_next_in(
+        node: &Node,
+        from: usize,
+        end: usize,
+        mark: Option<Mark>,
+    ) -> Option<(usize, NonNull<T>)> {
+        // the first index in node's range
+        let base = match usize::MAX.checked_shl((node.shift + SLOT_SHIFT) as u32) {
+            Some(high_bits) => from & high_bits,
+            None => 0,
+        };
+        let first_slot = node.slot_of(from);
+
+        let mut candidates = match mark {
+            Some(mark) => node.marks[mark.idx()].load(Ordering::Acquire),
+            None => node.present.load(Ordering::Acquire),
+        };
+        candidates &= u64::MAX << first_slot;
+
+        while candidates != 0 {
+            let slot = candidates.trailing_zeros() as usize;
+            candidates &= candidates - 1;
+
+            let slot_from = max(base + (slot << node.shift), from);
+            if slot_from >= end {
+                return None;
+            }
+            let child = node.slots[slot].load(Ordering::Acquire);
+            if child == 0 {
+                continue;
+            }
+            if node.shift == 0 {
+                return Some((slot_from, NonNull::new_unchecked(child as *mut T)));
+            }
+            if let Some(res) = Self::find_next_in(node_ref(child), slot_from, end, mark) {
+                return Some(res);
+            }
+        }
+        None
+    }
+
+    pub fn range(&self, range: Range<usize>) -> Iter<T, A> {
+        Iter {
+            tree: self,
+            next: Some(range.start),
+            end: range.end,
+            mark: None,
+        }
+    }
+
+    pub fn range_marked(&self, range: Range<usize>, mark: Mark) -> Iter<T, A> {
+        Iter {
+            tree: self,
+            next: Some(range.start),
+            end: range.end,
+            mark: Some(mark),
+        }
+    }
+}
+
+pub struct Iter<'a, T, A> {
+    tree: &'a RadixTree<T, A>,
+    next: Option<usize>,
+    end: usize,
+    mark: Option<Mark>,
+}
+
+impl<T, A: NodeAlloc> Iterator for Iter<'_, T, A> {
+    type Item = (usize, NonNull<T>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (index, entry) = self.tree.find_next(self.next?, self.end, self.mark)?;
+        self.next = index.checked_add(1);
+        Some((index, entry))
+    }
+}
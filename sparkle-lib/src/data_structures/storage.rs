@@ -1,5 +1,8 @@
 use atomic_traits::Atomic;
-use core::{marker::PhantomData, sync::atomic::Ordering};
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 // this trait could be used to copy types that are not Copy
 // so it is unsafe
@@ -9,6 +12,21 @@ pub unsafe trait Stores<T>: Copy {
     unsafe fn extract(store: Self) -> T;
 }
 
+// links to items that live forever, stored as their address (0 is None)
+// this has to be here, since Option<&mut T> and usize are both foreign everywhere else
+unsafe impl<T> Stores<Option<&'static mut T>> for usize {
+    unsafe fn store(val: &Option<&'static mut T>) -> Self {
+        match val {
+            Some(item) => *item as *const T as usize,
+            None => 0,
+        }
+    }
+
+    unsafe fn extract(store: Self) -> Option<&'static mut T> {
+        (store as *mut T).as_mut()
+    }
+}
+
 // a value of type T stored as type S
 #[repr(transparent)]
 pub struct Store<S, T>(S, PhantomData<T>);
@@ -60,6 +78,14 @@ impl<S: Stores<T>, T> Store<S, T> {
 
 pub struct AtomicStore<S, T>(S, PhantomData<T>);
 
+impl<T> AtomicStore<AtomicUsize, T> {
+    // for statics, since the generic new can't be const
+    // Safety: raw must be a valid stored T
+    pub const unsafe fn from_raw(raw: usize) -> Self {
+        Self(AtomicUsize::new(raw), PhantomData)
+    }
+}
+
 impl<S: Atomic, T> AtomicStore<S, T>
 where
     S::Type: Stores<T> + Copy,
@@ -76,6 +102,17 @@ where
         self.0.load(order)
     }
 
+    // Safety: val must be a valid stored T, and the old value is leaked
+    pub unsafe fn store_raw(&self, val: S::Type, order: Ordering) {
+        self.0.store(val, order)
+    }
+
+    pub fn swap(&self, val: T, order: Ordering) -> T {
+        let new_raw = unsafe { S::Type::store(&val) };
+        core::mem::forget(val);
+        unsafe { S::Type::extract(self.0.swap(new_raw, order)) }
+    }
+
     pub fn compare_exchange_weak(
         &self,
         old: S::Type,
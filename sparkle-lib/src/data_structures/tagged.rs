@@ -0,0 +1,105 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::Stores;
+
+// pointers only use the bottom 48 bits (57 with 5-level paging), the rest is sign extension
+// and the things tagged pointers point to are at least 8 byte aligned,
+// so the unused top bits and the bottom 3 can hold a counter that changes on every update
+// a cas on the whole word then fails if the pointer was changed and changed back (ABA)
+// that's 19 bits with 4-level paging, but only 10 with 5 levels,
+// where a cas that stalls through 1024 updates can be fooled, so keep stalls short there
+const ALIGN_BITS: usize = 3;
+const ALIGN_MASK: usize = (1 << ALIGN_BITS) - 1;
+
+// starts out as the most an address can need, the kernel lowers it once it knows the paging mode
+static ADDR_BITS: AtomicUsize = AtomicUsize::new(57);
+
+// going from 57 to 48 keeps stored words valid, the bits that were sign extension become tag
+// going the other way doesn't, so this can only lower it
+pub fn set_addr_bits(bits: usize) {
+    assert!(
+        (48..=ADDR_BITS.load(Ordering::Relaxed)).contains(&bits),
+        "address bits can only be lowered, to at least 48"
+    );
+    ADDR_BITS.store(bits, Ordering::Relaxed);
+}
+
+fn addr_bits() -> usize {
+    ADDR_BITS.load(Ordering::Relaxed)
+}
+
+fn high_tag_bits() -> usize {
+    usize::BITS as usize - addr_bits()
+}
+
+fn addr_mask() -> usize {
+    ((1 << addr_bits()) - 1) & !ALIGN_MASK
+}
+
+// how many bits of tag there are
+pub fn tag_bits() -> usize {
+    high_tag_bits() + ALIGN_BITS
+}
+
+fn tag_mask() -> usize {
+    (1 << tag_bits()) - 1
+}
+
+#[derive(Clone, Copy)]
+pub struct Tagged<T> {
+    pub val: T,
+    pub tag: usize,
+}
+
+impl<T> Tagged<T> {
+    pub fn new(val: T, tag: usize) -> Self {
+        Self {
+            val,
+            tag: tag & tag_mask(),
+        }
+    }
+
+    // the value that should replace a raw tagged word
+    pub fn after(raw: usize, val: T) -> Self {
+        Self::new(val, tag_of_raw(raw).wrapping_add(1))
+    }
+}
+
+fn sign_extend(addr: usize) -> usize {
+    let unused_bits = high_tag_bits();
+    ((addr << unused_bits) as isize >> unused_bits) as usize
+}
+
+pub fn tag_of_raw(raw: usize) -> usize {
+    ((raw >> addr_bits()) << ALIGN_BITS) | (raw & ALIGN_MASK)
+}
+
+pub fn untag_raw(raw: usize) -> usize {
+    // sign extends the address back to a canonical one
+    sign_extend(raw & addr_mask())
+}
+
+fn tag_raw(addr: usize, tag: usize) -> usize {
+    (addr & addr_mask()) | ((tag >> ALIGN_BITS) << addr_bits()) | (tag & ALIGN_MASK)
+}
+
+unsafe impl<T> Stores<Tagged<T>> for usize
+where
+    usize: Stores<T>,
+{
+    unsafe fn store(val: &Tagged<T>) -> Self {
+        let addr = usize::store(&val.val);
+        debug_assert!(
+            untag_raw(addr) == addr,
+            "stored a non-canonical or unaligned address"
+        );
+        tag_raw(addr, val.tag)
+    }
+
+    unsafe fn extract(store: Self) -> Tagged<T> {
+        Tagged {
+            val: usize::extract(untag_raw(store)),
+            tag: tag_of_raw(store),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use core::ops::Range;
+
+use crate::align::Alignment;
+use crate::bump_alloc::BumpAllocator;
+
+// 1GiB, big enough to back a huge page
+pub const MAX_PAGE_ORDER: u8 = 18;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameOrder(pub u8);
+
+#[derive(Clone, Copy, Debug)]
+pub enum FitPolicy {
+    FirstFit,
+    BestFit,
+}
+
+// counts of the largest free blocks, like the free lists of a buddy allocator would have
+#[derive(Clone, Copy)]
+pub struct FrameStats {
+    pub free_frames: usize,
+    pub free_blocks: [usize; MAX_PAGE_ORDER as usize + 1],
+}
+
+impl FrameStats {
+    pub fn empty() -> Self {
+        Self {
+            free_frames: 0,
+            free_blocks: [0; MAX_PAGE_ORDER as usize + 1],
+        }
+    }
+
+    pub fn add(&mut self, other: &Self) {
+        self.free_frames += other.free_frames;
+        for (count, other_count) in self.free_blocks.iter_mut().zip(other.free_blocks) {
+            *count += other_count;
+        }
+    }
+
+    pub fn add_block(&mut self, order: u8) {
+        self.free_blocks[order as usize] += 1;
+        self.free_frames += 1 << order;
+    }
+
+    pub fn largest_free_order(&self) -> Option<FrameOrder> {
+        (0..=MAX_PAGE_ORDER)
+            .rev()
+            .find(|&order| self.free_blocks[order as usize] != 0)
+            .map(FrameOrder)
+    }
+
+    // how much of the free memory is in blocks too small for an allocation of this order
+    // in thousandths, 0 means no fragmentation
+    pub fn fragmentation_index(&self, order: FrameOrder) -> usize {
+        if self.free_frames == 0 {
+            return 0;
+        }
+        let usable: usize = (order.0..=MAX_PAGE_ORDER)
+            .map(|o| self.free_blocks[o as usize] << o)
+            .sum();
+        (self.free_frames - usable) * 1000 / self.free_frames
+    }
+}
+
+impl core::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "free frames: {}", self.free_frames)?;
+        for (order, count) in self.free_blocks.iter().enumerate() {
+            let frag = self.fragmentation_index(FrameOrder(order as u8));
+            write!(
+                f,
+                "\n  order {}: {} blocks, fragmentation {}/1000",
+                order, count, frag
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// what each NUMA node's allocator has to do, so the rest of the kernel doesn't care which one it is
+// frames are global frame indices (physical address / page size), the allocator turns them into its own
+pub trait FrameAllocator {
+    // how much memory new takes from the bump allocator, including any alignment
+    fn bookkeeping_size(frame_count: usize) -> usize
+    where
+        Self: Sized;
+
+    // covers the frames in span, which starts aligned to the biggest block
+    // everything starts out used, add_free_range is how the memory map gets in
+    fn new(map_alloc: &mut BumpAllocator<'static>, span: Range<usize>) -> Self
+    where
+        Self: Sized;
+
+    fn span(&self) -> Range<usize>;
+
+    fn contains(&self, frame: usize) -> bool {
+        self.span().contains(&frame)
+    }
+
+    // frees frames that were never allocated, during init or when memory is reclaimed later
+    // anything outside the span is ignored, returns how many frames were added
+    fn add_free_range(&mut self, frames: Range<usize>) -> usize;
+
+    fn alloc(&mut self, order: FrameOrder) -> Option<usize>;
+
+    // a block of the order that's entirely inside frames
+    fn alloc_in(&mut self, frames: Range<usize>, order: FrameOrder) -> Option<usize>;
+
+    // count contiguous frames, starting at a multiple of align frames
+    fn alloc_run(&mut self, count: usize, align: Alignment) -> Option<usize>;
+
+    fn free(&mut self, frame: usize, order: FrameOrder);
+
+    fn free_run(&mut self, frame: usize, count: usize);
+
+    fn stats(&self) -> FrameStats;
+
+    // returns the old policy, allocators that don't have a choice just ignore it
+    fn set_fit_policy(&mut self, fit: FitPolicy) -> FitPolicy {
+        fit
+    }
+}
+
+// splits a range of frames into the biggest aligned blocks that fit, as (idx, order)
+// idx is in units of the block's size, like the rest of the allocator uses
+pub fn run_blocks(range: Range<usize>) -> impl Iterator<Item = (usize, FrameOrder)> {
+    let mut pos = range.start;
+    core::iter::from_fn(move || {
+        if pos >= range.end {
+            return None;
+        }
+        let order = (pos.trailing_zeros() as u8)
+            .min((range.end - pos).ilog2() as u8)
+            .min(MAX_PAGE_ORDER);
+        let block = (pos >> order, FrameOrder(order));
+        pos += 1 << order;
+        Some(block)
+    })
+}
@@ -0,0 +1,15 @@
+// the parts of the kernel that are just logic, so they can be tested on the host
+// nothing in here touches hardware or assumes it's running in the kernel
+#![no_std]
+#![feature(maybe_uninit_slice)]
+#![feature(alloc_layout_extra)]
+#![allow(dead_code)]
+
+pub mod align;
+pub mod bitmap_frame_alloc;
+pub mod buddy_frame_alloc;
+pub mod bump_alloc;
+pub mod data_structures;
+pub mod frame_alloc;
+pub mod ranges;
+pub mod sync;
@@ -0,0 +1,60 @@
+use core::iter::Peekable;
+use core::ops::Range;
+
+// the parts of the ranges in plus that aren't in any range in minus
+#[derive(Clone)]
+pub struct RangesDifference<A, I: Iterator<Item = Range<A>>, E: Iterator<Item = Range<A>>> {
+    plus: Peekable<I>,
+    minus: Peekable<E>,
+}
+
+impl<A, I: Iterator<Item = Range<A>>, E: Iterator<Item = Range<A>>> RangesDifference<A, I, E> {
+    // both have to be sorted, and minus can't overlap itself
+    pub fn new(plus: I, minus: E) -> Self {
+        Self {
+            plus: plus.peekable(),
+            minus: minus.peekable(),
+        }
+    }
+}
+
+impl<A: Clone + PartialOrd, I: Iterator<Item = Range<A>>, E: Iterator<Item = Range<A>>> Iterator
+    for RangesDifference<A, I, E>
+{
+    type Item = Range<A>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some(minus) = self.minus.peek().cloned() else {
+                return self.plus.next();
+            };
+
+            let plus = self.plus.peek()?.clone();
+
+            if minus.end <= plus.start {
+                self.minus.next();
+                continue;
+            }
+
+            // nothing left to subtract from this one
+            if minus.start >= plus.end {
+                return self.plus.next();
+            }
+
+            // minus and plus are guaranteed to overlap
+
+            // remove the current minus from the current plus
+            if minus.end < plus.end {
+                self.plus.peek_mut().unwrap().start = minus.end;
+                self.minus.next();
+            } else {
+                self.plus.next();
+            }
+
+            // if part of plus isn't subtracted, return that part
+            if plus.start < minus.start {
+                return Some(plus.start..minus.start);
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use proptest::prelude::*;
+use sparkle_lib::align::Alignment;
+
+#[test]
+fn basics() {
+    let align = Alignment::new(8);
+    assert_eq!(align.align_up(1), 8);
+    assert_eq!(align.align_up(8), 8);
+    assert_eq!(align.align_down(15), 8);
+    assert_eq!(align.align_up_offset(1), 7);
+    assert_eq!(align.align_up_offset(16), 0);
+    assert_eq!(align.align_down_offset(13), 5);
+    assert_eq!(align.shift(), 3);
+    assert_eq!(Alignment::new_from_shift(12).as_usize(), 4096);
+    assert_eq!(align.split_range(3..21), (5, 8..16, 5));
+}
+
+#[test]
+#[should_panic]
+fn not_power_of_two() {
+    Alignment::new(12);
+}
+
+proptest! {
+    #[test]
+    fn align_up_and_down(shift in 0usize..20, addr in 0usize..1 << 40) {
+        let align = Alignment::new_from_shift(shift);
+        let up = align.align_up(addr);
+        let down = align.align_down(addr);
+        prop_assert!(align.is_aligned(up) && align.is_aligned(down));
+        prop_assert!(down <= addr && addr <= up);
+        prop_assert!(up - down == 0 || up - down == align.as_usize());
+        prop_assert_eq!(up - addr, align.align_up_offset(addr));
+        prop_assert_eq!(addr - down, align.align_down_offset(addr));
+        prop_assert_eq!(align.checked_align_up(addr), Some(up));
+    }
+
+    #[test]
+    fn split_range(shift in 0usize..12, start in 0usize..1 << 20, len in 0usize..1 << 14) {
+        let align = Alignment::new_from_shift(shift);
+        let range = start..start + len;
+        let (before, middle, after) = align.split_range(range.clone());
+        if middle.start <= middle.end {
+            // the three parts cover the range exactly
+            prop_assert_eq!(range.start + before, middle.start);
+            prop_assert_eq!(middle.end + after, range.end);
+            prop_assert!(align.is_aligned(middle.start) && align.is_aligned(middle.end));
+        }
+    }
+}
+
+#[test]
+fn checked_align_up_overflow() {
+    assert_eq!(Alignment::new(16).checked_align_up(usize::MAX - 3), None);
+}
@@ -0,0 +1,202 @@
+use std::sync::atomic::AtomicUsize;
+use std::thread;
+
+use proptest::prelude::*;
+use sparkle_lib::data_structures::atomic_stack::{AtomicListLink, AtomicStack};
+use sparkle_lib::data_structures::mpsc_queue::MpscQueue;
+use sparkle_lib::data_structures::tagged::{self, Tagged};
+use sparkle_lib::data_structures::{AtomicStore, IsSlotOf, Stores};
+
+struct Node {
+    next: AtomicStore<AtomicUsize, Option<NodeLink>>,
+    val: usize,
+}
+
+type NodeLink = &'static mut Node;
+
+struct NextSlot;
+
+unsafe impl IsSlotOf<NodeLink> for NextSlot {
+    type Value = AtomicStore<AtomicUsize, Option<NodeLink>>;
+
+    fn get(st: &NodeLink) -> &Self::Value {
+        &st.next
+    }
+
+    fn get_mut(st: &mut NodeLink) -> &mut Self::Value {
+        &mut st.next
+    }
+}
+
+impl AtomicListLink for NodeLink {
+    type Slot = NextSlot;
+}
+
+fn node(val: usize) -> NodeLink {
+    Box::leak(Box::new(Node {
+        next: AtomicStore::new(None),
+        val,
+    }))
+}
+
+#[test]
+fn stack_push_pop() {
+    let stack = AtomicStack::empty();
+    assert!(stack.is_empty());
+    assert!(stack.pop().is_none());
+    stack.push(node(1));
+    stack.push(node(2));
+    assert!(!stack.is_empty());
+    assert_eq!(stack.pop().unwrap().val, 2);
+    assert_eq!(stack.pop().unwrap().val, 1);
+    assert!(stack.pop().is_none());
+}
+
+#[test]
+fn stack_popped_items_are_unlinked() {
+    let stack = AtomicStack::empty();
+    stack.push(node(1));
+    stack.push(node(2));
+    let item = stack.pop().unwrap();
+    assert_eq!(item.next.load_raw(std::sync::atomic::Ordering::Relaxed), 0);
+}
+
+// host addresses fit in 48 bits, like the kernel's with 4-level paging
+// the 16 bits above them go to the tag, so it only wraps after 2^19 updates
+#[test]
+fn tag_uses_free_high_bits() {
+    tagged::set_addr_bits(48);
+    assert_eq!(tagged::tag_bits(), 19);
+
+    let item = node(7);
+    let addr = &*item as *const Node as usize;
+    let tag = (1 << 19) - 1;
+    let raw = unsafe { usize::store(&Tagged::new(Some(item), tag)) };
+    let back: Tagged<Option<NodeLink>> = unsafe { usize::extract(raw) };
+    assert_eq!(back.tag, tag);
+    assert_eq!(back.val.unwrap() as *const Node as usize, addr);
+}
+
+// every thread keeps popping an item and pushing it back,
+// which is exactly what makes the same head come back (ABA)
+#[test]
+fn stack_threads_dont_lose_items() {
+    static STACK: AtomicStack<NodeLink> = AtomicStack::empty();
+    const ITEMS: usize = 64;
+    for val in 0..ITEMS {
+        STACK.push(node(val));
+    }
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            thread::spawn(|| {
+                for _ in 0..20000 {
+                    if let Some(item) = STACK.pop() {
+                        STACK.push(item);
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let mut vals = vec![];
+    while let Some(item) = STACK.pop() {
+        vals.push(item.val);
+    }
+    vals.sort();
+    assert_eq!(vals, (0..ITEMS).collect::<Vec<_>>());
+}
+
+#[test]
+fn queue_is_fifo() {
+    let queue = MpscQueue::empty();
+    unsafe {
+        assert!(queue.is_empty());
+        assert!(queue.pop().is_none());
+        queue.push(node(1));
+        queue.push(node(2));
+        assert_eq!(queue.pop().unwrap().val, 1);
+        // pushed while the consumer has a batch out
+        queue.push(node(3));
+        assert_eq!(queue.pop().unwrap().val, 2);
+        assert_eq!(queue.pop().unwrap().val, 3);
+        assert!(queue.is_empty());
+    }
+}
+
+// each producer's items come out in the order it pushed them
+#[test]
+fn queue_threads_keep_order() {
+    static QUEUE: MpscQueue<NodeLink> = MpscQueue::empty();
+    const PRODUCERS: usize = 4;
+    const PER_PRODUCER: usize = 10000;
+    let threads: Vec<_> = (0..PRODUCERS)
+        .map(|producer| {
+            thread::spawn(move || {
+                for seq in 0..PER_PRODUCER {
+                    QUEUE.push(node(producer * PER_PRODUCER + seq));
+                }
+            })
+        })
+        .collect();
+
+    let mut next = [0; PRODUCERS];
+    let mut received = 0;
+    while received < PRODUCERS * PER_PRODUCER {
+        let Some(item) = (unsafe { QUEUE.pop() }) else {
+            thread::yield_now();
+            continue;
+        };
+        let (producer, seq) = (item.val / PER_PRODUCER, item.val % PER_PRODUCER);
+        assert_eq!(seq, next[producer]);
+        next[producer] += 1;
+        received += 1;
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert!(unsafe { QUEUE.is_empty() });
+}
+
+proptest! {
+    #[test]
+    fn stack_matches_vec(ops in prop::collection::vec(prop::option::of(0usize..1000), 0..200)) {
+        let stack = AtomicStack::empty();
+        let mut model = vec![];
+        for op in ops {
+            match op {
+                Some(val) => {
+                    stack.push(node(val));
+                    model.push(val);
+                }
+                None => prop_assert_eq!(stack.pop().map(|n| n.val), model.pop()),
+            }
+        }
+        while let Some(val) = model.pop() {
+            prop_assert_eq!(stack.pop().map(|n| n.val), Some(val));
+        }
+        prop_assert!(stack.pop().is_none());
+    }
+
+    #[test]
+    fn queue_matches_vec_deque(ops in prop::collection::vec(prop::option::of(0usize..1000), 0..200)) {
+        let queue = MpscQueue::empty();
+        let mut model = std::collections::VecDeque::new();
+        for op in ops {
+            match op {
+                Some(val) => {
+                    queue.push(node(val));
+                    model.push_back(val);
+                }
+                None => prop_assert_eq!(unsafe { queue.pop() }.map(|n| n.val), model.pop_front()),
+            }
+        }
+        while let Some(val) = model.pop_front() {
+            prop_assert_eq!(unsafe { queue.pop() }.map(|n| n.val), Some(val));
+        }
+        let empty = unsafe { queue.is_empty() };
+        prop_assert!(empty);
+    }
+}
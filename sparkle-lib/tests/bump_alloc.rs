@@ -0,0 +1,65 @@
+use core::mem::MaybeUninit;
+
+use proptest::prelude::*;
+use sparkle_lib::bump_alloc::BumpAllocator;
+
+fn buffer(len: usize) -> Vec<MaybeUninit<u64>> {
+    vec![MaybeUninit::uninit(); len.div_ceil(8)]
+}
+
+fn bump(buf: &mut [MaybeUninit<u64>], len: usize) -> BumpAllocator<'_> {
+    unsafe { BumpAllocator::new_raw(buf.as_mut_ptr() as *mut u8, len) }
+}
+
+#[test]
+fn default_slice() {
+    let mut buf = buffer(256);
+    let mut bump = bump(&mut buf, 256);
+    let slice = bump.alloc_slice_default::<u32>(10);
+    assert!(slice.iter().all(|&x| x == 0));
+    assert_eq!(bump.remaining(), 256 - 40);
+}
+
+#[test]
+fn alignment() {
+    let mut buf = buffer(256);
+    let mut bump = bump(&mut buf, 256);
+    bump.alloc_bytes(3);
+    let ptr = bump.alloc_ptr::<u64>();
+    assert_eq!(ptr as usize % 8, 0);
+    assert_eq!(bump.remaining(), 256 - 16);
+    assert_eq!(bump.max_allocs_of::<u64>(), (256 - 16) / 8);
+}
+
+#[test]
+#[should_panic]
+fn out_of_space() {
+    let mut buf = buffer(64);
+    let mut bump = bump(&mut buf, 64);
+    bump.alloc_slice_default::<u8>(65);
+}
+
+proptest! {
+    // allocations are aligned, inside the buffer, and don't overlap
+    #[test]
+    fn allocs_dont_overlap(sizes in prop::collection::vec((1usize..64, 0u32..4), 1..50)) {
+        let len = 4096;
+        let mut buf = buffer(len);
+        let start = buf.as_ptr() as usize;
+        let mut bump = bump(&mut buf, len);
+        let mut end = start;
+        for (size, align_shift) in sizes {
+            let align = 1 << align_shift;
+            let layout = core::alloc::Layout::from_size_align(size, align).unwrap();
+            if bump.max_allocs_of_layout(layout) == 0 {
+                break;
+            }
+            let ptr = bump.alloc_layout(layout) as usize;
+            prop_assert_eq!(ptr % align, 0);
+            prop_assert!(ptr >= end);
+            end = ptr + size;
+            prop_assert!(end <= start + len);
+            prop_assert_eq!(bump.curr_ptr() as usize, end);
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use core::ops::Range;
+
+use proptest::prelude::*;
+use sparkle_lib::align::Alignment;
+use sparkle_lib::bitmap_frame_alloc::BitmapFrameAllocator;
+use sparkle_lib::buddy_frame_alloc::BuddyFrameAllocator;
+use sparkle_lib::bump_alloc::BumpAllocator;
+use sparkle_lib::frame_alloc::{
+    FitPolicy, FrameAllocator, FrameOrder, FrameStats, MAX_PAGE_ORDER,
+};
+
+// allocators are aligned to the biggest block, like the kernel's node allocators
+const BASE: usize = 1 << MAX_PAGE_ORDER;
+
+fn make<A: FrameAllocator>(frame_count: usize, free: &[Range<usize>]) -> A {
+    let size = A::bookkeeping_size(frame_count);
+    let buf: &'static mut [u64] = Vec::leak(vec![0; size.div_ceil(8)]);
+    let mut bump = unsafe { BumpAllocator::new_raw(buf.as_mut_ptr() as *mut u8, size) };
+    let mut frame_alloc = A::new(&mut bump, BASE..BASE + frame_count);
+    for range in free {
+        frame_alloc.add_free_range(BASE + range.start..BASE + range.end);
+    }
+    frame_alloc
+}
+
+// what the allocator should look like from outside: which frames are free
+struct Model {
+    free: Vec<bool>,
+}
+
+impl Model {
+    fn new(frame_count: usize, free: &[Range<usize>]) -> Self {
+        let mut model = Self {
+            free: vec![false; frame_count],
+        };
+        for range in free {
+            model.free[range.clone()].fill(true);
+        }
+        model
+    }
+
+    fn free_count(&self) -> usize {
+        self.free.iter().filter(|&&f| f).count()
+    }
+
+    fn is_free(&self, range: Range<usize>) -> bool {
+        range.end <= self.free.len() && self.free[range].iter().all(|&f| f)
+    }
+
+    fn take(&mut self, range: Range<usize>) {
+        assert!(self.is_free(range.clone()), "allocated used frames {:?}", range);
+        self.free[range].fill(false);
+    }
+
+    fn give_back(&mut self, range: Range<usize>) {
+        assert!(self.free[range.clone()].iter().all(|&f| !f));
+        self.free[range].fill(true);
+    }
+
+    // whether a run of count frames starting at a multiple of align is free inside within
+    fn has_run(&self, within: Range<usize>, count: usize, align: usize) -> bool {
+        let start = Alignment::new(align).align_up(within.start);
+        (start..within.end)
+            .step_by(align)
+            .any(|s| s + count <= within.end && self.is_free(s..s + count))
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Alloc(u8),
+    AllocIn(usize, usize, u8),
+    AllocRun(usize, u8),
+    Free(usize),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (0u8..8).prop_map(Op::Alloc),
+        1 => (0usize..1 << 14, 1usize..2048, 0u8..5).prop_map(|(s, l, o)| Op::AllocIn(s, l, o)),
+        1 => (1usize..40, 0u8..4).prop_map(|(c, a)| Op::AllocRun(c, a)),
+        3 => any::<usize>().prop_map(Op::Free),
+    ]
+}
+
+enum Live {
+    Block(usize, FrameOrder),
+    Run(usize, usize),
+}
+
+// runs ops against the allocator and the model, checking every result
+// exact_runs is whether alloc_run is expected to find any run that fits
+fn check_ops<A: FrameAllocator>(
+    frame_count: usize,
+    free: &[Range<usize>],
+    ops: &[Op],
+    exact_runs: bool,
+) -> Result<(), TestCaseError> {
+    let mut frame_alloc: A = make(frame_count, free);
+    let mut model = Model::new(frame_count, free);
+    let initial = frame_alloc.stats();
+    prop_assert_eq!(initial.free_frames, model.free_count());
+    let mut live = vec![];
+
+    for op in ops {
+        match *op {
+            Op::Alloc(order) => match frame_alloc.alloc(FrameOrder(order)) {
+                Some(frame) => {
+                    let start = frame - BASE;
+                    prop_assert_eq!(start % (1 << order), 0);
+                    model.take(start..start + (1 << order));
+                    live.push(Live::Block(start, FrameOrder(order)));
+                }
+                None => prop_assert!(!model.has_run(0..frame_count, 1 << order, 1 << order)),
+            },
+            Op::AllocIn(start, len, order) => {
+                let within = start..start + len;
+                let frames = BASE + within.start..BASE + within.end;
+                match frame_alloc.alloc_in(frames, FrameOrder(order)) {
+                    Some(frame) => {
+                        let start = frame - BASE;
+                        prop_assert_eq!(start % (1 << order), 0);
+                        prop_assert!(within.start <= start && start + (1 << order) <= within.end);
+                        model.take(start..start + (1 << order));
+                        live.push(Live::Block(start, FrameOrder(order)));
+                    }
+                    None => {
+                        let within = within.start..within.end.min(frame_count);
+                        prop_assert!(!model.has_run(within, 1 << order, 1 << order));
+                    }
+                }
+            }
+            Op::AllocRun(count, align_shift) => {
+                let align = 1 << align_shift;
+                match frame_alloc.alloc_run(count, Alignment::new(align)) {
+                    Some(frame) => {
+                        let start = frame - BASE;
+                        prop_assert_eq!(start % align, 0);
+                        model.take(start..start + count);
+                        live.push(Live::Run(start, count));
+                    }
+                    None if exact_runs => {
+                        prop_assert!(!model.has_run(0..frame_count, count, align))
+                    }
+                    None => {}
+                }
+            }
+            Op::Free(idx) => {
+                if live.is_empty() {
+                    continue;
+                }
+                match live.swap_remove(idx % live.len()) {
+                    Live::Block(start, order) => {
+                        frame_alloc.free(BASE + start, order);
+                        model.give_back(start..start + (1 << order.0));
+                    }
+                    Live::Run(start, count) => {
+                        frame_alloc.free_run(BASE + start, count);
+                        model.give_back(start..start + count);
+                    }
+                }
+            }
+        }
+
+        let stats = frame_alloc.stats();
+        prop_assert_eq!(stats.free_frames, model.free_count());
+        let block_frames: usize = (0..=MAX_PAGE_ORDER)
+            .map(|o| stats.free_blocks[o as usize] << o)
+            .sum();
+        prop_assert_eq!(block_frames, stats.free_frames);
+    }
+
+    // with everything freed, it should have merged back into the same blocks
+    for entry in live {
+        match entry {
+            Live::Block(start, order) => frame_alloc.free(BASE + start, order),
+            Live::Run(start, count) => frame_alloc.free_run(BASE + start, count),
+        }
+    }
+    prop_assert_eq!(frame_alloc.stats().free_blocks, initial.free_blocks);
+    Ok(())
+}
+
+fn free_ranges() -> impl Strategy<Value = (usize, Vec<Range<usize>>)> {
+    (1usize << 12..1 << 14).prop_flat_map(|frame_count| {
+        let cuts = prop::collection::btree_set(0..frame_count, 0..8);
+        (Just(frame_count), cuts).prop_map(|(frame_count, cuts)| {
+            // every other piece between the cuts is free
+            let mut points: Vec<usize> = cuts.into_iter().collect();
+            points.insert(0, 0);
+            points.push(frame_count);
+            let ranges = points
+                .windows(2)
+                .step_by(2)
+                .map(|w| w[0]..w[1])
+                .collect();
+            (frame_count, ranges)
+        })
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn bitmap_best_fit((frame_count, free) in free_ranges(), ops in prop::collection::vec(op(), 1..300)) {
+        check_ops::<BitmapFrameAllocator>(frame_count, &free, &ops, true)?;
+    }
+
+    #[test]
+    fn bitmap_first_fit((frame_count, free) in free_ranges(), ops in prop::collection::vec(op(), 1..300)) {
+        check_ops::<FirstFit>(frame_count, &free, &ops, true)?;
+    }
+
+    #[test]
+    fn buddy((frame_count, free) in free_ranges(), ops in prop::collection::vec(op(), 1..300)) {
+        check_ops::<BuddyFrameAllocator>(frame_count, &free, &ops, false)?;
+    }
+}
+
+// the bitmap allocator with the other fit policy, so check_ops can make it
+struct FirstFit(BitmapFrameAllocator);
+
+impl FrameAllocator for FirstFit {
+    fn bookkeeping_size(frame_count: usize) -> usize {
+        BitmapFrameAllocator::bookkeeping_size(frame_count)
+    }
+
+    fn new(map_alloc: &mut BumpAllocator<'static>, span: Range<usize>) -> Self {
+        let mut frame_alloc = BitmapFrameAllocator::new(map_alloc, span);
+        frame_alloc.set_fit_policy(FitPolicy::FirstFit);
+        Self(frame_alloc)
+    }
+
+    fn span(&self) -> Range<usize> {
+        self.0.span()
+    }
+
+    fn add_free_range(&mut self, frames: Range<usize>) -> usize {
+        self.0.add_free_range(frames)
+    }
+
+    fn alloc(&mut self, order: FrameOrder) -> Option<usize> {
+        self.0.alloc(order)
+    }
+
+    fn alloc_in(&mut self, frames: Range<usize>, order: FrameOrder) -> Option<usize> {
+        self.0.alloc_in(frames, order)
+    }
+
+    fn alloc_run(&mut self, count: usize, align: Alignment) -> Option<usize> {
+        self.0.alloc_run(count, align)
+    }
+
+    fn free(&mut self, frame: usize, order: FrameOrder) {
+        self.0.free(frame, order)
+    }
+
+    fn free_run(&mut self, frame: usize, count: usize) {
+        self.0.free_run(frame, count)
+    }
+
+    fn stats(&self) -> FrameStats {
+        self.0.stats()
+    }
+}
+
+fn huge_page<A: FrameAllocator>() {
+    let frame_count = 1 << 19;
+    let mut frame_alloc: A = make(frame_count, &[10..frame_count]);
+    // the first 1GiB has a hole in it, so only the second one can be used
+    let frame = frame_alloc.alloc(FrameOrder(18)).unwrap();
+    assert_eq!(frame, BASE + (1 << 18));
+    assert!(frame_alloc.alloc(FrameOrder(18)).is_none());
+    frame_alloc.free(frame, FrameOrder(18));
+    assert_eq!(frame_alloc.stats().free_blocks[18], 1);
+    assert!(frame_alloc.alloc(FrameOrder(19)).is_none());
+}
+
+#[test]
+fn bitmap_huge_page() {
+    huge_page::<BitmapFrameAllocator>();
+}
+
+#[test]
+fn buddy_huge_page() {
+    huge_page::<BuddyFrameAllocator>();
+}
+
+// memory that shows up after allocations have happened, like reclaimed ACPI tables
+fn add_after_allocs<A: FrameAllocator>() {
+    let frame_count = 20000;
+    let mut frame_alloc: A = make(frame_count, &[0..8000, 12000..frame_count]);
+    let mut model = Model::new(frame_count, &[0..8000, 12000..frame_count]);
+    let mut live = vec![];
+    for order in (0..10).cycle().take(100) {
+        if let Some(frame) = frame_alloc.alloc(FrameOrder(order)) {
+            model.take(frame - BASE..frame - BASE + (1 << order));
+            live.push((frame, FrameOrder(order)));
+        }
+    }
+
+    assert_eq!(frame_alloc.add_free_range(BASE + 8000..BASE + 12000), 4000);
+    model.give_back(8000..12000);
+    // anything past the end is ignored
+    assert_eq!(frame_alloc.add_free_range(BASE + frame_count..BASE + frame_count + 100), 0);
+    assert_eq!(frame_alloc.stats().free_frames, model.free_count());
+
+    for (frame, order) in live {
+        frame_alloc.free(frame, order);
+    }
+    let mut count = 0;
+    while let Some(frame) = frame_alloc.alloc(FrameOrder(0)) {
+        assert!(frame_alloc.contains(frame));
+        count += 1;
+    }
+    assert_eq!(count, frame_count);
+}
+
+#[test]
+fn bitmap_add_after_allocs() {
+    add_after_allocs::<BitmapFrameAllocator>();
+}
+
+#[test]
+fn buddy_add_after_allocs() {
+    add_after_allocs::<BuddyFrameAllocator>();
+}
+
+#[test]
+fn bitmap_prefers_high_memory() {
+    let mut frame_alloc: BitmapFrameAllocator = make(4096, &[0..4096]);
+    assert_eq!(frame_alloc.alloc(FrameOrder(0)), Some(BASE + 4095));
+    // 4088..4096 isn't all free anymore
+    assert_eq!(frame_alloc.alloc(FrameOrder(3)), Some(BASE + 4080));
+}
+
+#[test]
+fn fragmentation_index() {
+    let mut frame_alloc: BitmapFrameAllocator = make(64, &[0..64]);
+    // take every other frame, so nothing bigger than order 0 is left
+    let frames: Vec<_> = (0..64).map(|_| frame_alloc.alloc(FrameOrder(0)).unwrap()).collect();
+    for frame in frames.iter().filter(|&&f| f % 2 == 0) {
+        frame_alloc.free(*frame, FrameOrder(0));
+    }
+    let stats = frame_alloc.stats();
+    assert_eq!(stats.free_frames, 32);
+    assert_eq!(stats.largest_free_order().unwrap().0, 0);
+    assert_eq!(stats.fragmentation_index(FrameOrder(0)), 0);
+    assert_eq!(stats.fragmentation_index(FrameOrder(1)), 1000);
+}
+
+// randomly allocates and frees frames to fragment memory, then measures how bad it got
+// everything is freed again afterwards
+fn churn<A: FrameAllocator>(frame_alloc: &mut A, steps: usize, seed: u64) -> FrameStats {
+    const LIVE_COUNT: usize = 256;
+    let mut live: [Option<(usize, FrameOrder)>; LIVE_COUNT] = [None; LIVE_COUNT];
+
+    // xorshift
+    let mut rng = seed | 1;
+    for _ in 0..steps {
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+
+        let slot = rng as usize % LIVE_COUNT;
+        match live[slot].take() {
+            Some((frame, order)) => frame_alloc.free(frame, order),
+            None => {
+                // mostly small allocations, with some bigger ones mixed in
+                let order = FrameOrder(match (rng >> 32) % 16 {
+                    0 => 4,
+                    1..=3 => 2,
+                    4..=6 => 1,
+                    _ => 0,
+                });
+                live[slot] = frame_alloc.alloc(order).map(|frame| (frame, order));
+            }
+        }
+    }
+
+    let stats = frame_alloc.stats();
+    for (frame, order) in live.into_iter().flatten() {
+        frame_alloc.free(frame, order);
+    }
+    stats
+}
+
+// run with --nocapture to compare how much each one fragments
+fn churn_recovers<A: FrameAllocator>(name: &str) {
+    let frame_count = 1 << 16;
+    let mut frame_alloc: A = make(frame_count, &[0..frame_count]);
+    let before = frame_alloc.stats();
+    let during = churn(&mut frame_alloc, 10000, 0x5eed);
+    println!("after churn with {}:\n{}", name, during);
+    assert!(during.free_frames < before.free_frames);
+
+    // with everything freed, it all merges back into the same blocks
+    let after = frame_alloc.stats();
+    assert_eq!(after.free_frames, before.free_frames);
+    assert_eq!(after.free_blocks, before.free_blocks);
+}
+
+#[test]
+fn bitmap_best_fit_churn() {
+    churn_recovers::<BitmapFrameAllocator>("best fit");
+}
+
+#[test]
+fn bitmap_first_fit_churn() {
+    churn_recovers::<FirstFit>("first fit");
+}
+
+#[test]
+fn buddy_churn() {
+    churn_recovers::<BuddyFrameAllocator>("buddy");
+}
@@ -0,0 +1,368 @@
+use std::collections::VecDeque;
+
+use proptest::prelude::*;
+use sparkle_lib::data_structures::intrusive_dlist::{DListLink, DListLinks, IntrusiveDList};
+use sparkle_lib::data_structures::{IsSlotOf, Stores};
+
+struct Node {
+    links: DListLinks<RawNode>,
+    val: usize,
+}
+
+// shared links, so the tests can hold on to items that are in a list
+type NodeLink = &'static Node;
+type NodeLinkMut = &'static mut Node;
+
+#[derive(Clone, Copy)]
+struct RawNode(*mut Node);
+
+unsafe impl Stores<NodeLink> for RawNode {
+    unsafe fn store(val: &NodeLink) -> Self {
+        RawNode(*val as *const Node as *mut Node)
+    }
+
+    unsafe fn extract(store: Self) -> NodeLink {
+        unsafe { &*store.0 }
+    }
+}
+
+unsafe impl Stores<NodeLinkMut> for RawNode {
+    unsafe fn store(val: &NodeLinkMut) -> Self {
+        RawNode(*val as *const Node as *mut Node)
+    }
+
+    unsafe fn extract(store: Self) -> NodeLinkMut {
+        unsafe { &mut *store.0 }
+    }
+}
+
+struct LinksSlot;
+
+unsafe impl IsSlotOf<NodeLink> for LinksSlot {
+    type Value = DListLinks<RawNode>;
+
+    fn get(st: &NodeLink) -> &Self::Value {
+        &st.links
+    }
+
+    fn get_mut(_: &mut NodeLink) -> &mut Self::Value {
+        unreachable!("shared links are never borrowed mutably")
+    }
+}
+
+unsafe impl IsSlotOf<NodeLinkMut> for LinksSlot {
+    type Value = DListLinks<RawNode>;
+
+    fn get(st: &NodeLinkMut) -> &Self::Value {
+        &st.links
+    }
+
+    fn get_mut(st: &mut NodeLinkMut) -> &mut Self::Value {
+        &mut st.links
+    }
+}
+
+impl DListLink for NodeLink {
+    type Slot = LinksSlot;
+    type Raw = RawNode;
+}
+
+impl DListLink for NodeLinkMut {
+    type Slot = LinksSlot;
+    type Raw = RawNode;
+}
+
+fn node(val: usize) -> NodeLink {
+    Box::leak(Box::new(Node {
+        links: DListLinks::new(),
+        val,
+    }))
+}
+
+fn vals(list: &IntrusiveDList<NodeLink>) -> Vec<usize> {
+    let mut res = vec![];
+    let mut cursor = list.cursor_front();
+    while let Some(item) = cursor.current() {
+        res.push(item.val);
+        cursor.move_next();
+    }
+    res
+}
+
+fn vals_backwards(list: &IntrusiveDList<NodeLink>) -> Vec<usize> {
+    let mut res = vec![];
+    let mut cursor = list.cursor_back();
+    while let Some(item) = cursor.current() {
+        res.push(item.val);
+        cursor.move_prev();
+    }
+    res.reverse();
+    res
+}
+
+#[test]
+fn push_pop_both_ends() {
+    let mut list = IntrusiveDList::empty();
+    list.push_back(node(2));
+    list.push_front(node(1));
+    list.push_back(node(3));
+    assert_eq!(vals(&list), [1, 2, 3]);
+    assert_eq!(list.len(), 3);
+    assert_eq!(list.pop_back().unwrap().val, 3);
+    assert_eq!(list.pop_front().unwrap().val, 1);
+    assert_eq!(list.pop_front().unwrap().val, 2);
+    assert!(list.pop_back().is_none());
+    assert!(list.is_empty());
+}
+
+#[test]
+fn remove_through_own_link() {
+    let mut list = IntrusiveDList::empty();
+    let mut other = IntrusiveDList::empty();
+    let items = [node(1), node(2), node(3)];
+    for item in items {
+        list.push_back(item);
+    }
+    let stranger = node(4);
+    other.push_back(stranger);
+
+    assert!(other.remove(&items[1]).is_none());
+    assert!(list.remove(&stranger).is_none());
+    assert_eq!(list.remove(&items[1]).unwrap().val, 2);
+    assert!(list.remove(&items[1]).is_none());
+    assert_eq!(vals(&list), [1, 3]);
+    assert_eq!(vals(&other), [4]);
+
+    // it's free to go somewhere else now
+    other.push_front(items[1]);
+    assert_eq!(vals(&other), [2, 4]);
+}
+
+#[test]
+#[should_panic(expected = "already in a list")]
+fn push_twice_panics() {
+    let mut list = IntrusiveDList::empty();
+    let mut other = IntrusiveDList::empty();
+    let item = node(1);
+    list.push_back(item);
+    other.push_back(item);
+}
+
+#[test]
+fn splice_moves_ownership() {
+    let mut list = IntrusiveDList::empty();
+    let mut other = IntrusiveDList::empty();
+    let moved = node(3);
+    list.push_back(node(1));
+    other.push_back(node(2));
+    other.push_back(moved);
+    list.splice_back(&mut other);
+    assert!(other.is_empty());
+    assert!(other.remove(&moved).is_none());
+    assert_eq!(list.remove(&moved).unwrap().val, 3);
+    assert_eq!(vals(&list), [1, 2]);
+}
+
+#[test]
+fn drop_unlinks_items() {
+    let item = node(1);
+    {
+        let mut list = IntrusiveDList::empty();
+        list.push_back(item);
+    }
+    assert!(!item.links.is_linked());
+    let mut list = IntrusiveDList::empty();
+    list.push_back(item);
+    assert_eq!(vals(&list), [1]);
+}
+
+#[test]
+fn cursor_wraps_through_ghost() {
+    let mut list = IntrusiveDList::empty();
+    list.push_back(node(1));
+    list.push_back(node(2));
+    let mut cursor = list.cursor_back();
+    cursor.move_next();
+    assert!(cursor.current().is_none());
+    cursor.move_next();
+    assert_eq!(cursor.current().unwrap().val, 1);
+    cursor.move_prev();
+    cursor.move_prev();
+    assert_eq!(cursor.current().unwrap().val, 2);
+}
+
+#[test]
+fn cursor_current_mut_is_pinned() {
+    let mut list: IntrusiveDList<NodeLinkMut> = IntrusiveDList::empty();
+    list.push_back(Box::leak(Box::new(Node {
+        links: DListLinks::new(),
+        val: 1,
+    })));
+    let mut cursor = list.cursor_front_mut();
+    let item = cursor.current_mut().unwrap();
+    // changing fields other than the links is fine
+    unsafe { item.get_unchecked_mut().val = 5 };
+    assert_eq!(list.pop_front().unwrap().val, 5);
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    PushFront,
+    PushBack,
+    PopFront,
+    PopBack,
+    // any item that was ever made, in the list or not
+    Remove(usize),
+    Reinsert(usize),
+    // through a cursor that's moved this many times from the front
+    InsertAfter(usize),
+    InsertBefore(usize),
+    RemoveCurrent(usize),
+    // a new list with this many items
+    SpliceBack(usize),
+    SpliceFront(usize),
+    SpliceAfter(usize, usize),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        Just(Op::PushFront),
+        Just(Op::PushBack),
+        Just(Op::PopFront),
+        Just(Op::PopBack),
+        any::<usize>().prop_map(Op::Remove),
+        any::<usize>().prop_map(Op::Reinsert),
+        (0usize..20).prop_map(Op::InsertAfter),
+        (0usize..20).prop_map(Op::InsertBefore),
+        (0usize..20).prop_map(Op::RemoveCurrent),
+        (0usize..4).prop_map(Op::SpliceBack),
+        (0usize..4).prop_map(Op::SpliceFront),
+        ((0usize..20), (0usize..4)).prop_map(|(moves, count)| Op::SpliceAfter(moves, count)),
+    ]
+}
+
+// a new item, remembered so later ops can refer to it
+fn new_node(made: &mut Vec<NodeLink>) -> NodeLink {
+    let item = node(made.len());
+    made.push(item);
+    item
+}
+
+// the position a cursor ends up at after moving from the front, None being the ghost
+fn cursor_pos(len: usize, moves: usize) -> Option<usize> {
+    let pos = moves % (len + 1);
+    (pos < len).then_some(pos)
+}
+
+proptest! {
+    #[test]
+    fn matches_vec_deque(ops in prop::collection::vec(op(), 0..200)) {
+        let mut list = IntrusiveDList::empty();
+        let mut model = VecDeque::new();
+        let mut made: Vec<NodeLink> = vec![];
+        for op in ops {
+            match op {
+                Op::PushFront => {
+                    let item = new_node(&mut made);
+                    list.push_front(item);
+                    model.push_front(item.val);
+                }
+                Op::PushBack => {
+                    let item = new_node(&mut made);
+                    list.push_back(item);
+                    model.push_back(item.val);
+                }
+                Op::PopFront => prop_assert_eq!(list.pop_front().map(|n| n.val), model.pop_front()),
+                Op::PopBack => prop_assert_eq!(list.pop_back().map(|n| n.val), model.pop_back()),
+                Op::Remove(i) if !made.is_empty() => {
+                    let item = made[i % made.len()];
+                    let idx = model.iter().position(|&v| v == item.val);
+                    prop_assert_eq!(list.remove(&item).map(|n| n.val), idx.map(|_| item.val));
+                    if let Some(idx) = idx {
+                        model.remove(idx);
+                    }
+                }
+                Op::Reinsert(i) if !made.is_empty() => {
+                    let item = made[i % made.len()];
+                    if !item.links.is_linked() {
+                        list.push_back(item);
+                        model.push_back(item.val);
+                    }
+                }
+                Op::InsertAfter(moves) | Op::InsertBefore(moves) => {
+                    let item = new_node(&mut made);
+                    let pos = cursor_pos(model.len(), moves);
+                    let mut cursor = list.cursor_front_mut();
+                    for _ in 0..moves % (model.len() + 1) {
+                        cursor.move_next();
+                    }
+                    if matches!(op, Op::InsertAfter(_)) {
+                        cursor.insert_after(item);
+                        model.insert(pos.map_or(0, |p| p + 1), item.val);
+                    } else {
+                        cursor.insert_before(item);
+                        model.insert(pos.unwrap_or(model.len()), item.val);
+                    }
+                }
+                Op::RemoveCurrent(moves) => {
+                    let pos = cursor_pos(model.len(), moves);
+                    let mut cursor = list.cursor_front_mut();
+                    for _ in 0..moves % (model.len() + 1) {
+                        cursor.move_next();
+                    }
+                    let removed = cursor.remove_current().map(|n| n.val);
+                    let next = cursor.current().map(|n| n.val);
+                    prop_assert_eq!(removed, pos.map(|p| model[p]));
+                    if let Some(p) = pos {
+                        model.remove(p);
+                        prop_assert_eq!(next, model.get(p).copied());
+                    }
+                }
+                Op::SpliceBack(count) | Op::SpliceFront(count) | Op::SpliceAfter(_, count) => {
+                    let mut other = IntrusiveDList::empty();
+                    let mut other_vals = vec![];
+                    for _ in 0..count {
+                        let item = new_node(&mut made);
+                        other.push_back(item);
+                        other_vals.push(item.val);
+                    }
+                    match op {
+                        Op::SpliceBack(_) => {
+                            list.splice_back(&mut other);
+                            model.extend(other_vals);
+                        }
+                        Op::SpliceFront(_) => {
+                            list.splice_front(&mut other);
+                            for val in other_vals.into_iter().rev() {
+                                model.push_front(val);
+                            }
+                        }
+                        Op::SpliceAfter(moves, _) => {
+                            let pos = cursor_pos(model.len(), moves);
+                            let mut cursor = list.cursor_front_mut();
+                            for _ in 0..moves % (model.len() + 1) {
+                                cursor.move_next();
+                            }
+                            cursor.splice_after(&mut other);
+                            let at = pos.map_or(0, |p| p + 1);
+                            for (i, val) in other_vals.into_iter().enumerate() {
+                                model.insert(at + i, val);
+                            }
+                        }
+                        _ => unreachable!(),
+                    }
+                    prop_assert!(other.is_empty());
+                }
+                _ => {}
+            }
+
+            let expected: Vec<usize> = model.iter().copied().collect();
+            prop_assert_eq!(vals(&list), expected.clone());
+            prop_assert_eq!(vals_backwards(&list), expected);
+            prop_assert_eq!(list.len(), model.len());
+            for item in &made {
+                prop_assert_eq!(list.contains(item), model.contains(&item.val));
+            }
+        }
+    }
+}
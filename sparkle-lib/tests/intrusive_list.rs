@@ -0,0 +1,94 @@
+use proptest::prelude::*;
+use sparkle_lib::data_structures::intrusive_list::{IntrusiveList, ListLink};
+use sparkle_lib::data_structures::{IsSlotOf, Store, Stores};
+
+struct Node {
+    next: Store<RawNode, Option<NodeLink>>,
+    val: usize,
+}
+
+type NodeLink = &'static mut Node;
+
+#[derive(Clone, Copy)]
+struct RawNode(*mut Node);
+
+unsafe impl Stores<Option<NodeLink>> for RawNode {
+    unsafe fn store(val: &Option<NodeLink>) -> Self {
+        match val {
+            Some(node) => RawNode(*node as *const Node as *mut Node),
+            None => RawNode(core::ptr::null_mut()),
+        }
+    }
+
+    unsafe fn extract(store: Self) -> Option<NodeLink> {
+        unsafe { store.0.as_mut() }
+    }
+}
+
+struct NextSlot;
+
+unsafe impl IsSlotOf<NodeLink> for NextSlot {
+    type Value = Store<RawNode, Option<NodeLink>>;
+
+    fn get(st: &NodeLink) -> &Self::Value {
+        &st.next
+    }
+
+    fn get_mut(st: &mut NodeLink) -> &mut Self::Value {
+        &mut st.next
+    }
+}
+
+impl ListLink for NodeLink {
+    type Slot = NextSlot;
+    type Store = RawNode;
+}
+
+fn node(val: usize) -> NodeLink {
+    Box::leak(Box::new(Node {
+        next: Store::new(None),
+        val,
+    }))
+}
+
+#[test]
+fn push_pop() {
+    let mut list = IntrusiveList::empty();
+    assert!(list.pop().is_none());
+    list.push(node(1));
+    list.push(node(2));
+    assert_eq!(list.pop().unwrap().val, 2);
+    assert_eq!(list.pop().unwrap().val, 1);
+    assert!(list.pop().is_none());
+}
+
+#[test]
+fn store() {
+    let mut store: Store<RawNode, Option<NodeLink>> = Store::new(None);
+    assert!(store.take().is_none());
+    store.set(Some(node(5)));
+    assert_eq!(store.replace(None).unwrap().val, 5);
+    assert!(store.into_inner().is_none());
+}
+
+proptest! {
+    // it's a stack, so it should act like a Vec
+    #[test]
+    fn matches_vec(ops in prop::collection::vec(prop::option::of(0usize..1000), 0..200)) {
+        let mut list = IntrusiveList::empty();
+        let mut model = vec![];
+        for op in ops {
+            match op {
+                Some(val) => {
+                    list.push(node(val));
+                    model.push(val);
+                }
+                None => prop_assert_eq!(list.pop().map(|n| n.val), model.pop()),
+            }
+        }
+        while let Some(val) = model.pop() {
+            prop_assert_eq!(list.pop().map(|n| n.val), Some(val));
+        }
+        prop_assert!(list.pop().is_none());
+    }
+}
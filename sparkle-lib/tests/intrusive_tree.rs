@@ -0,0 +1,233 @@
+use core::ops::Range;
+
+use proptest::prelude::*;
+use sparkle_lib::data_structures::intrusive_tree::{IntrusiveTree, TreeLink, TreeLinks};
+use sparkle_lib::data_structures::{IsSlotOf, Stores};
+
+struct Node {
+    range: Range<usize>,
+    links: TreeLinks<RawNode>,
+}
+
+type NodeLink = &'static mut Node;
+
+#[derive(Clone, Copy, PartialEq)]
+struct RawNode(*mut Node);
+
+unsafe impl Stores<NodeLink> for RawNode {
+    unsafe fn store(val: &NodeLink) -> Self {
+        RawNode(*val as *const Node as *mut Node)
+    }
+
+    unsafe fn extract(store: Self) -> NodeLink {
+        unsafe { &mut *store.0 }
+    }
+}
+
+struct LinksSlot;
+
+unsafe impl IsSlotOf<NodeLink> for LinksSlot {
+    type Value = TreeLinks<RawNode>;
+
+    fn get(st: &NodeLink) -> &Self::Value {
+        &st.links
+    }
+
+    fn get_mut(st: &mut NodeLink) -> &mut Self::Value {
+        &mut st.links
+    }
+}
+
+impl TreeLink for NodeLink {
+    type Slot = LinksSlot;
+    type Raw = RawNode;
+
+    fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+}
+
+fn node(range: Range<usize>) -> NodeLink {
+    Box::leak(Box::new(Node {
+        range,
+        links: TreeLinks::new(),
+    }))
+}
+
+fn ranges(tree: &IntrusiveTree<NodeLink>) -> Vec<Range<usize>> {
+    tree.iter().map(|item| item.range.clone()).collect()
+}
+
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+// the lowest spot in bounds that doesn't overlap anything, by trying everywhere a gap can start
+fn model_gap(model: &[Range<usize>], size: usize, bounds: Range<usize>) -> Option<usize> {
+    let candidates = core::iter::once(bounds.start).chain(model.iter().map(|r| r.end));
+    candidates
+        .filter(|&start| start >= bounds.start && start + size <= bounds.end)
+        .filter(|&start| !model.iter().any(|r| overlaps(r, &(start..start + size))))
+        .min()
+}
+
+#[test]
+fn find_gap_between_items() {
+    let mut tree = IntrusiveTree::empty();
+    tree.insert(node(0..10));
+    tree.insert(node(20..30));
+    tree.insert(node(35..50));
+    assert_eq!(tree.find_gap(10, 0..100), Some(10));
+    assert_eq!(tree.find_gap(11, 0..100), Some(50));
+    assert_eq!(tree.find_gap(5, 30..100), Some(30));
+    assert_eq!(tree.find_gap(5, 25..100), Some(30));
+    assert_eq!(tree.find_gap(51, 0..100), None);
+    assert_eq!(tree.find_gap(11, 0..60), None);
+}
+
+#[test]
+fn lookups() {
+    let mut tree = IntrusiveTree::empty();
+    for start in [40, 0, 20, 60] {
+        tree.insert(node(start..start + 10));
+    }
+    assert_eq!(tree.len(), 4);
+    assert_eq!(tree.find(25).unwrap().range, 20..30);
+    assert!(tree.find(35).is_none());
+    assert_eq!(tree.floor(35).unwrap().range, 20..30);
+    assert_eq!(tree.ceil(35).unwrap().range, 40..50);
+    assert_eq!(tree.first().unwrap().range, 0..10);
+    assert_eq!(tree.last().unwrap().range, 60..70);
+    let overlapping: Vec<_> = tree.range(25..45).map(|item| item.range.clone()).collect();
+    assert_eq!(overlapping, [20..30, 40..50]);
+    assert_eq!(tree.take_containing(45).unwrap().range, 40..50);
+    assert_eq!(tree.pop_first().unwrap().range, 0..10);
+    assert_eq!(ranges(&tree), [20..30, 60..70]);
+}
+
+#[test]
+fn equal_starts_keep_insertion_order() {
+    let mut tree = IntrusiveTree::empty();
+    let mut inserted = vec![];
+    for _ in 0..5 {
+        // empty ranges are points, so lots of them can start at the same place
+        let item = node(7..7);
+        inserted.push(item as *const Node);
+        tree.insert(item);
+    }
+    let mut popped = vec![];
+    while let Some(item) = tree.pop_first() {
+        popped.push(item as *const Node);
+    }
+    assert_eq!(popped, inserted);
+}
+
+// drops an item that left the tree from items
+fn forget(items: &mut Vec<RawNode>, item: &NodeLink) -> Range<usize> {
+    items.retain(|raw| !core::ptr::eq(raw.0, *item));
+    item.range.clone()
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    // at the lowest gap in bounds, like an address space does
+    InsertAnywhere(usize, Range<usize>),
+    InsertFixed(usize, usize),
+    TakeContaining(usize),
+    PopFirst,
+    // any item in the tree, by index into the sorted items
+    Remove(usize),
+}
+
+const SPACE: usize = 1000;
+
+fn op() -> impl Strategy<Value = Op> {
+    let bounds = (0..SPACE, 0..SPACE).prop_map(|(a, b)| a.min(b)..a.max(b));
+    prop_oneof![
+        3 => ((1usize..60), bounds).prop_map(|(size, bounds)| Op::InsertAnywhere(size, bounds)),
+        3 => ((0..SPACE), (1usize..60)).prop_map(|(start, size)| Op::InsertFixed(start, size)),
+        1 => (0..SPACE).prop_map(Op::TakeContaining),
+        1 => Just(Op::PopFirst),
+        2 => any::<usize>().prop_map(Op::Remove),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn matches_model(
+        ops in prop::collection::vec(op(), 0..150),
+        probes in prop::collection::vec(((1usize..80), (0..SPACE), (0..SPACE)), 20),
+    ) {
+        let mut tree = IntrusiveTree::empty();
+        // kept sorted by start
+        let mut model: Vec<Range<usize>> = vec![];
+        // the items that are in the tree
+        let mut items: Vec<RawNode> = vec![];
+
+        for op in ops {
+            match op {
+                Op::InsertAnywhere(size, bounds) => {
+                    let gap = tree.find_gap(size, bounds.clone());
+                    prop_assert_eq!(gap, model_gap(&model, size, bounds));
+                    if let Some(start) = gap {
+                        let item = node(start..start + size);
+                        items.push(RawNode(item as *mut Node));
+                        tree.insert(item);
+                        model.push(start..start + size);
+                    }
+                }
+                Op::InsertFixed(start, size) => {
+                    let range = start..start + size;
+                    if !model.iter().any(|r| overlaps(r, &range)) {
+                        let item = node(range.clone());
+                        items.push(RawNode(item as *mut Node));
+                        tree.insert(item);
+                        model.push(range);
+                    }
+                }
+                Op::TakeContaining(addr) => {
+                    let expected = model.iter().position(|r| r.contains(&addr));
+                    let taken = tree.take_containing(addr).map(|item| forget(&mut items, &item));
+                    prop_assert_eq!(taken, expected.map(|i| model.remove(i)));
+                }
+                Op::PopFirst => {
+                    let expected = (!model.is_empty()).then(|| model.remove(0));
+                    let popped = tree.pop_first().map(|item| forget(&mut items, &item));
+                    prop_assert_eq!(popped, expected);
+                }
+                Op::Remove(i) if !model.is_empty() => {
+                    let range = model.remove(i % model.len());
+                    let raw = *items
+                        .iter()
+                        .find(|raw| unsafe { (*raw.0).range == range })
+                        .unwrap();
+                    // the tree owns it, this is just something to point at it with
+                    let alias = core::mem::ManuallyDrop::new(unsafe { &mut *raw.0 });
+                    let removed = unsafe { tree.remove(&alias) };
+                    prop_assert_eq!(forget(&mut items, &removed), range);
+                }
+                Op::Remove(_) => {}
+            }
+            model.sort_by_key(|r| r.start);
+
+            prop_assert_eq!(ranges(&tree), model.clone());
+            prop_assert_eq!(tree.len(), model.len());
+            for &(size, a, b) in &probes {
+                let bounds = a.min(b)..a.max(b);
+                prop_assert_eq!(
+                    tree.find_gap(size, bounds.clone()),
+                    model_gap(&model, size, bounds.clone())
+                );
+                prop_assert_eq!(
+                    tree.find(a).map(|item| item.range.clone()),
+                    model.iter().find(|r| r.contains(&a)).cloned()
+                );
+                let overlapping: Vec<_> = tree.range(bounds.clone()).map(|item| item.range.clone()).collect();
+                let expected: Vec<_> = model.iter().filter(|r| overlaps(r, &bounds)).cloned().collect();
+                prop_assert_eq!(overlapping, expected);
+            }
+        }
+    }
+}
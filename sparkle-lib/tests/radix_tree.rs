@@ -0,0 +1,198 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::ptr::NonNull;
+use std::rc::Rc;
+
+use proptest::prelude::*;
+use sparkle_lib::data_structures::radix_tree::{
+    node_layout, Mark, NodeAlloc, RadixTree, MARK_COUNT,
+};
+
+// nodes from the host allocator, counting how many are out
+struct Nodes(Rc<Cell<usize>>);
+
+unsafe impl NodeAlloc for Nodes {
+    fn alloc(&mut self) -> Option<*mut ()> {
+        self.0.set(self.0.get() + 1);
+        Some(unsafe { std::alloc::alloc(node_layout()) } as *mut ())
+    }
+
+    unsafe fn dealloc(&mut self, node: *mut ()) {
+        self.0.set(self.0.get() - 1);
+        unsafe { std::alloc::dealloc(node as *mut u8, node_layout()) };
+    }
+}
+
+type Tree = RadixTree<u8, Nodes>;
+
+fn tree() -> (Tree, Rc<Cell<usize>>) {
+    let live = Rc::new(Cell::new(0));
+    (RadixTree::new(Nodes(live.clone())), live)
+}
+
+// the tree never looks behind its entries, so any non-null value will do
+fn entry(val: usize) -> NonNull<u8> {
+    NonNull::new(val as *mut u8).unwrap()
+}
+
+fn entries(iter: impl Iterator<Item = (usize, NonNull<u8>)>) -> Vec<(usize, usize)> {
+    iter.map(|(index, val)| (index, val.as_ptr() as usize))
+        .collect()
+}
+
+#[test]
+fn grow_keeps_entries_and_marks() {
+    let (tree, _) = tree();
+    tree.store(3, entry(30)).unwrap();
+    assert!(tree.set_mark(3, Mark(1)));
+    // needs a new level on top of the root for every 6 bits
+    let far = (1 << 60) + 7;
+    tree.store(far, entry(70)).unwrap();
+
+    assert_eq!(tree.load(3), Some(entry(30)));
+    assert_eq!(tree.load(far), Some(entry(70)));
+    assert!(tree.get_mark(3, Mark(1)));
+    assert_eq!(entries(tree.range(0..usize::MAX)), [(3, 30), (far, 70)]);
+    assert_eq!(entries(tree.range(4..usize::MAX)), [(far, 70)]);
+    assert_eq!(
+        entries(tree.range_marked(0..usize::MAX, Mark(1))),
+        [(3, 30)]
+    );
+
+    // the mark has to make it up to the root and back out when it's cleared
+    assert!(tree.set_mark(far, Mark(0)));
+    assert_eq!(
+        entries(tree.range_marked(0..usize::MAX, Mark(0))),
+        [(far, 70)]
+    );
+    tree.clear_mark(far, Mark(0));
+    assert!(!tree.get_mark(far, Mark(0)));
+    assert!(tree.range_marked(0..usize::MAX, Mark(0)).next().is_none());
+    assert!(!tree.set_mark(4, Mark(0)));
+}
+
+#[test]
+fn erased_nodes_are_freed_later() {
+    let (tree, live) = tree();
+    for index in 0..200 {
+        tree.store(index, entry(index + 1)).unwrap();
+    }
+    tree.store(usize::MAX, entry(1)).unwrap();
+    let used = live.get();
+    for index in 0..200 {
+        assert_eq!(tree.erase(index), Some(entry(index + 1)));
+    }
+    assert_eq!(tree.erase(usize::MAX), Some(entry(1)));
+    assert!(tree.load(0).is_none());
+    // readers could still be on the erased nodes
+    assert_eq!(live.get(), used);
+    unsafe { tree.free_retired() };
+    assert_eq!(live.get(), 0);
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Store(usize, usize),
+    Erase(usize),
+    SetMark(usize, u8),
+    ClearMark(usize, u8),
+    FreeRetired,
+}
+
+// clustered so the same indices come up again, with some past 1 << 60 so the root has to grow
+fn index() -> impl Strategy<Value = usize> {
+    prop_oneof![
+        0usize..300,
+        (1usize << 60) - 100..(1 << 60) + 100,
+        usize::MAX - 100..=usize::MAX,
+    ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let mark = 0..MARK_COUNT as u8;
+    prop_oneof![
+        4 => (index(), 1usize..1000).prop_map(|(index, val)| Op::Store(index, val)),
+        2 => index().prop_map(Op::Erase),
+        2 => (index(), mark.clone()).prop_map(|(index, mark)| Op::SetMark(index, mark)),
+        1 => (index(), mark).prop_map(|(index, mark)| Op::ClearMark(index, mark)),
+        1 => Just(Op::FreeRetired),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn matches_model(
+        ops in prop::collection::vec(op(), 0..150),
+        probes in prop::collection::vec((index(), index()), 10),
+    ) {
+        let (tree, live) = tree();
+        // the entry and its marks for every index that has one
+        let mut model: BTreeMap<usize, (usize, [bool; MARK_COUNT])> = BTreeMap::new();
+
+        for op in ops {
+            match op {
+                Op::Store(index, val) => {
+                    let old = tree.store(index, entry(val)).unwrap();
+                    let expected = match model.get_mut(&index) {
+                        // the marks stay with the index
+                        Some(slot) => Some(core::mem::replace(&mut slot.0, val)),
+                        None => {
+                            model.insert(index, (val, [false; MARK_COUNT]));
+                            None
+                        }
+                    };
+                    prop_assert_eq!(old, expected.map(entry));
+                }
+                Op::Erase(index) => {
+                    let expected = model.remove(&index).map(|(val, _)| entry(val));
+                    prop_assert_eq!(tree.erase(index), expected);
+                }
+                Op::SetMark(index, mark) => {
+                    let expected = model.get_mut(&index).map(|slot| slot.1[mark as usize] = true);
+                    prop_assert_eq!(tree.set_mark(index, Mark(mark)), expected.is_some());
+                }
+                Op::ClearMark(index, mark) => {
+                    if let Some(slot) = model.get_mut(&index) {
+                        slot.1[mark as usize] = false;
+                    }
+                    tree.clear_mark(index, Mark(mark));
+                }
+                Op::FreeRetired => {
+                    unsafe { tree.free_retired() };
+                    if model.is_empty() {
+                        prop_assert_eq!(live.get(), 0);
+                    }
+                }
+            }
+
+            for &(a, b) in &probes {
+                prop_assert_eq!(tree.load(a), model.get(&a).map(|&(val, _)| entry(val)));
+                for mark in 0..MARK_COUNT {
+                    let marked = model.get(&a).is_some_and(|slot| slot.1[mark]);
+                    prop_assert_eq!(tree.get_mark(a, Mark(mark as u8)), marked);
+                }
+
+                let bounds = a.min(b)..a.max(b);
+                let expected: Vec<_> = model.range(bounds.clone()).map(|(&index, &(val, _))| (index, val)).collect();
+                prop_assert_eq!(entries(tree.range(bounds.clone())), expected);
+                for mark in 0..MARK_COUNT {
+                    let expected: Vec<_> = model
+                        .range(bounds.clone())
+                        .filter(|(_, slot)| slot.1[mark])
+                        .map(|(&index, &(val, _))| (index, val))
+                        .collect();
+                    prop_assert_eq!(entries(tree.range_marked(bounds.clone(), Mark(mark as u8))), expected);
+                }
+            }
+        }
+
+        let indices: Vec<_> = model.keys().copied().collect();
+        for index in indices {
+            prop_assert!(tree.erase(index).is_some());
+        }
+        unsafe { tree.free_retired() };
+        prop_assert_eq!(live.get(), 0);
+    }
+}
@@ -0,0 +1,50 @@
+use core::ops::Range;
+
+use proptest::prelude::*;
+use sparkle_lib::ranges::RangesDifference;
+
+fn difference(plus: &[Range<usize>], minus: &[Range<usize>]) -> Vec<Range<usize>> {
+    RangesDifference::new(plus.iter().cloned(), minus.iter().cloned()).collect()
+}
+
+#[test]
+fn basics() {
+    assert_eq!(difference(&[0..10], &[]), [0..10]);
+    assert_eq!(difference(&[0..10], &[3..5]), [0..3, 5..10]);
+    assert_eq!(difference(&[0..10, 20..30], &[5..25]), [0..5, 25..30]);
+    assert_eq!(difference(&[0..10], &[0..10]), []);
+    assert_eq!(difference(&[5..10], &[0..2, 3..6, 8..20]), [6..8]);
+}
+
+// sorted ranges that don't overlap, with gaps of at least one between them
+fn sorted_ranges() -> impl Strategy<Value = Vec<Range<usize>>> {
+    prop::collection::vec((1usize..20, 1usize..20), 0..10).prop_map(|pieces| {
+        let mut pos = 0;
+        pieces
+            .into_iter()
+            .map(|(gap, len)| {
+                pos += gap;
+                let range = pos..pos + len;
+                pos += len;
+                range
+            })
+            .collect()
+    })
+}
+
+proptest! {
+    // the same as taking the points out one by one
+    #[test]
+    fn matches_point_sets(plus in sorted_ranges(), minus in sorted_ranges()) {
+        let result = difference(&plus, &minus);
+        let expected: Vec<usize> = plus
+            .iter()
+            .flat_map(|r| r.clone())
+            .filter(|p| !minus.iter().any(|m| m.contains(p)))
+            .collect();
+        let got: Vec<usize> = result.iter().flat_map(|r| r.clone()).collect();
+        prop_assert_eq!(got, expected);
+        prop_assert!(result.iter().all(|r| !r.is_empty()));
+        prop_assert!(result.windows(2).all(|w| w[0].end <= w[1].start));
+    }
+}